@@ -1,18 +1,65 @@
 #![allow(non_snake_case)]
 
-use super::dnet::{DNet, DNetResult, NetPacketType};
-use crate::packet::Packet;
+use super::dnet::{DNet, DNetResult, DisconnectReason, NetPacketType};
+use super::event::EventManager;
+use super::ghost::{read_ghost_packet, GhostAlwaysObjectEvent, GhostTable};
+use super::ghost_manager::GhostManager;
+use super::moves::{Move, MoveQueue};
+use super::net_class::{NetClassGroupMasks, NetClassRegistry, NetClassTypes};
+use super::net_event::NetEvent;
+use super::net_string::{is_tagged_string, make_tagged_string, NetStringEvent, NetStringTable};
+use super::packet_header::{
+    ClientPacketHeader, ControlObjectUpdate, ControlScheme, ServerPacketHeader,
+};
+use super::rate::RateControl;
+use super::remote_command::{RemoteCommand, RemoteCommandEvent};
+use super::stats::ConnectionStats;
+use crate::error::{DnetError, Result};
+use crate::logging::{HexDump, HEX_DUMP_TARGET};
+use crate::packet::{HuffmanTable, Packet, StringEncoding, MAX_PACKET_DATA_SIZE};
+use crate::NetClassGroups::NetClassGroupGame;
 use crate::PacketSource::GameToGame;
 use crate::{BitStream, PacketSource};
-use anyhow::{Error, Result};
-use rand::Rng;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::broadcast;
+use tracing::{debug, info, info_span, trace, warn, Instrument, Span};
+
+// So log lines from different connections can be told apart
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(1);
 
 pub struct GameConnection {
+    id: u32,
+    span: Span,
     socket: UdpSocket,
     connect_sequence: u32,
     dnet: DNet,
+    net_class_group: u32,
+    class_registry: NetClassRegistry,
+    events: EventManager<Box<dyn NetEvent>>,
+    commands_tx: broadcast::Sender<RemoteCommand>,
+    string_table: NetStringTable,
+    ghosts: GhostTable,
+    // For ghosting our own objects to the other side, like a server would
+    ghost_manager: GhostManager,
+    ghosting_from: bool,
+    moves: MoveQueue,
+    // Sent in the next packet header, then cleared
+    control_scheme_update: Option<ControlScheme>,
+    first_person_update: Option<bool>,
+    camera_fov_update: Option<u32>,
+    // Most recent header from the server
+    server_header: ServerPacketHeader,
+    // What the server's headers have told us so far, since not everything is in each one
+    control_object: Option<u32>,
+    damage_flash: f32,
+    white_out: f32,
+    rate: RateControl,
+    disconnect_reason: Option<DisconnectReason>,
+    string_encoding: StringEncoding,
+    huffman_table: Arc<HuffmanTable>,
 }
 
 impl GameConnection {
@@ -28,85 +75,548 @@ impl GameConnection {
         let socket = UdpSocket::from_std(std_socket)?;
         socket.connect(connect_address).await?;
 
-        let mut connection = GameConnection {
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        let span = info_span!("connection", id, remote = %socket.peer_addr()?);
+
+        let mut class_registry = NetClassRegistry::new();
+        class_registry.register_event(
+            RemoteCommandEvent::CLASS_NAME,
+            NetClassGroupMasks::NetClassGroupGameMask,
+            RemoteCommandEvent::create,
+        );
+        class_registry.register_event(
+            NetStringEvent::CLASS_NAME,
+            NetClassGroupMasks::NetClassGroupGameMask,
+            NetStringEvent::create,
+        );
+        class_registry.register_event(
+            GhostAlwaysObjectEvent::CLASS_NAME,
+            NetClassGroupMasks::NetClassGroupGameMask,
+            GhostAlwaysObjectEvent::create,
+        );
+
+        let (commands_tx, _) = broadcast::channel::<RemoteCommand>(64);
+
+        let connection = GameConnection {
+            id,
+            span,
             socket,
             connect_sequence,
             dnet: DNet::new(connect_sequence),
+            net_class_group: NetClassGroupGame,
+            class_registry,
+            events: EventManager::new(),
+            commands_tx,
+            string_table: NetStringTable::new(),
+            ghosts: GhostTable::new(),
+            ghost_manager: GhostManager::new(),
+            ghosting_from: false,
+            moves: MoveQueue::new(),
+            control_scheme_update: None,
+            first_person_update: None,
+            camera_fov_update: None,
+            server_header: ServerPacketHeader::default(),
+            control_object: None,
+            damage_flash: 0.0,
+            white_out: 0.0,
+            rate: RateControl::new(),
+            disconnect_reason: None,
+            string_encoding: StringEncoding::default(),
+            huffman_table: HuffmanTable::stock(),
         };
 
         Ok(connection)
     }
 
-    pub async fn send_packet(&mut self, packet: Packet) -> Result<()> {
-        println!("Send {:?}", packet);
-        let bytes = packet.into_bytes();
-        println!(">>> {:?}", &bytes);
-        self.socket.send(bytes.as_slice()).await?;
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Everything this connection logs is inside this span
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn net_class_group(&self) -> u32 {
+        self.net_class_group
+    }
+
+    pub fn class_registry(&self) -> &NetClassRegistry {
+        &self.class_registry
+    }
+
+    pub fn class_registry_mut(&mut self) -> &mut NetClassRegistry {
+        &mut self.class_registry
+    }
+
+    // Fails if the event's class isn't registered in our class group, since it couldn't
+    // be sent
+    pub fn post_event(&mut self, event: Box<dyn NetEvent>) -> Result<()> {
+        if self
+            .class_registry
+            .class_id(
+                self.net_class_group,
+                NetClassTypes::NetClassTypeEvent,
+                event.class_name(),
+            )
+            .is_none()
+        {
+            return Err(DnetError::UnregisteredClass(event.class_name().to_string()));
+        }
+        let guarantee_type = event.guarantee_type();
+        self.events.post_event(event, guarantee_type);
         Ok(())
     }
 
+    pub fn string_table(&self) -> &NetStringTable {
+        &self.string_table
+    }
+
+    pub fn string_table_mut(&mut self) -> &mut NetStringTable {
+        &mut self.string_table
+    }
+
+    pub fn ghosts(&self) -> &GhostTable {
+        &self.ghosts
+    }
+
+    pub fn ghosts_mut(&mut self) -> &mut GhostTable {
+        &mut self.ghosts
+    }
+
+    pub fn ghost_manager(&self) -> &GhostManager {
+        &self.ghost_manager
+    }
+
+    pub fn ghost_manager_mut(&mut self) -> &mut GhostManager {
+        &mut self.ghost_manager
+    }
+
+    pub fn is_ghosting_from(&self) -> bool {
+        self.ghosting_from
+    }
+
+    // NetConnection::setGhostFrom. Ghosts go one way, so once this is on, data packets
+    // we send end with the ghost_manager's ghosts and ones we get are read without any.
+    // Clients leave it off, the server is the one doing the ghosting.
+    pub fn set_ghosting_from(&mut self, ghosting_from: bool) {
+        self.ghosting_from = ghosting_from;
+    }
+
+    // NetConnection::ghostReadPacket, the last part of a data packet. Packets that come
+    // in through process_raw_packet already go through this.
+    pub fn read_ghost_packet(&mut self, stream: &mut BitStream) -> Result<()> {
+        read_ghost_packet(self, stream)
+    }
+
+    pub fn moves(&self) -> &MoveQueue {
+        &self.moves
+    }
+
+    // Queues a move to be sent with the next data packets, until the server acks it.
+    // Returns false if the queue is full.
+    pub fn push_move(&mut self, new_move: Move) -> bool {
+        self.moves.push(new_move)
+    }
+
+    pub fn set_control_scheme(&mut self, control_scheme: ControlScheme) {
+        self.control_scheme_update = Some(control_scheme);
+    }
+
+    pub fn set_first_person(&mut self, first_person: bool) {
+        self.first_person_update = Some(first_person);
+    }
+
+    pub fn set_camera_fov(&mut self, camera_fov: u32) {
+        self.camera_fov_update = Some(camera_fov);
+    }
+
+    pub fn server_header(&self) -> &ServerPacketHeader {
+        &self.server_header
+    }
+
+    // Ghost index of the object the server has us controlling
+    pub fn control_object(&self) -> Option<u32> {
+        self.control_object
+    }
+
+    // Ghost index of the object we are looking through, when it isn't the control object
+    pub fn camera_object(&self) -> Option<u32> {
+        self.server_header.camera_object
+    }
+
+    pub fn damage_flash(&self) -> f32 {
+        self.damage_flash
+    }
+
+    pub fn white_out(&self) -> f32 {
+        self.white_out
+    }
+
+    pub fn rate(&self) -> &RateControl {
+        &self.rate
+    }
+
+    // $pref::Net::PacketRateToClient / $pref::Net::PacketSize, the server is told about
+    // it in the next packet
+    pub fn set_max_rate(&mut self, packet_rate: u32, packet_size: u32) {
+        self.rate.set_max_rate(packet_rate, packet_size);
+    }
+
+    // Gets a tagged string for sending, telling the other side about it first if needed
+    pub fn tag_string(&mut self, string: &str) -> Result<String> {
+        if is_tagged_string(string) {
+            return Ok(string.to_string());
+        }
+        let (id, is_new) = self.string_table.check_string(string);
+        if is_new {
+            self.post_event(Box::new(NetStringEvent {
+                id,
+                string: string.to_string(),
+            }))?;
+        }
+        Ok(make_tagged_string(id))
+    }
+
+    pub fn resolve_string(&self, string: &str) -> String {
+        self.string_table.resolve(string)
+    }
+
+    pub fn command_to_server(&mut self, name: &str, args: &[&str]) -> Result<()> {
+        let name = self.tag_string(name)?;
+        let event = RemoteCommandEvent::new(&name, args)?;
+        self.post_event(Box::new(event))
+    }
+
+    // Incoming commandToClient calls
+    pub fn commands(&self) -> broadcast::Receiver<RemoteCommand> {
+        self.commands_tx.subscribe()
+    }
+
+    pub(crate) fn handle_remote_command(&mut self, command: RemoteCommand) {
+        debug!(?command, "Remote command");
+        // Nobody listening is fine
+        let _ = self.commands_tx.send(command);
+    }
+
+    pub async fn send_packet(&mut self, packet: Packet) -> Result<()> {
+        let span = self.span.clone();
+        async move {
+            debug!(?packet, "Send");
+            let bytes = packet.try_into_bytes()?;
+            trace!(target: HEX_DUMP_TARGET, ">>> {} bytes\n{}", bytes.len(), HexDump(&bytes));
+            self.socket.send(bytes.as_slice()).await?;
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
     pub async fn send_raw(&mut self, stream: BitStream) -> Result<()> {
-        let bytes = stream.into_bytes();
-        println!(">>> {:?}", &bytes);
-        self.socket.send(bytes.as_slice()).await?;
-        Ok(())
+        let span = self.span.clone();
+        async move {
+            let bytes = stream.try_into_bytes()?;
+            trace!(target: HEX_DUMP_TARGET, ">>> {} bytes\n{}", bytes.len(), HexDump(&bytes));
+            self.socket.send(bytes.as_slice()).await?;
+            self.dnet.record_sent(bytes.len());
+            Ok(())
+        }
+        .instrument(span)
+        .await
     }
 
     pub async fn read_packet(&mut self) -> Result<Option<Packet>> {
-        let mut bytes = vec![];
-        bytes.resize(1440, 0); // UDP MTR
-        let len = self.socket.recv(bytes.as_mut_slice()).await?;
+        let span = self.span.clone();
+        async move {
+            let mut bytes = vec![0; 1440]; // UDP MTR
+            let len = self.socket.recv(bytes.as_mut_slice()).await?;
+
+            trace!(target: HEX_DUMP_TARGET, "<<< {} bytes\n{}", len, HexDump(&bytes[0..len]));
+            // Anyone can send us anything, so a bad packet isn't worth dropping the
+            // connection over
+            let packet = match Packet::parse_bytes_with_encoding(
+                &bytes[0..len],
+                GameToGame,
+                self.string_encoding,
+            ) {
+                Ok(packet) => Some(packet),
+                Err(error) => {
+                    debug!(%error, bytes = len, "Dropping malformed packet");
+                    None
+                }
+            };
+
+            if let Some(Packet::Disconnect { sequence, reason }) = &packet {
+                if *sequence == self.connect_sequence {
+                    info!(%reason, "Disconnected by remote");
+                    self.disconnect_reason = Some(DisconnectReason::Remote(reason.clone()));
+                }
+            }
 
-        println!("<<< {:?}", &bytes[0..len]);
-        let packet = Packet::try_from_bytes(&bytes[0..len], GameToGame);
+            Ok(packet)
+        }
+        .instrument(span)
+        .await
+    }
 
-        Ok(packet)
+    // Why the connection is gone, if it is
+    pub fn disconnect_reason(&self) -> Option<&DisconnectReason> {
+        self.disconnect_reason.as_ref()
     }
 
-    pub async fn process_raw_packet(&mut self, stream: BitStream) -> Result<()> {
-        for result in self.dnet.process_raw_packet(stream)? {
-            match result {
-                DNetResult::SendPacket(packet) => self.send_raw(packet).await?,
-                DNetResult::KeepAlive => {
-                    // Nothing
-                }
-                DNetResult::HandleConnectionEstablished => {
-                    println!("Connection established");
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnect_reason.is_some()
+    }
+
+    pub async fn disconnect(&mut self, reason: &str) -> Result<()> {
+        self.send_packet(Packet::Disconnect {
+            sequence: self.connect_sequence,
+            reason: reason.to_string(),
+        })
+        .await?;
+        self.disconnect_reason = Some(DisconnectReason::Local(reason.to_string()));
+        Ok(())
+    }
+
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.dnet.round_trip_time()
+    }
+
+    pub fn stats(&self) -> &ConnectionStats {
+        self.dnet.stats()
+    }
+
+    pub fn set_ping_timeout(&mut self, ping_timeout: Duration, ping_retry_count: u32) {
+        self.dnet.set_ping_timeout(ping_timeout, ping_retry_count);
+    }
+
+    pub fn string_encoding(&self) -> StringEncoding {
+        self.string_encoding
+    }
+
+    // How strings in data packets are encoded, which depends on the engine
+    pub fn set_string_encoding(&mut self, string_encoding: StringEncoding) {
+        self.string_encoding = string_encoding;
+    }
+
+    pub fn set_huffman_table(&mut self, huffman_table: Arc<HuffmanTable>) {
+        self.huffman_table = huffman_table;
+    }
+
+    // Call regularly along with check_packet_send. Pings the server when it's gone quiet,
+    // and returns the reason once we've given up on it.
+    pub async fn check_timeout(&mut self) -> Result<Option<DisconnectReason>> {
+        let span = self.span.clone();
+        async move {
+            if let Some(result) = self.dnet.check_timeout(Instant::now())? {
+                self.handle_dnet_result(result).await?;
+            }
+            Ok(self.disconnect_reason.clone())
+        }
+        .instrument(span)
+        .await
+    }
+
+    pub async fn process_raw_packet(&mut self, mut stream: BitStream) -> Result<()> {
+        let span = self.span.clone();
+        async move {
+            stream.set_string_encoding(self.string_encoding);
+            stream.set_huffman_table(self.huffman_table.clone());
+            for result in self.dnet.process_raw_packet(stream)? {
+                self.handle_dnet_result(result).await?;
+            }
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn handle_dnet_result(&mut self, result: DNetResult) -> Result<()> {
+        match result {
+            DNetResult::SendPacket(packet) => self.send_raw(packet).await?,
+            DNetResult::KeepAlive => {
+                self.dnet.keep_alive();
+            }
+            DNetResult::HandleConnectionEstablished => {
+                info!("Connection established");
+            }
+            DNetResult::HandleNotify(recvd) => {
+                trace!(recvd, "Notify");
+                self.rate.handle_notify(recvd);
+                self.ghost_manager.handle_notify(recvd);
+                for (mut event, made_it) in self.events.handle_notify(recvd) {
+                    event.notify_delivered(self, made_it);
                 }
-                DNetResult::HandleNotify(recvd) => {
-                    println!("Last packet was recvd: {}", recvd);
+            }
+            DNetResult::HandlePacket(packet) => {
+                trace!(bytes = packet.len(), "Data packet");
+                // DNet has already acked the packet, so whatever was lost in it won't
+                // be sent again. Torque drops the connection too.
+                if let Err(error) = self.read_data_packet(packet) {
+                    warn!(%error, "Invalid packet, disconnecting");
+                    self.disconnect("Invalid packet.").await?;
+                    return Err(error);
                 }
-                DNetResult::HandlePacket(packet) => {
-                    println!("Packet: {:?}", packet);
 
-                    self.send_raw_packet().await?;
-                }
+                self.check_packet_send(false).await?;
+            }
+            DNetResult::Disconnect(reason) => {
+                info!(?reason, "Disconnected");
+                self.disconnect_reason = Some(reason);
             }
         }
         Ok(())
     }
 
+    fn read_data_packet(&mut self, mut stream: BitStream) -> Result<()> {
+        self.rate.read_packet(&mut stream)?;
+
+        let header = ServerPacketHeader::read(&mut stream, |ghost_index, stream| {
+            let mut object = self
+                .ghosts
+                .remove(ghost_index)
+                .ok_or(DnetError::UnknownGhost(ghost_index))?;
+            let result = object.read_packet_data(self, stream);
+            self.ghosts.insert(ghost_index, object)?;
+            result
+        })?;
+        self.moves.ack(header.last_move_ack);
+        match header.control_object {
+            Some(ControlObjectUpdate::Update(ghost_index)) => {
+                self.control_object = Some(ghost_index)
+            }
+            Some(ControlObjectUpdate::CompressionPoint(_)) => {}
+            None => self.control_object = None,
+        }
+        if let Some(damage_flash) = header.damage_flash {
+            self.damage_flash = damage_flash;
+        }
+        if let Some(white_out) = header.white_out {
+            self.white_out = white_out;
+        }
+        self.server_header = header;
+
+        // Events get to look at the connection while they unpack, so the event manager
+        // has to come out of it for a bit
+        let mut event_manager = std::mem::take(&mut self.events);
+        let connection = &*self;
+        let events = event_manager.read_packet(&mut stream, |stream| {
+            let registry = connection.class_registry();
+            let group = connection.net_class_group();
+            let class_id =
+                registry.read_class_id(stream, NetClassTypes::NetClassTypeEvent, group)?;
+            let mut event = registry.create_event(group, class_id)?;
+            event.unpack(connection, stream)?;
+            Ok(event)
+        });
+        self.events = event_manager;
+
+        for mut event in events? {
+            debug!(?event, "Event");
+            event.process(self)?;
+        }
+
+        if !self.ghosting_from {
+            read_ghost_packet(self, &mut stream)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_data_packet(&mut self, stream: &mut BitStream) -> Result<()> {
+        self.rate.write_packet(stream);
+
+        ClientPacketHeader {
+            first_move_index: self.moves.first_move_index(),
+            moves: self.moves.pending_moves(),
+            camera_pos: false,
+            control_force_mismatch: false,
+            control_scheme: self.control_scheme_update.take(),
+            first_person: self.first_person_update.take(),
+            camera_fov: self.camera_fov_update.take(),
+        }
+        .write(stream);
+
+        let max_bits = self.rate.max_packet_bits();
+        let registry = &self.class_registry;
+        let group = self.net_class_group;
+        let events = self.events.write_packet(stream, max_bits, |event, stream| {
+            // Checked in post_event, but the class could have been re-registered in
+            // another group since
+            let class_name = event.class_name();
+            let class_id = registry
+                .class_id(group, NetClassTypes::NetClassTypeEvent, class_name)
+                .ok_or_else(|| DnetError::UnregisteredClass(class_name.to_string()))?;
+            registry.write_class_id(stream, class_id, NetClassTypes::NetClassTypeEvent, group);
+            event.pack(stream);
+            Ok(())
+        });
+
+        // The ghost manager needs a notify entry for every packet, whether or not the
+        // events made it in
+        if self.ghosting_from && events.is_ok() {
+            self.ghost_manager
+                .write_packet(stream, max_bits, &self.class_registry, group)
+        } else {
+            self.ghost_manager.push_empty_notify();
+            events
+        }
+    }
+
+    // NetConnection::checkPacketSend, call this regularly (see next_send_time). Sends a
+    // data packet if it's been long enough since the last one and DNet has room for it,
+    // or regardless of timing if forced. Returns whether a packet was sent.
+    pub async fn check_packet_send(&mut self, force: bool) -> Result<bool> {
+        let span = self.span.clone();
+        async move {
+            let now = Instant::now();
+            if !force && !self.rate.check_send_time(now) {
+                return Ok(false);
+            }
+            if self.dnet.window_full() {
+                debug!("Send window full");
+                return Ok(false);
+            }
+            self.rate.mark_sent(now);
+            self.send_raw_packet().await?;
+            Ok(true)
+        }
+        .instrument(span)
+        .await
+    }
+
+    pub fn next_send_time(&self) -> Option<Instant> {
+        self.rate.next_send_time()
+    }
+
     pub async fn send_raw_packet(&mut self) -> Result<()> {
-        let mut packet = BitStream::new();
+        let mut packet = BitStream::with_max_size(MAX_PACKET_DATA_SIZE);
+        packet.set_string_encoding(self.string_encoding);
+        packet.set_huffman_table(self.huffman_table.clone());
         self.dnet
-            .build_send_packet_header(&mut packet, NetPacketType::DataPacket);
-
-        // packet.write_flag(false); // update mCurRate
-        // packet.write_flag(false); // more rate control
-        //
-        // packet.write_int(0, 32); // move packet start
-        // packet.write_int(0, 5); // move packet count
-        //
-        // packet.write_flag(false); // camera pos
-        // packet.write_flag(false); // control force mismatch
-        // packet.write_flag(false); // changed control scheme
-        // packet.write_flag(false); // changed first person
-        // packet.write_flag(false); // changed fov
-
-        // LOL crash
-        for _ in 0..2000 {
-            packet.write_flag(rand::thread_rng().gen_bool(0.5));
-        }
+            .build_send_packet_header(&mut packet, NetPacketType::DataPacket)?;
+        self.write_data_packet(&mut packet)?;
+
+        self.send_raw(packet).await?;
+
+        Ok(())
+    }
+
+    // Sends a data packet with whatever you want in it, for poking at servers
+    pub async fn send_raw_packet_with<F>(&mut self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut BitStream),
+    {
+        let mut packet = BitStream::with_max_size(MAX_PACKET_DATA_SIZE);
+        packet.set_string_encoding(self.string_encoding);
+        packet.set_huffman_table(self.huffman_table.clone());
+        self.dnet
+            .build_send_packet_header(&mut packet, NetPacketType::DataPacket)?;
+        // DNet will notify us about this packet like any other
+        self.rate.push_empty_notify();
+        self.events.push_empty_notify();
+        self.ghost_manager.push_empty_notify();
+        write(&mut packet);
 
         self.send_raw(packet).await?;
 
@@ -1,5 +1,12 @@
+use super::stats::ConnectionStats;
+use crate::error::{DnetError, Result};
 use crate::packet::BitStream;
-use anyhow::{Error, Result};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+// NetConnection::DefaultPingTimeout / DefaultPingRetryCount
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_millis(5000);
+pub const DEFAULT_PING_RETRY_COUNT: u32 = 5;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum NetPacketType {
@@ -18,6 +25,24 @@ pub struct DNet {
     connect_sequence: u32,
     last_recv_ack_ack: u32,
     connection_established: bool,
+    // When each data packet in the ack window was sent, for round trip times
+    send_times: [Option<Instant>; 32],
+    round_trip_time: Option<Duration>,
+    ping_timeout: Duration,
+    ping_retry_count: u32,
+    last_ping_send_time: Option<Instant>,
+    ping_send_count: u32,
+    stats: ConnectionStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    // Nothing came back after this many pings
+    TimedOut { unanswered_pings: u32 },
+    // The other side sent a Disconnect packet
+    Remote(String),
+    // We sent a Disconnect packet
+    Local(String),
 }
 
 pub enum DNetResult {
@@ -26,6 +51,7 @@ pub enum DNetResult {
     HandleConnectionEstablished,
     HandleNotify(bool),
     HandlePacket(BitStream),
+    Disconnect(DisconnectReason),
 }
 
 impl DNet {
@@ -39,7 +65,65 @@ impl DNet {
             connect_sequence,
             last_recv_ack_ack: 0,
             connection_established: false,
+            send_times: [None; 32],
+            round_trip_time: None,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            ping_retry_count: DEFAULT_PING_RETRY_COUNT,
+            last_ping_send_time: None,
+            ping_send_count: 0,
+            stats: ConnectionStats::new(),
+        }
+    }
+
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    // Headers are built before the rest of the packet, and building one doesn't mean it
+    // gets sent, so whoever sends it fills this in
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.stats.record_sent(bytes);
+    }
+
+    pub fn set_ping_timeout(&mut self, ping_timeout: Duration, ping_retry_count: u32) {
+        self.ping_timeout = ping_timeout;
+        self.ping_retry_count = ping_retry_count;
+    }
+
+    // Time between sending the last data packet that was acked and getting the ack
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.round_trip_time
+    }
+
+    // We heard from the other side, so stop pinging
+    pub fn keep_alive(&mut self) {
+        self.last_ping_send_time = None;
+        self.ping_send_count = 0;
+    }
+
+    // NetConnection::checkTimeout, call regularly. Sends a ping every ping_timeout that
+    // we don't hear anything, and gives up after ping_retry_count of them.
+    pub fn check_timeout(&mut self, now: Instant) -> Result<Option<DNetResult>> {
+        let last_ping_send_time = match self.last_ping_send_time {
+            Some(time) => time,
+            None => {
+                self.last_ping_send_time = Some(now);
+                return Ok(None);
+            }
+        };
+
+        if now.duration_since(last_ping_send_time) <= self.ping_timeout {
+            return Ok(None);
         }
+        if self.ping_send_count >= self.ping_retry_count {
+            return Ok(Some(DNetResult::Disconnect(DisconnectReason::TimedOut {
+                unanswered_pings: self.ping_send_count,
+            })));
+        }
+
+        self.last_ping_send_time = Some(now);
+        self.ping_send_count += 1;
+        Ok(Some(DNetResult::SendPacket(self.make_ping_packet()?)))
     }
 
     pub fn window_full(&self) -> bool {
@@ -48,6 +132,7 @@ impl DNet {
 
     pub fn process_raw_packet(&mut self, mut stream: BitStream) -> Result<Vec<DNetResult>> {
         let mut results = vec![];
+        self.stats.record_received(stream.len());
 
         stream.read_flag()?;
         let connect_seq_bit = stream.read_int(1)?;
@@ -57,13 +142,13 @@ impl DNet {
         let ack_byte_count = stream.read_int(3)?;
 
         if connect_seq_bit != (self.connect_sequence & 1) {
-            return Err(Error::msg("Bad seq bit"));
+            return Err(DnetError::BadConnectSequence);
         }
         if ack_byte_count > 4 {
-            return Err(Error::msg("Too many ack bytes"));
+            return Err(DnetError::TooManyAckBytes(ack_byte_count));
         }
         if packet_type >= NetPacketType::InvalidPacketType as u32 {
-            return Err(Error::msg("Invalid packet type"));
+            return Err(DnetError::InvalidPacketType(packet_type));
         }
 
         let ack_mask = stream.read_int((8 * ack_byte_count) as usize)?;
@@ -77,6 +162,8 @@ impl DNet {
 
         if seq_num > self.last_seq_received + 31 {
             // Out of order
+            debug!(seq_num, "Dropping packet outside of the receive window");
+            self.stats.record_out_of_window();
             return Ok(vec![]);
         }
 
@@ -88,21 +175,26 @@ impl DNet {
 
         if highest_ack > self.last_send_seq {
             // Out of order
+            debug!(
+                highest_ack,
+                "Dropping packet acking something we never sent"
+            );
+            self.stats.record_out_of_window();
             return Ok(vec![]);
         }
 
         for i in (self.last_seq_received + 1)..seq_num {
-            println!("Not recv: {}", i);
+            debug!(seq = i, "Not recv");
         }
-        println!(
-            "Recv: {} {}",
+        trace!(
             seq_num,
-            match packet_type {
+            packet_type = match packet_type {
                 a if a == NetPacketType::DataPacket as u32 => "DataPacket",
                 a if a == NetPacketType::PingPacket as u32 => "PingPacket",
                 a if a == NetPacketType::AckPacket as u32 => "AckPacket",
                 _ => "??",
-            }
+            },
+            "Recv"
         );
 
         self.ack_mask <<= seq_num - self.last_seq_received;
@@ -115,10 +207,16 @@ impl DNet {
         for i in (self.highest_acked_seq + 1)..=highest_ack {
             let transmit_success = (ack_mask & (1 << (highest_ack - i))) != 0;
             results.push(DNetResult::HandleNotify(transmit_success));
+            self.stats.record_notify(transmit_success);
 
-            println!("Ack {} {}", i, transmit_success);
+            trace!(seq = i, transmit_success, "Ack");
 
             if transmit_success {
+                if let Some(send_time) = self.send_times[(i & 0x1F) as usize].take() {
+                    let round_trip_time = send_time.elapsed();
+                    self.round_trip_time = Some(round_trip_time);
+                    self.stats.record_rtt(round_trip_time);
+                }
                 self.last_recv_ack_ack = self.last_seq_recvd_at_send[(i & 0x1F) as usize];
                 if !self.connection_established {
                     self.connection_established = true;
@@ -149,29 +247,35 @@ impl DNet {
 
     fn make_ping_packet(&mut self) -> Result<BitStream> {
         let mut stream = BitStream::new();
-        self.build_send_packet_header(&mut stream, NetPacketType::PingPacket);
-        println!("Send ping: {}", self.last_send_seq);
+        self.build_send_packet_header(&mut stream, NetPacketType::PingPacket)?;
+        debug!(seq = self.last_send_seq, "Send ping");
 
         Ok(stream)
     }
 
     fn make_ack_packet(&mut self) -> Result<BitStream> {
         let mut stream = BitStream::new();
-        self.build_send_packet_header(&mut stream, NetPacketType::AckPacket);
-        println!("Send ack: {}", self.last_send_seq);
+        self.build_send_packet_header(&mut stream, NetPacketType::AckPacket)?;
+        trace!(seq = self.last_send_seq, "Send ack");
 
         Ok(stream)
     }
 
-    pub fn build_send_packet_header(&mut self, stream: &mut BitStream, packet_type: NetPacketType) {
+    pub fn build_send_packet_header(
+        &mut self,
+        stream: &mut BitStream,
+        packet_type: NetPacketType,
+    ) -> Result<()> {
         let ack_byte_count = (self.last_seq_received - self.last_recv_ack_ack + 7) >> 3;
-        assert!(ack_byte_count <= 4);
+        if ack_byte_count > 4 {
+            return Err(DnetError::TooManyAckBytes(ack_byte_count));
+        }
 
         if packet_type == NetPacketType::DataPacket {
             self.last_send_seq += 1;
         }
 
-        println!("build hdr {} {:?}", self.last_send_seq, packet_type);
+        trace!(seq = self.last_send_seq, ?packet_type, "Build header");
 
         stream.write_flag(true);
         stream.write_int(self.connect_sequence & 1, 1);
@@ -184,6 +288,8 @@ impl DNet {
         if packet_type == NetPacketType::DataPacket {
             self.last_seq_recvd_at_send[(self.last_send_seq & 0x1F) as usize] =
                 self.last_seq_received;
+            self.send_times[(self.last_send_seq & 0x1F) as usize] = Some(Instant::now());
         }
+        Ok(())
     }
 }
@@ -0,0 +1,303 @@
+use crate::error::Result;
+use crate::packet::BitStream;
+use std::collections::VecDeque;
+
+// Torque's NetConnection::eventWritePacket / eventReadPacket, minus the NetEvent bits
+// so that whatever is sitting on top can decide how messages are packed.
+
+const INVALID_SEND_EVENT_SEQ: i32 = -1;
+const FIRST_VALID_SEND_EVENT_SEQ: i32 = 0;
+// How far ahead of the last acked ordered event we are allowed to send
+const EVENT_SEQ_WINDOW: i32 = 126;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum GuaranteeType {
+    GuaranteedOrdered = 0,
+    Guaranteed = 1,
+    Unguaranteed = 2,
+}
+
+struct EventNote<M> {
+    message: M,
+    guarantee_type: GuaranteeType,
+    seq: i32,
+}
+
+pub struct EventManager<M> {
+    // Guaranteed and unguaranteed events, sent in the first half of the packet
+    unordered_send_queue: VecDeque<EventNote<M>>,
+    // Guaranteed ordered events, sent with sequence numbers
+    send_queue: VecDeque<EventNote<M>>,
+    // One entry per data packet sent, popped when DNet notifies us about it
+    notify_queue: VecDeque<Vec<EventNote<M>>>,
+    // Ordered events that made it, but are waiting on earlier ones to be acked
+    notify_event_list: Vec<EventNote<M>>,
+    // Ordered events we received out of order
+    wait_seq_events: Vec<(i32, M)>,
+    next_send_event_seq: i32,
+    last_acked_event_seq: i32,
+    next_recv_event_seq: i32,
+}
+
+impl<M> Default for EventManager<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> EventManager<M> {
+    pub fn new() -> Self {
+        EventManager {
+            unordered_send_queue: VecDeque::new(),
+            send_queue: VecDeque::new(),
+            notify_queue: VecDeque::new(),
+            notify_event_list: vec![],
+            wait_seq_events: vec![],
+            next_send_event_seq: FIRST_VALID_SEND_EVENT_SEQ,
+            last_acked_event_seq: FIRST_VALID_SEND_EVENT_SEQ - 1,
+            next_recv_event_seq: FIRST_VALID_SEND_EVENT_SEQ,
+        }
+    }
+
+    pub fn post_event(&mut self, message: M, guarantee_type: GuaranteeType) {
+        if guarantee_type == GuaranteeType::GuaranteedOrdered {
+            self.send_queue.push_back(EventNote {
+                message,
+                guarantee_type,
+                seq: self.next_send_event_seq,
+            });
+            self.next_send_event_seq += 1;
+        } else {
+            self.unordered_send_queue.push_back(EventNote {
+                message,
+                guarantee_type,
+                seq: INVALID_SEND_EVENT_SEQ,
+            });
+        }
+    }
+
+    pub fn is_send_pending(&self) -> bool {
+        !self.unordered_send_queue.is_empty() || !self.send_queue.is_empty()
+    }
+
+    // Every data packet sent needs a notify entry, so packets without events in them
+    // (see GameConnection::send_raw_packet_with) still line up with DNet's notifies
+    pub fn push_empty_notify(&mut self) {
+        self.notify_queue.push_back(vec![]);
+    }
+
+    // Events are packed until one goes past max_bits, which is taken back out of the
+    // stream and left queued for the next packet. If pack fails, the event is taken
+    // back out too and the packet is ended there. The packet is still tracked either
+    // way, since DNet will notify us about it whether or not it gets sent.
+    pub fn write_packet<F>(
+        &mut self,
+        stream: &mut BitStream,
+        max_bits: usize,
+        mut pack: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&M, &mut BitStream) -> Result<()>,
+    {
+        let mut packet_notes = vec![];
+        let mut result = Ok(());
+
+        // Unguaranteed and guaranteed (unordered) events first, leaving room for the
+        // flags that end both phases
+        while let Some(note) = self.unordered_send_queue.front() {
+            let start = stream.get_bit_pos();
+            stream.write_flag(true);
+            if let Err(error) = pack(&note.message, stream) {
+                stream.rewind(start);
+                result = Err(error);
+                break;
+            }
+            if stream.is_overflowed() || stream.get_bit_pos() + 2 > max_bits {
+                stream.rewind(start);
+                break;
+            }
+            packet_notes.push(self.unordered_send_queue.pop_front().unwrap());
+        }
+        stream.write_flag(false);
+
+        // Then guaranteed ordered, which get sequence numbers
+        let mut prev_seq = -2;
+        while let Some(note) = self.send_queue.front() {
+            if result.is_err() {
+                break;
+            }
+            // Don't run off the end of the receiver's window
+            if note.seq > self.last_acked_event_seq + EVENT_SEQ_WINDOW {
+                break;
+            }
+
+            let start = stream.get_bit_pos();
+            stream.write_flag(true);
+            if !stream.write_flag(note.seq == prev_seq + 1) {
+                stream.write_int((note.seq & 0x7F) as u32, 7);
+            }
+            if let Err(error) = pack(&note.message, stream) {
+                stream.rewind(start);
+                result = Err(error);
+                break;
+            }
+            if stream.is_overflowed() || stream.get_bit_pos() + 1 > max_bits {
+                stream.rewind(start);
+                break;
+            }
+            prev_seq = note.seq;
+            packet_notes.push(self.send_queue.pop_front().unwrap());
+        }
+        stream.write_flag(false);
+
+        self.notify_queue.push_back(packet_notes);
+        result
+    }
+
+    // Returns messages in the order they should be processed. The whole packet is read
+    // before anything is delivered, so if one event fails to unpack, none of the packet
+    // counts as received. DNet has acked it by then and nothing in it will be sent
+    // again, so like Torque, the caller should treat that as fatal to the connection.
+    pub fn read_packet<F>(&mut self, stream: &mut BitStream, mut unpack: F) -> Result<Vec<M>>
+    where
+        F: FnMut(&mut BitStream) -> Result<M>,
+    {
+        let mut unordered = vec![];
+        let mut ordered = vec![];
+        let mut prev_seq = -2;
+        let mut unguaranteed_phase = true;
+
+        loop {
+            let mut bit = stream.read_flag()?;
+            if unguaranteed_phase && !bit {
+                unguaranteed_phase = false;
+                bit = stream.read_flag()?;
+            }
+            if !unguaranteed_phase && !bit {
+                break;
+            }
+
+            if unguaranteed_phase {
+                unordered.push(unpack(stream)?);
+                continue;
+            }
+
+            // Set when this event follows straight on from the last one
+            let mut seq = if stream.read_flag()? {
+                prev_seq + 1
+            } else {
+                stream.read_int(7)? as i32 | (self.next_recv_event_seq & !0x7F)
+            };
+            if seq < self.next_recv_event_seq {
+                seq += 128;
+            }
+            prev_seq = seq;
+
+            ordered.push((seq, unpack(stream)?));
+        }
+
+        let mut results = unordered;
+        for (seq, message) in ordered {
+            if seq == self.next_recv_event_seq {
+                results.push(message);
+                self.next_recv_event_seq += 1;
+
+                // Anything we were holding on to that is now in order can go too
+                while !self.wait_seq_events.is_empty()
+                    && self.wait_seq_events[0].0 == self.next_recv_event_seq
+                {
+                    results.push(self.wait_seq_events.remove(0).1);
+                    self.next_recv_event_seq += 1;
+                }
+            } else {
+                let index = self
+                    .wait_seq_events
+                    .iter()
+                    .position(|(wait_seq, _)| *wait_seq >= seq)
+                    .unwrap_or(self.wait_seq_events.len());
+                // Duplicates can show up if an ack was lost, just drop them
+                if index < self.wait_seq_events.len() && self.wait_seq_events[index].0 == seq {
+                    continue;
+                }
+                self.wait_seq_events.insert(index, (seq, message));
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Call once for each DNetResult::HandleNotify, returns messages that are done being
+    // tracked along with whether or not they made it to the other side
+    pub fn handle_notify(&mut self, recvd: bool) -> Vec<(M, bool)> {
+        let notes = match self.notify_queue.pop_front() {
+            Some(notes) => notes,
+            None => return vec![],
+        };
+
+        if recvd {
+            self.packet_received(notes)
+        } else {
+            self.packet_dropped(notes)
+        }
+    }
+
+    fn packet_received(&mut self, notes: Vec<EventNote<M>>) -> Vec<(M, bool)> {
+        let mut finished = vec![];
+
+        for note in notes {
+            if note.guarantee_type != GuaranteeType::GuaranteedOrdered {
+                finished.push((note.message, true));
+                continue;
+            }
+
+            let index = self
+                .notify_event_list
+                .iter()
+                .position(|other| other.seq >= note.seq)
+                .unwrap_or(self.notify_event_list.len());
+            self.notify_event_list.insert(index, note);
+        }
+
+        // Ordered events are only delivered once everything before them is
+        while !self.notify_event_list.is_empty()
+            && self.notify_event_list[0].seq == self.last_acked_event_seq + 1
+        {
+            self.last_acked_event_seq += 1;
+            finished.push((self.notify_event_list.remove(0).message, true));
+        }
+
+        finished
+    }
+
+    fn packet_dropped(&mut self, notes: Vec<EventNote<M>>) -> Vec<(M, bool)> {
+        let mut finished = vec![];
+        let mut unordered_resend = vec![];
+
+        for note in notes {
+            match note.guarantee_type {
+                GuaranteeType::GuaranteedOrdered => {
+                    // Back into the send queue, in sequence order
+                    let index = self
+                        .send_queue
+                        .iter()
+                        .position(|other| other.seq > note.seq)
+                        .unwrap_or(self.send_queue.len());
+                    self.send_queue.insert(index, note);
+                }
+                GuaranteeType::Guaranteed => {
+                    unordered_resend.push(note);
+                }
+                GuaranteeType::Unguaranteed => {
+                    finished.push((note.message, false));
+                }
+            }
+        }
+
+        // Guaranteed events go to the front of the line, keeping their original order
+        for note in unordered_resend.into_iter().rev() {
+            self.unordered_send_queue.push_front(note);
+        }
+
+        finished
+    }
+}
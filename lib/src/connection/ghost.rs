@@ -0,0 +1,187 @@
+use super::connection::GameConnection;
+use super::net_class::NetClassTypes;
+use super::net_event::NetEvent;
+use super::net_object::NetObject;
+use crate::error::{DnetError, Result};
+use crate::packet::BitStream;
+
+// NetConnection::GhostConstants
+pub const GHOST_ID_BIT_SIZE: usize = 12;
+pub const MAX_GHOST_COUNT: usize = 1 << GHOST_ID_BIT_SIZE;
+// Ghost packets start with how many bits their ghost indices take, minus 3
+pub const GHOST_INDEX_BIT_SIZE: usize = 4;
+
+// Client side mLocalGhosts, indexed by the ghost index the server gave us
+#[derive(Debug)]
+pub struct GhostTable {
+    ghosts: Vec<Option<Box<dyn NetObject>>>,
+    ghosts_active: usize,
+}
+
+impl Default for GhostTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GhostTable {
+    pub fn new() -> Self {
+        GhostTable {
+            ghosts: (0..MAX_GHOST_COUNT).map(|_| None).collect(),
+            ghosts_active: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ghosts_active
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ghosts_active == 0
+    }
+
+    pub fn get(&self, index: u32) -> Option<&dyn NetObject> {
+        self.ghosts
+            .get(index as usize)
+            .and_then(|ghost| ghost.as_deref())
+    }
+
+    pub fn get_mut(&mut self, index: u32) -> Option<&mut (dyn NetObject + 'static)> {
+        self.ghosts
+            .get_mut(index as usize)
+            .and_then(|ghost| ghost.as_deref_mut())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &dyn NetObject)> {
+        self.ghosts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, ghost)| ghost.as_deref().map(|ghost| (i as u32, ghost)))
+    }
+
+    pub fn insert(&mut self, index: u32, object: Box<dyn NetObject>) -> Result<()> {
+        let slot = self
+            .ghosts
+            .get_mut(index as usize)
+            .ok_or(DnetError::GhostIndexOutOfRange(index))?;
+        if slot.is_none() {
+            self.ghosts_active += 1;
+        }
+        *slot = Some(object);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: u32) -> Option<Box<dyn NetObject>> {
+        let removed = self
+            .ghosts
+            .get_mut(index as usize)
+            .and_then(|slot| slot.take());
+        if removed.is_some() {
+            self.ghosts_active -= 1;
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.ghosts.iter_mut() {
+            *slot = None;
+        }
+        self.ghosts_active = 0;
+    }
+}
+
+// NetConnection::ghostReadPacket
+pub(crate) fn read_ghost_packet(
+    connection: &mut GameConnection,
+    stream: &mut BitStream,
+) -> Result<()> {
+    if !stream.read_flag()? {
+        return Ok(());
+    }
+
+    // Any wider and the indices could run past the ghost table
+    let id_size = stream.read_int(GHOST_INDEX_BIT_SIZE)? as usize + 3;
+    if id_size > GHOST_ID_BIT_SIZE {
+        return Err(DnetError::GhostIndexSizeOutOfRange(id_size));
+    }
+
+    while stream.read_flag()? {
+        let index = stream.read_int(id_size)?;
+
+        if stream.read_flag()? {
+            // Being deleted
+            if connection.ghosts_mut().remove(index).is_none() {
+                return Err(DnetError::UnknownGhost(index));
+            }
+            continue;
+        }
+
+        // Take it out of the table so it can see the connection while it unpacks
+        let mut object = match connection.ghosts_mut().remove(index) {
+            Some(object) => object,
+            None => {
+                // New ghost
+                let group = connection.net_class_group();
+                let registry = connection.class_registry();
+                let class_id =
+                    registry.read_class_id(stream, NetClassTypes::NetClassTypeObject, group)?;
+                registry.create_object(group, class_id)?
+            }
+        };
+
+        let result = object.unpack_update(connection, stream);
+        connection.ghosts_mut().insert(index, object)?;
+        result?;
+    }
+
+    Ok(())
+}
+
+// Objects that are always ghosted get sent up front as guaranteed events. We
+// only ever receive these, GhostManager doesn't do ghost always objects
+#[derive(Debug, Default)]
+pub(crate) struct GhostAlwaysObjectEvent {
+    ghost_index: u32,
+    object: Option<Box<dyn NetObject>>,
+}
+
+impl GhostAlwaysObjectEvent {
+    pub const CLASS_NAME: &'static str = "GhostAlwaysObjectEvent";
+
+    pub fn create() -> Box<dyn NetEvent> {
+        Box::new(GhostAlwaysObjectEvent::default())
+    }
+}
+
+impl NetEvent for GhostAlwaysObjectEvent {
+    fn class_name(&self) -> &'static str {
+        Self::CLASS_NAME
+    }
+
+    fn pack(&self, stream: &mut BitStream) {
+        // Only the server sends these, and we don't have its class ids here
+        stream.write_int(self.ghost_index, GHOST_ID_BIT_SIZE);
+        stream.write_flag(false);
+    }
+
+    fn unpack(&mut self, connection: &GameConnection, stream: &mut BitStream) -> Result<()> {
+        self.ghost_index = stream.read_int(GHOST_ID_BIT_SIZE)?;
+        if stream.read_flag()? {
+            let group = connection.net_class_group();
+            let registry = connection.class_registry();
+            let class_id =
+                registry.read_class_id(stream, NetClassTypes::NetClassTypeObject, group)?;
+            let mut object = registry.create_object(group, class_id)?;
+            object.unpack_update(connection, stream)?;
+            self.object = Some(object);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, connection: &mut GameConnection) -> Result<()> {
+        if let Some(object) = self.object.take() {
+            connection.ghosts_mut().insert(self.ghost_index, object)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+use super::ghost::{GHOST_INDEX_BIT_SIZE, MAX_GHOST_COUNT};
+use super::net_class::{NetClassRegistry, NetClassTypes};
+use super::net_object::NetObject;
+use crate::error::{DnetError, Result};
+use crate::packet::BitStream;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Server side half of NetConnection's ghosting: ghostWritePacket and friends.
+// The world owns the objects, we just hold on to them while they are in scope for
+// this connection.
+
+pub type NetObjectRef = Arc<Mutex<dyn NetObject>>;
+
+// GhostInfo flags
+const NOT_YET_GHOSTED: u32 = 1 << 0;
+const GHOSTING: u32 = 1 << 1;
+const KILL_GHOST: u32 = 1 << 2;
+const KILLING_GHOST: u32 = 1 << 3;
+
+// Priority given to ghosts that need deleting, so they go out first
+const KILL_GHOST_PRIORITY: f32 = 10000.0;
+
+struct GhostInfo {
+    object_id: u32,
+    object: NetObjectRef,
+    // Slots get reused, so notifies need to know which ghost they were for
+    generation: u32,
+    flags: u32,
+    update_mask: u32,
+    update_skip_count: u32,
+}
+
+// What was sent for a ghost in a given packet
+struct GhostRef {
+    index: u32,
+    generation: u32,
+    mask: u32,
+    ghost_info_flags: u32,
+}
+
+pub struct GhostManager {
+    ghosts: Vec<Option<GhostInfo>>,
+    // Object id -> ghost index, for objects that are in scope
+    object_ghosts: HashMap<u32, u32>,
+    // One entry per data packet sent, popped when DNet notifies us about it
+    notify_queue: VecDeque<Vec<GhostRef>>,
+    next_generation: u32,
+    ghosting: bool,
+}
+
+impl Default for GhostManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GhostManager {
+    pub fn new() -> Self {
+        GhostManager {
+            ghosts: (0..MAX_GHOST_COUNT).map(|_| None).collect(),
+            object_ghosts: HashMap::new(),
+            notify_queue: VecDeque::new(),
+            next_generation: 0,
+            ghosting: false,
+        }
+    }
+
+    pub fn is_ghosting(&self) -> bool {
+        self.ghosting
+    }
+
+    // activateGhosting
+    pub fn activate_ghosting(&mut self) {
+        self.ghosting = true;
+    }
+
+    // resetGhosting, the client needs to be told to clear its ghosts separately
+    pub fn reset_ghosting(&mut self) {
+        self.ghosting = false;
+        for slot in self.ghosts.iter_mut() {
+            *slot = None;
+        }
+        self.object_ghosts.clear();
+    }
+
+    pub fn ghost_count(&self) -> usize {
+        self.ghosts.iter().filter(|ghost| ghost.is_some()).count()
+    }
+
+    pub fn ghost_index(&self, object_id: u32) -> Option<u32> {
+        self.object_ghosts.get(&object_id).copied()
+    }
+
+    // getGhostIndex, only once the client has the ghost and it isn't being deleted
+    pub fn ghosted_index(&self, object_id: u32) -> Option<u32> {
+        self.ghost_index(object_id).filter(|&index| {
+            matches!(&self.ghosts[index as usize], Some(ghost)
+                if ghost.flags & (NOT_YET_GHOSTED | GHOSTING | KILL_GHOST | KILLING_GHOST) == 0)
+        })
+    }
+
+    // Start ghosting an object to this connection, if it isn't already
+    pub fn object_in_scope(&mut self, object_id: u32, object: NetObjectRef) -> Result<u32> {
+        if let Some(index) = self.ghost_index(object_id) {
+            return Ok(index);
+        }
+
+        let index = self
+            .ghosts
+            .iter()
+            .position(|ghost| ghost.is_none())
+            .ok_or(DnetError::TooManyGhosts(object_id))?;
+
+        self.ghosts[index] = Some(GhostInfo {
+            object_id,
+            object,
+            generation: self.next_generation,
+            flags: NOT_YET_GHOSTED,
+            update_mask: 0xFFFFFFFF,
+            update_skip_count: 0,
+        });
+        self.next_generation = self.next_generation.wrapping_add(1);
+        self.object_ghosts.insert(object_id, index as u32);
+        Ok(index as u32)
+    }
+
+    // detachObject, the ghost is deleted on the client in a later packet
+    pub fn object_out_of_scope(&mut self, object_id: u32) -> bool {
+        let index = match self.object_ghosts.remove(&object_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        if let Some(ghost) = self.ghosts[index as usize].as_mut() {
+            ghost.flags |= KILL_GHOST;
+        }
+        true
+    }
+
+    // Call when an object changes, so the bits in mask get sent out
+    pub fn set_mask_bits(&mut self, object_id: u32, mask: u32) {
+        if let Some(index) = self.ghost_index(object_id) {
+            if let Some(ghost) = self.ghosts[index as usize].as_mut() {
+                ghost.update_mask |= mask;
+            }
+        }
+    }
+
+    fn free_ghost(&mut self, index: u32) {
+        if let Some(ghost) = self.ghosts[index as usize].take() {
+            // Only if the object wasn't re-scoped into a new slot already
+            if self.object_ghosts.get(&ghost.object_id) == Some(&index) {
+                self.object_ghosts.remove(&ghost.object_id);
+            }
+        }
+    }
+
+    // Every data packet sent needs a notify entry, including ones without ghosts
+    pub fn push_empty_notify(&mut self) {
+        self.notify_queue.push_back(vec![]);
+    }
+
+    // NetConnection::ghostWritePacket, goes at the end of the data packet. Ghosts are
+    // written until one goes past max_bits, which is taken back out of the stream and
+    // left for a later packet. The packet is tracked even if this fails, since DNet
+    // will notify us about it either way.
+    pub fn write_packet(
+        &mut self,
+        stream: &mut BitStream,
+        max_bits: usize,
+        registry: &NetClassRegistry,
+        group: u32,
+    ) -> Result<()> {
+        let mut packet_refs = vec![];
+        let result = self.write_ghosts(stream, max_bits, registry, group, &mut packet_refs);
+        self.notify_queue.push_back(packet_refs);
+        result
+    }
+
+    fn write_ghosts(
+        &mut self,
+        stream: &mut BitStream,
+        max_bits: usize,
+        registry: &NetClassRegistry,
+        group: u32,
+        packet_refs: &mut Vec<GhostRef>,
+    ) -> Result<()> {
+        if !stream.write_flag(self.ghosting) {
+            return Ok(());
+        }
+
+        // Anything with nothing to send can be ignored
+        let mut max_index = 0;
+        let mut update_list = vec![];
+        for index in 0..self.ghosts.len() as u32 {
+            let ghost = match self.ghosts[index as usize].as_mut() {
+                Some(ghost) => ghost,
+                None => continue,
+            };
+            if ghost.update_mask == 0 && ghost.flags & KILL_GHOST == 0 {
+                continue;
+            }
+
+            ghost.update_skip_count += 1;
+            max_index = max_index.max(index);
+
+            // Never made it to the client, so there's nothing to delete
+            if ghost.flags & KILL_GHOST != 0 && ghost.flags & NOT_YET_GHOSTED != 0 {
+                self.free_ghost(index);
+                continue;
+            }
+            // Wait until the client has it / got rid of it before doing anything else
+            if ghost.flags & (KILLING_GHOST | GHOSTING) != 0 {
+                continue;
+            }
+
+            let priority = if ghost.flags & KILL_GHOST != 0 {
+                KILL_GHOST_PRIORITY
+            } else {
+                ghost
+                    .object
+                    .lock()
+                    .map_err(|_| DnetError::PoisonedGhost(ghost.object_id))?
+                    .update_priority(ghost.update_mask, ghost.update_skip_count)
+            };
+            update_list.push((index, priority));
+        }
+
+        // Highest priority first
+        update_list.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut send_size = 1;
+        while max_index >> send_size != 0 {
+            send_size += 1;
+        }
+        let send_size = send_size.max(3);
+        stream.write_int(send_size as u32 - 3, GHOST_INDEX_BIT_SIZE);
+
+        // Room for the flag that ends the list
+        let fits = |stream: &BitStream| !stream.is_overflowed() && stream.get_bit_pos() < max_bits;
+
+        for (index, _) in update_list {
+            let start = stream.get_bit_pos();
+            let ghost = self.ghosts[index as usize].as_mut().unwrap();
+
+            if ghost.flags & KILL_GHOST != 0 {
+                stream.write_flag(true);
+                stream.write_int(index, send_size);
+                stream.write_flag(true);
+                if !fits(stream) {
+                    stream.rewind(start);
+                    break;
+                }
+
+                ghost.flags &= !KILL_GHOST;
+                ghost.flags |= KILLING_GHOST;
+                packet_refs.push(GhostRef {
+                    index,
+                    generation: ghost.generation,
+                    mask: ghost.update_mask,
+                    ghost_info_flags: KILLING_GHOST,
+                });
+                ghost.update_mask = 0;
+                continue;
+            }
+
+            let mut object = ghost
+                .object
+                .lock()
+                .map_err(|_| DnetError::PoisonedGhost(ghost.object_id))?;
+
+            // Look this up before writing anything so a bad class doesn't leave half a ghost
+            let class_id = if ghost.flags & NOT_YET_GHOSTED != 0 {
+                let class_name = object.class_name();
+                Some(
+                    registry
+                        .class_id(group, NetClassTypes::NetClassTypeObject, class_name)
+                        .ok_or_else(|| DnetError::UnregisteredClass(class_name.to_string()))?,
+                )
+            } else {
+                None
+            };
+
+            stream.write_flag(true);
+            stream.write_int(index, send_size);
+            stream.write_flag(false);
+            if let Some(class_id) = class_id {
+                registry.write_class_id(stream, class_id, NetClassTypes::NetClassTypeObject, group);
+            }
+
+            // Objects can't add new bits to their mask by packing
+            let update_mask = ghost.update_mask;
+            let ret_mask = object.pack_update(update_mask, stream) & update_mask;
+            drop(object);
+
+            // Didn't fit, so it goes out with the same mask next time
+            if !fits(stream) {
+                stream.rewind(start);
+                break;
+            }
+
+            let mut ghost_info_flags = 0;
+            if class_id.is_some() {
+                ghost.flags &= !NOT_YET_GHOSTED;
+                ghost.flags |= GHOSTING;
+                ghost_info_flags = GHOSTING;
+            }
+            ghost.update_mask = ret_mask;
+            ghost.update_skip_count = 0;
+            packet_refs.push(GhostRef {
+                index,
+                generation: ghost.generation,
+                mask: update_mask & !ret_mask,
+                ghost_info_flags,
+            });
+        }
+        stream.write_flag(false);
+        Ok(())
+    }
+
+    // Call once for each DNetResult::HandleNotify, alongside the event manager
+    pub fn handle_notify(&mut self, recvd: bool) {
+        let refs = match self.notify_queue.pop_front() {
+            Some(refs) => refs,
+            None => return,
+        };
+
+        if recvd {
+            self.packet_received(refs);
+        } else {
+            self.packet_dropped(refs);
+        }
+    }
+
+    fn packet_received(&mut self, refs: Vec<GhostRef>) {
+        for packet_ref in refs {
+            let ghost = match self.ghost_for_ref(&packet_ref) {
+                Some(ghost) => ghost,
+                None => continue,
+            };
+
+            if packet_ref.ghost_info_flags & GHOSTING != 0 {
+                ghost.flags &= !GHOSTING;
+            } else if packet_ref.ghost_info_flags & KILLING_GHOST != 0 {
+                self.free_ghost(packet_ref.index);
+            }
+        }
+    }
+
+    fn packet_dropped(&mut self, refs: Vec<GhostRef>) {
+        for packet_ref in refs {
+            // Anything sent again in a later packet doesn't need resending
+            let mut update_flags = packet_ref.mask;
+            for later in self.notify_queue.iter().flatten() {
+                if later.index == packet_ref.index && later.generation == packet_ref.generation {
+                    update_flags &= !later.mask;
+                }
+            }
+
+            let ghost = match self.ghost_for_ref(&packet_ref) {
+                Some(ghost) => ghost,
+                None => continue,
+            };
+            ghost.update_mask |= update_flags;
+
+            if packet_ref.ghost_info_flags & GHOSTING != 0 {
+                ghost.flags |= NOT_YET_GHOSTED;
+                ghost.flags &= !GHOSTING;
+            } else if packet_ref.ghost_info_flags & KILLING_GHOST != 0 {
+                ghost.flags |= KILL_GHOST;
+                ghost.flags &= !KILLING_GHOST;
+            }
+        }
+    }
+
+    fn ghost_for_ref(&mut self, packet_ref: &GhostRef) -> Option<&mut GhostInfo> {
+        self.ghosts[packet_ref.index as usize]
+            .as_mut()
+            .filter(|ghost| ghost.generation == packet_ref.generation)
+    }
+}
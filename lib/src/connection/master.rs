@@ -1,23 +1,30 @@
-use crate::packet::Packet;
+use super::master_query::{ServerQuery, ALL_LIST_PAGES};
+use crate::error::{DnetError, Result};
+use crate::logging::{HexDump, HEX_DUMP_TARGET};
+use crate::packet::{Packet, MAX_PACKET_DATA_SIZE};
 use crate::BitStream;
 use crate::PacketSource::GameToMaster;
-use anyhow::{anyhow, Error, Result};
+use std::collections::HashSet;
 use std::net::Ipv4Addr;
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
 use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
 use tokio::task;
-use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, info_span, trace, warn, Instrument};
+
+// gMasterServerTimeout / gMasterServerRetryCount in Torque's serverQuery.cpp
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_millis(2000);
+pub const DEFAULT_QUERY_RETRY_COUNT: u32 = 3;
 
 pub struct MasterServer {
     tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
     rx: tokio::sync::broadcast::Receiver<Packet>,
-    tx_thread: JoinHandle<Result<()>>,
-    rx_thread: JoinHandle<Result<()>>,
-    ids: Arc<Mutex<Box<dyn Iterator<Item = usize>>>>,
+    ids: Arc<Mutex<Box<dyn Iterator<Item = usize> + Send>>>,
+    query_timeout: Duration,
+    query_retry_count: u32,
 }
 
 impl MasterServer {
@@ -32,6 +39,8 @@ impl MasterServer {
         let socket = UdpSocket::from_std(std_socket)?;
         socket.connect(connect_address).await?;
 
+        let span = info_span!("master_server", remote = %socket.peer_addr()?);
+
         // Turn the socket into an Arc so that we can send it to both tasks
         let socket = Arc::new(socket);
         let tx_socket = socket.clone();
@@ -44,121 +53,242 @@ impl MasterServer {
         // self.rx
         let (rx_tx, rx_rx) = tokio::sync::broadcast::channel::<Packet>(16);
 
-        let tx_thread = tokio::spawn(async move {
-            loop {
-                if let Some(packet) = tx_rx.recv().await {
-                    println!(">>> {:?}", &packet);
-                    tx_socket.send(packet.as_slice()).await?;
-                } else {
-                    break;
+        tokio::spawn(
+            async move {
+                while let Some(packet) = tx_rx.recv().await {
+                    trace!(target: HEX_DUMP_TARGET, ">>> {} bytes\n{}", packet.len(), HexDump(&packet));
+                    // The same port unreachables can turn up on a send instead
+                    if let Err(error) = tx_socket.send(packet.as_slice()).await {
+                        debug!(%error, "Send failed");
+                    }
                 }
             }
-            Ok(())
-        });
-
-        let rx_thread = tokio::spawn(async move {
-            loop {
-                let mut buf: [u8; 1400] = [0; 1400];
-                let len = rx_socket.recv(&mut buf).await?;
-                println!("<<< {:?}", &buf[0..len]);
-                if let Some(packet) = Packet::try_from_bytes(&buf[0..len], GameToMaster) {
-                    println!("<<< {:#?}", &packet);
-                    rx_tx.send(packet)?;
+            .instrument(span.clone()),
+        );
+
+        tokio::spawn(
+            async move {
+                loop {
+                    let mut buf: [u8; MAX_PACKET_DATA_SIZE] = [0; MAX_PACKET_DATA_SIZE];
+                    // The socket is connected, so ICMP port unreachables from a master
+                    // that isn't up come back as errors here. Queries will time out on
+                    // their own, so keep going for when it is.
+                    let len = match rx_socket.recv(&mut buf).await {
+                        Ok(len) => len,
+                        Err(error) => {
+                            debug!(%error, "Recv failed");
+                            continue;
+                        }
+                    };
+                    trace!(target: HEX_DUMP_TARGET, "<<< {} bytes\n{}", len, HexDump(&buf[0..len]));
+                    match Packet::parse_bytes(&buf[0..len], GameToMaster) {
+                        Ok(packet) => {
+                            debug!(?packet, "Recv");
+                            // Nobody is listening, so the MasterServer is gone
+                            if rx_tx.send(packet).is_err() {
+                                break;
+                            }
+                        }
+                        Err(error) => debug!(%error, "Dropping malformed packet"),
+                    }
                 }
             }
-        });
+            .instrument(span),
+        );
 
         let connection = MasterServer {
             tx: tx_tx,
             rx: rx_rx,
-            tx_thread,
-            rx_thread,
             ids: Arc::new(Mutex::new(Box::new(
                 (0usize..).into_iter().map(|i| (i & 0xFFF) + 0x1000),
             ))),
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            query_retry_count: DEFAULT_QUERY_RETRY_COUNT,
         };
 
         Ok(connection)
     }
 
     pub async fn send_packet(&self, packet: Packet) -> Result<()> {
-        let bytes = packet.into_bytes();
+        let bytes = packet.try_into_bytes()?;
         self.tx.send(bytes)?;
         Ok(())
     }
 
     pub async fn send_raw(&self, stream: BitStream) -> Result<()> {
-        let bytes = stream.into_bytes();
-        println!(">>> {:?}", &bytes);
+        let bytes = stream.try_into_bytes()?;
         self.tx.send(bytes)?;
         Ok(())
     }
 
-    pub async fn query_servers(
-        &self,
-        flags: u8,
-        game_type: String,
-        mission_type: String,
-        min_players: u8,
-        max_players: u8,
-        region_mask: u32,
-        version: u32,
-        filter_flag: u8,
-        max_bots: u8,
-        min_cpu: u16,
-        buddy_list: Vec<u32>,
-    ) -> Result<Vec<(Ipv4Addr, u16)>> {
+    // How long to wait for a response before asking again, and how many times to ask
+    // again before giving up
+    pub fn set_query_timeout(&mut self, query_timeout: Duration, query_retry_count: u32) {
+        self.query_timeout = query_timeout;
+        self.query_retry_count = query_retry_count;
+    }
+
+    // Like Torque's processMasterServerQuery: pages that don't arrive are asked for again
+    // by index, and the whole query is sent again if nothing comes back at all. Once the
+    // retries run out, this returns the servers from the pages that did arrive, or
+    // QueryTimedOut if none of them did.
+    pub async fn query_servers(&self, query: ServerQuery) -> Result<Vec<(Ipv4Addr, u16)>> {
         let mut rx = self.rx.resubscribe();
         let tx = self.tx.clone();
-        let key = self.ids.lock().await.next().expect("never ends") as u16;
-        let session = self.ids.lock().await.next().expect("never ends") as u16;
+        let (key, session) = self.next_key_session().await;
+        let query_timeout = self.query_timeout;
+        let query_retry_count = self.query_retry_count;
 
         task::spawn(async move {
             tx.send(
-                Packet::MasterServerListRequest {
-                    flags,
-                    key,
-                    session,
-                    packet_index: 0,
-                    game_type,
-                    mission_type,
-                    min_players,
-                    max_players,
-                    region_mask,
-                    version,
-                    filter_flag,
-                    max_bots,
-                    min_cpu,
-                    buddy_list,
-                }
-                .into_bytes(),
+                query
+                    .to_request(key, session, ALL_LIST_PAGES)
+                    .try_into_bytes()?,
             )?;
 
+            // Empty until the first page says how many there are
+            let mut received_pages: Vec<bool> = vec![];
+            // Servers can move between pages while we're asking for them
             let mut found_servers = vec![];
+            let mut seen_servers = HashSet::new();
+            let mut retries = 0;
+            let mut deadline = Instant::now() + query_timeout;
+
+            while received_pages.is_empty() || received_pages.contains(&false) {
+                let packet = match timeout_at(deadline, rx.recv()).await {
+                    Ok(Ok(packet)) => packet,
+                    Ok(Err(RecvError::Lagged(count))) => {
+                        debug!(count, "Missed packets, will ask for them again");
+                        continue;
+                    }
+                    Ok(Err(RecvError::Closed)) => return Err(DnetError::ChannelClosed),
+                    Err(_) => {
+                        if retries >= query_retry_count {
+                            if received_pages.is_empty() {
+                                return Err(DnetError::QueryTimedOut);
+                            }
+                            // Torque shows whatever it got too
+                            let missing_pages = received_pages
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, received)| !**received)
+                                .map(|(index, _)| index)
+                                .collect::<Vec<_>>();
+                            warn!(
+                                ?missing_pages,
+                                total = received_pages.len(),
+                                "Giving up on missing pages"
+                            );
+                            break;
+                        }
+                        retries += 1;
+
+                        if received_pages.is_empty() {
+                            debug!(retries, "No response, sending query again");
+                            tx.send(
+                                query
+                                    .to_request(key, session, ALL_LIST_PAGES)
+                                    .try_into_bytes()?,
+                            )?;
+                        } else {
+                            for (index, _) in received_pages
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, received)| !**received)
+                            {
+                                debug!(retries, index, "Asking for missing page");
+                                tx.send(
+                                    query
+                                        .to_request(key, session, index as u8)
+                                        .try_into_bytes()?,
+                                )?;
+                            }
+                        }
+                        deadline = Instant::now() + query_timeout;
+                        continue;
+                    }
+                };
 
-            loop {
-                let packet = rx.recv().await?;
                 match packet {
                     Packet::MasterServerListResponse {
-                        flags,
                         key: response_key,
                         session: response_session,
                         packet_index,
                         packet_total,
                         servers,
+                        ..
                     } if response_key == key && response_session == session => {
-                        found_servers.extend(servers.into_iter());
-                        if packet_index + 1 >= packet_total {
-                            break;
+                        if received_pages.is_empty() {
+                            received_pages = vec![false; (packet_total as usize).max(1)];
+                        }
+                        match received_pages.get_mut(packet_index as usize) {
+                            Some(received) if !*received => *received = true,
+                            _ => continue,
+                        }
+
+                        for server in servers {
+                            if seen_servers.insert(server) {
+                                found_servers.push(server);
+                            }
                         }
+                        deadline = Instant::now() + query_timeout;
                     }
                     _ => {
                         continue;
                     }
                 }
             }
-            return Ok(found_servers);
+            Ok(found_servers)
         })
         .await?
     }
+
+    // Every game type and mission type the master has servers for
+    pub async fn query_game_types(&self) -> Result<(Vec<String>, Vec<String>)> {
+        let mut rx = self.rx.resubscribe();
+        let (key, session) = self.next_key_session().await;
+        let request = Packet::MasterServerGameTypesRequest {
+            flags: 0,
+            key,
+            session,
+        };
+
+        self.send_packet(request.clone()).await?;
+        let mut retries = 0;
+        let mut deadline = Instant::now() + self.query_timeout;
+
+        loop {
+            match timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(Packet::MasterServerGameTypesResponse {
+                    key: response_key,
+                    session: response_session,
+                    game_types,
+                    mission_types,
+                    ..
+                })) if response_key == key && response_session == session => {
+                    return Ok((game_types, mission_types));
+                }
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => {
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) => return Err(DnetError::ChannelClosed),
+                Err(_) => {
+                    if retries >= self.query_retry_count {
+                        return Err(DnetError::QueryTimedOut);
+                    }
+                    retries += 1;
+                    debug!(retries, "No response, sending query again");
+                    self.send_packet(request.clone()).await?;
+                    deadline = Instant::now() + self.query_timeout;
+                }
+            }
+        }
+    }
+
+    async fn next_key_session(&self) -> (u16, u16) {
+        let mut ids = self.ids.lock().await;
+        let key = ids.next().expect("never ends") as u16;
+        let session = ids.next().expect("never ends") as u16;
+        (key, session)
+    }
 }
@@ -0,0 +1,363 @@
+use super::master_query::{
+    ServerMasterInfo, ServerQuery, ALL_LIST_PAGES, MAX_SERVERS_PER_LIST_PAGE,
+};
+use crate::error::Result;
+use crate::logging::{HexDump, HEX_DUMP_TARGET};
+use crate::packet::{Packet, MAX_PACKET_DATA_SIZE};
+use crate::PacketSource::GameToMaster;
+use std::collections::{BTreeSet, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+
+// Torque servers heartbeat every two minutes, so this allows for one going missing
+pub const DEFAULT_SERVER_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[derive(Debug, Clone)]
+pub struct ListedServer {
+    pub address: (Ipv4Addr, u16),
+    pub last_heartbeat: Instant,
+    // None until the server answers our GameMasterInfoRequest, and not listed until then
+    pub info: Option<ServerMasterInfo>,
+    // Key and session of the last GameMasterInfoRequest we sent it
+    info_request: (u16, u16),
+}
+
+struct ServerList {
+    servers: HashMap<(Ipv4Addr, u16), ListedServer>,
+    server_timeout: Duration,
+}
+
+impl ServerList {
+    fn new(server_timeout: Duration) -> Self {
+        ServerList {
+            servers: HashMap::new(),
+            server_timeout,
+        }
+    }
+
+    fn expire(&mut self) {
+        let server_timeout = self.server_timeout;
+        self.servers.retain(|address, server| {
+            let alive = server.last_heartbeat.elapsed() < server_timeout;
+            if !alive {
+                info!(address = ?address, "Server expired");
+            }
+            alive
+        });
+    }
+
+    // Returns the key and session to poll the server with
+    fn heartbeat(&mut self, address: (Ipv4Addr, u16)) -> (u16, u16) {
+        let info_request = (rand::random(), rand::random());
+        let server = self.servers.entry(address).or_insert_with(|| {
+            info!(?address, "New server");
+            ListedServer {
+                address,
+                last_heartbeat: Instant::now(),
+                info: None,
+                info_request,
+            }
+        });
+        server.last_heartbeat = Instant::now();
+        server.info_request = info_request;
+        info_request
+    }
+
+    fn update_info(
+        &mut self,
+        address: (Ipv4Addr, u16),
+        key: u16,
+        session: u16,
+        info: ServerMasterInfo,
+    ) {
+        match self.servers.get_mut(&address) {
+            Some(server) if server.info_request == (key, session) => {
+                server.info = Some(info);
+            }
+            Some(_) => debug!(?address, "Info response doesn't match our request"),
+            None => debug!(
+                ?address,
+                "Info response from a server that hasn't sent a heartbeat"
+            ),
+        }
+    }
+
+    // Sorted by address so asking for the same page again gets the same servers.
+    // Always at least one page, so an empty list still gets a response.
+    fn list_pages(&mut self, query: &ServerQuery) -> Vec<Vec<(Ipv4Addr, u16)>> {
+        self.expire();
+        let matching = self
+            .servers
+            .values()
+            .filter(|server| server.info.as_ref().is_some_and(|info| query.matches(info)))
+            .map(|server| server.address)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut pages = matching
+            .chunks(MAX_SERVERS_PER_LIST_PAGE)
+            .map(|page| page.to_vec())
+            .collect::<Vec<_>>();
+        if pages.is_empty() {
+            pages.push(vec![]);
+        }
+
+        // packet_total is a u8 and ALL_LIST_PAGES can't be a page index
+        if pages.len() > ALL_LIST_PAGES as usize {
+            warn!(
+                pages = pages.len(),
+                "Too many servers to list, dropping some"
+            );
+            pages.truncate(ALL_LIST_PAGES as usize);
+        }
+        pages
+    }
+
+    fn game_types(&mut self) -> (Vec<String>, Vec<String>) {
+        self.expire();
+        let mut game_types = BTreeSet::new();
+        let mut mission_types = BTreeSet::new();
+        for info in self
+            .servers
+            .values()
+            .filter_map(|server| server.info.as_ref())
+        {
+            game_types.insert(info.game_type.clone());
+            mission_types.insert(info.mission_type.clone());
+        }
+        // Both lists are sent with a u8 length
+        (
+            game_types.into_iter().take(0xFF).collect(),
+            mission_types.into_iter().take(0xFF).collect(),
+        )
+    }
+}
+
+// A master server for game servers to heartbeat to and clients to get server lists from
+pub struct MasterServerHost {
+    local_addr: SocketAddr,
+    servers: Arc<Mutex<ServerList>>,
+    rx_thread: JoinHandle<Result<()>>,
+}
+
+impl MasterServerHost {
+    pub async fn bind<A: ToSocketAddrs>(bind_address: A) -> Result<Self> {
+        Self::bind_with_timeout(bind_address, DEFAULT_SERVER_TIMEOUT).await
+    }
+
+    // Servers that haven't sent a heartbeat in server_timeout are dropped from the list
+    pub async fn bind_with_timeout<A: ToSocketAddrs>(
+        bind_address: A,
+        server_timeout: Duration,
+    ) -> Result<Self> {
+        let socket = UdpSocket::bind(bind_address).await?;
+        let local_addr = socket.local_addr()?;
+        let span = info_span!("master_server_host", local = %local_addr);
+
+        let servers = Arc::new(Mutex::new(ServerList::new(server_timeout)));
+        let rx_servers = servers.clone();
+
+        let rx_thread = tokio::spawn(
+            async move {
+                loop {
+                    let mut buf: [u8; MAX_PACKET_DATA_SIZE] = [0; MAX_PACKET_DATA_SIZE];
+                    // Errors here are usually ICMP port unreachables from a client
+                    // that went away, which shouldn't take the whole list down
+                    let (len, from) = match socket.recv_from(&mut buf).await {
+                        Ok(result) => result,
+                        Err(error) => {
+                            debug!(%error, "Recv failed");
+                            continue;
+                        }
+                    };
+                    trace!(target: HEX_DUMP_TARGET, "<<< {} bytes from {}\n{}", len, from, HexDump(&buf[0..len]));
+
+                    let from = match from {
+                        SocketAddr::V4(from) => (*from.ip(), from.port()),
+                        SocketAddr::V6(_) => {
+                            debug!(%from, "Dropping packet from IPv6 address");
+                            continue;
+                        }
+                    };
+                    let packet = match Packet::parse_bytes(&buf[0..len], GameToMaster) {
+                        Ok(packet) => packet,
+                        Err(error) => {
+                            debug!(%error, ?from, "Dropping malformed packet");
+                            continue;
+                        }
+                    };
+                    debug!(?packet, ?from, "Recv");
+
+                    let responses = Self::handle_packet(&rx_servers, from, packet).await;
+                    for response in responses {
+                        Self::send_to(&socket, from, response).await;
+                    }
+                }
+            }
+            .instrument(span),
+        );
+
+        Ok(MasterServerHost {
+            local_addr,
+            servers,
+            rx_thread,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Every server that has heartbeated recently, including ones that haven't sent
+    // their info yet
+    pub async fn servers(&self) -> Vec<ListedServer> {
+        let mut servers = self.servers.lock().await;
+        servers.expire();
+        servers.servers.values().cloned().collect()
+    }
+
+    // Runs until the task panics or is aborted
+    pub async fn join(&mut self) -> Result<()> {
+        (&mut self.rx_thread).await?
+    }
+
+    async fn handle_packet(
+        servers: &Mutex<ServerList>,
+        from: (Ipv4Addr, u16),
+        packet: Packet,
+    ) -> Vec<Packet> {
+        match packet {
+            Packet::GameHeartbeat { .. } => {
+                let (key, session) = servers.lock().await.heartbeat(from);
+                vec![Packet::GameMasterInfoRequest {
+                    flags: 0,
+                    key,
+                    session,
+                }]
+            }
+            Packet::GameMasterInfoResponse {
+                key,
+                session,
+                game_type,
+                mission_type,
+                max_players,
+                region_mask,
+                version,
+                filter_flag,
+                bot_count,
+                cpu_speed,
+                player_count,
+                guid_list,
+                ..
+            } => {
+                let info = ServerMasterInfo {
+                    game_type,
+                    mission_type,
+                    max_players,
+                    region_mask,
+                    version,
+                    filter_flag,
+                    bot_count,
+                    cpu_speed,
+                    player_count,
+                    guid_list,
+                };
+                servers.lock().await.update_info(from, key, session, info);
+                vec![]
+            }
+            Packet::MasterServerListRequest {
+                flags,
+                key,
+                session,
+                packet_index,
+                game_type,
+                mission_type,
+                min_players,
+                max_players,
+                region_mask,
+                version,
+                filter_flag,
+                max_bots,
+                min_cpu,
+                buddy_list,
+            } => {
+                let query = ServerQuery {
+                    flags,
+                    game_type,
+                    mission_type,
+                    min_players,
+                    max_players,
+                    region_mask,
+                    version,
+                    filter_flag,
+                    max_bots,
+                    min_cpu,
+                    buddy_list,
+                };
+                let pages = servers.lock().await.list_pages(&query);
+                let packet_total = pages.len() as u8;
+
+                pages
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| {
+                        packet_index == ALL_LIST_PAGES || *index == packet_index as usize
+                    })
+                    .map(|(index, servers)| Packet::MasterServerListResponse {
+                        flags,
+                        key,
+                        session,
+                        packet_index: index as u8,
+                        packet_total,
+                        servers,
+                    })
+                    .collect()
+            }
+            Packet::MasterServerGameTypesRequest {
+                flags,
+                key,
+                session,
+            } => {
+                let (game_types, mission_types) = servers.lock().await.game_types();
+                vec![Packet::MasterServerGameTypesResponse {
+                    flags,
+                    key,
+                    session,
+                    game_types,
+                    mission_types,
+                }]
+            }
+            _ => {
+                debug!(?from, "Ignoring packet");
+                vec![]
+            }
+        }
+    }
+
+    async fn send_to(socket: &UdpSocket, to: (Ipv4Addr, u16), packet: Packet) {
+        debug!(?packet, ?to, "Send");
+        let bytes = match packet.try_into_bytes() {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                warn!(%error, ?to, "Couldn't encode response");
+                return;
+            }
+        };
+        trace!(target: HEX_DUMP_TARGET, ">>> {} bytes to {:?}\n{}", bytes.len(), to, HexDump(&bytes));
+        if let Err(error) = socket.send_to(&bytes, to).await {
+            debug!(%error, ?to, "Send failed");
+        }
+    }
+}
+
+impl Drop for MasterServerHost {
+    fn drop(&mut self) {
+        self.rx_thread.abort();
+    }
+}
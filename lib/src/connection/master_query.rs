@@ -0,0 +1,189 @@
+use crate::packet::{FilterFlags, Packet, QueryFlags, MAX_PACKET_DATA_SIZE};
+
+// What the master server client (master.rs) and host (master_host.rs) both need to
+// know about server lists
+
+// A MasterServerListResponse is 10 bytes of header, then 6 bytes per server
+pub const MAX_SERVERS_PER_LIST_PAGE: usize = (MAX_PACKET_DATA_SIZE - 10) / 6;
+
+// packet_index in a MasterServerListRequest that asks for every page at once
+pub const ALL_LIST_PAGES: u8 = 0xFF;
+
+// What a server said about itself in its last GameMasterInfoResponse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerMasterInfo {
+    pub game_type: String,
+    pub mission_type: String,
+    pub max_players: u8,
+    pub region_mask: u32,
+    pub version: u32,
+    pub filter_flag: u8,
+    pub bot_count: u8,
+    pub cpu_speed: u32,
+    pub player_count: u8,
+    pub guid_list: Vec<u32>,
+}
+
+// The filters for a server list query. Defaults to every server, and can be narrowed
+// down with the setters:
+//     ServerQuery::new().game_type("Test").min_players(1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerQuery {
+    pub flags: u8,
+    // "any" matches every type
+    pub game_type: String,
+    pub mission_type: String,
+    pub min_players: u8,
+    pub max_players: u8,
+    // Servers in any of these regions
+    pub region_mask: u32,
+    // Only checked with FilterFlags::CurrentVersion
+    pub version: u32,
+    // FilterFlags the servers need to have
+    pub filter_flag: u8,
+    pub max_bots: u8,
+    pub min_cpu: u16,
+    // If not empty, only servers with one of these players on them
+    pub buddy_list: Vec<u32>,
+}
+
+impl Default for ServerQuery {
+    fn default() -> Self {
+        ServerQuery {
+            flags: QueryFlags::OnlineQuery,
+            game_type: "any".to_string(),
+            mission_type: "any".to_string(),
+            min_players: 0,
+            max_players: u8::MAX,
+            region_mask: u32::MAX,
+            version: 0,
+            filter_flag: 0,
+            max_bots: u8::MAX,
+            min_cpu: 0,
+            buddy_list: vec![],
+        }
+    }
+}
+
+impl ServerQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn game_type<S: Into<String>>(mut self, game_type: S) -> Self {
+        self.game_type = game_type.into();
+        self
+    }
+
+    pub fn mission_type<S: Into<String>>(mut self, mission_type: S) -> Self {
+        self.mission_type = mission_type.into();
+        self
+    }
+
+    pub fn min_players(mut self, min_players: u8) -> Self {
+        self.min_players = min_players;
+        self
+    }
+
+    pub fn max_players(mut self, max_players: u8) -> Self {
+        self.max_players = max_players;
+        self
+    }
+
+    pub fn region_mask(mut self, region_mask: u32) -> Self {
+        self.region_mask = region_mask;
+        self
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn filter_flag(mut self, filter_flag: u8) -> Self {
+        self.filter_flag = filter_flag;
+        self
+    }
+
+    pub fn max_bots(mut self, max_bots: u8) -> Self {
+        self.max_bots = max_bots;
+        self
+    }
+
+    pub fn min_cpu(mut self, min_cpu: u16) -> Self {
+        self.min_cpu = min_cpu;
+        self
+    }
+
+    pub fn buddy_list(mut self, buddy_list: Vec<u32>) -> Self {
+        self.buddy_list = buddy_list;
+        self
+    }
+
+    pub fn to_request(&self, key: u16, session: u16, packet_index: u8) -> Packet {
+        Packet::MasterServerListRequest {
+            flags: self.flags,
+            key,
+            session,
+            packet_index,
+            game_type: self.game_type.clone(),
+            mission_type: self.mission_type.clone(),
+            min_players: self.min_players,
+            max_players: self.max_players,
+            region_mask: self.region_mask,
+            version: self.version,
+            filter_flag: self.filter_flag,
+            max_bots: self.max_bots,
+            min_cpu: self.min_cpu,
+            buddy_list: self.buddy_list.clone(),
+        }
+    }
+
+    // Same checks as Torque's server query does on the client
+    pub(crate) fn matches(&self, info: &ServerMasterInfo) -> bool {
+        let type_matches = |filter: &str, value: &str| {
+            filter.eq_ignore_ascii_case("any") || filter.eq_ignore_ascii_case(value)
+        };
+        if !type_matches(&self.game_type, &info.game_type)
+            || !type_matches(&self.mission_type, &info.mission_type)
+        {
+            return false;
+        }
+
+        if info.player_count < self.min_players || info.player_count > self.max_players {
+            return false;
+        }
+        if info.region_mask & self.region_mask == 0 {
+            return false;
+        }
+        if info.bot_count > self.max_bots || info.cpu_speed < self.min_cpu as u32 {
+            return false;
+        }
+
+        // CurrentVersion asks for an exact version, the rest have to be set on the server
+        if self.filter_flag & FilterFlags::CurrentVersion != 0 && info.version != self.version {
+            return false;
+        }
+        let required_flags = self.filter_flag & !FilterFlags::CurrentVersion;
+        if info.filter_flag & required_flags != required_flags {
+            return false;
+        }
+
+        // Only servers with one of our buddies on them
+        if !self.buddy_list.is_empty()
+            && !self
+                .buddy_list
+                .iter()
+                .any(|guid| info.guid_list.contains(guid))
+        {
+            return false;
+        }
+
+        true
+    }
+}
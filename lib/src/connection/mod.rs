@@ -1,6 +1,82 @@
 mod connection;
 mod dnet;
+mod event;
+mod ghost;
+mod ghost_manager;
 mod master;
+mod master_host;
+mod master_query;
+mod moves;
+mod net_class;
+mod net_event;
+mod net_object;
+mod net_string;
+mod packet_header;
+mod rate;
+mod remote_command;
+mod server_browser;
+mod stats;
 
 pub use connection::GameConnection;
+pub use dnet::DNet;
+pub use dnet::DNetResult;
+pub use dnet::DisconnectReason;
+pub use dnet::NetPacketType;
+pub use dnet::DEFAULT_PING_RETRY_COUNT;
+pub use dnet::DEFAULT_PING_TIMEOUT;
+pub use event::EventManager;
+pub use event::GuaranteeType;
+pub use ghost::GhostTable;
+pub use ghost::GHOST_ID_BIT_SIZE;
+pub use ghost::GHOST_INDEX_BIT_SIZE;
+pub use ghost::MAX_GHOST_COUNT;
+pub use ghost_manager::GhostManager;
+pub use ghost_manager::NetObjectRef;
 pub use master::MasterServer;
+pub use master::DEFAULT_QUERY_RETRY_COUNT;
+pub use master::DEFAULT_QUERY_TIMEOUT;
+pub use master_host::ListedServer;
+pub use master_host::MasterServerHost;
+pub use master_host::DEFAULT_SERVER_TIMEOUT;
+pub use master_query::ServerMasterInfo;
+pub use master_query::ServerQuery;
+pub use master_query::ALL_LIST_PAGES;
+pub use master_query::MAX_SERVERS_PER_LIST_PAGE;
+pub use moves::Move;
+pub use moves::MoveQueue;
+pub use moves::MAX_MOVE_COUNT;
+pub use moves::MAX_MOVE_QUEUE_SIZE;
+pub use moves::MAX_TRIGGER_KEYS;
+pub use net_class::calculate_crc;
+pub use net_class::NetClassFactory;
+pub use net_class::NetClassGroupMasks;
+pub use net_class::NetClassRegistry;
+pub use net_class::NetClassRep;
+pub use net_class::NetClassTypes;
+pub use net_event::NetEvent;
+pub use net_object::NetObject;
+pub use net_string::detag;
+pub use net_string::is_tagged_string;
+pub use net_string::make_tagged_string;
+pub use net_string::pack_string;
+pub use net_string::tag_id;
+pub use net_string::unpack_string;
+pub use net_string::NetStringEvent;
+pub use net_string::NetStringTable;
+pub use net_string::StringTagPrefixByte;
+pub use packet_header::ClientPacketHeader;
+pub use packet_header::ControlObjectUpdate;
+pub use packet_header::ControlScheme;
+pub use packet_header::GameConnectionPacketHeader;
+pub use packet_header::ServerPacketHeader;
+pub use rate::NetRate;
+pub use rate::RateControl;
+pub use remote_command::RemoteCommand;
+pub use remote_command::RemoteCommandEvent;
+pub use server_browser::ServerBrowser;
+pub use server_browser::ServerInfo;
+pub use server_browser::DEFAULT_BROWSER_RETRY_COUNT;
+pub use server_browser::DEFAULT_BROWSER_TIMEOUT;
+pub use server_browser::MAX_CONCURRENT_SERVER_QUERIES;
+pub use stats::ConnectionStats;
+pub use stats::LOSS_WINDOW_SIZE;
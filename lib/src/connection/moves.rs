@@ -0,0 +1,282 @@
+use crate::error::{DnetError, Result};
+use crate::packet::BitStream;
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+
+// MoveManager / GameConnection move constants
+pub const MAX_TRIGGER_KEYS: usize = 6;
+pub const MAX_MOVE_COUNT: usize = 30;
+pub const MAX_MOVE_QUEUE_SIZE: usize = 45;
+const MOVE_COUNT_BITS: usize = 5;
+
+// FANG2IANG / IANG2FANG, angles go over the wire as 16 bits
+fn angle_to_packed(angle: f32) -> u32 {
+    (((0x10000 as f32 / TAU) * angle) as i32 as i16 as u16) as u32
+}
+
+fn packed_to_angle(packed: u32) -> f32 {
+    (TAU / 0x10000 as f32) * (packed as u16 as i16) as f32
+}
+
+// -1..1 into 0..32
+fn clamp_range_clamp(value: f32) -> i32 {
+    if value < -1.0 {
+        return 0;
+    }
+    if value > 1.0 {
+        return 32;
+    }
+    ((value + 1.0) * 16.0) as i32
+}
+
+// Torque's Move. Set the float fields and call clamp() before packing, the packed
+// fields are what actually get sent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Move {
+    pub px: i32,
+    pub py: i32,
+    pub pz: i32,
+    pub pyaw: u32,
+    pub ppitch: u32,
+    pub proll: u32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    pub free_look: bool,
+    pub trigger: [bool; MAX_TRIGGER_KEYS],
+}
+
+// NullMove
+impl Default for Move {
+    fn default() -> Self {
+        Move {
+            px: 16,
+            py: 16,
+            pz: 16,
+            pyaw: 0,
+            ppitch: 0,
+            proll: 0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+            free_look: false,
+            trigger: [false; MAX_TRIGGER_KEYS],
+        }
+    }
+}
+
+impl Move {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clamp(&mut self) {
+        self.pyaw = angle_to_packed(self.yaw);
+        self.ppitch = angle_to_packed(self.pitch);
+        self.proll = angle_to_packed(self.roll);
+        self.px = clamp_range_clamp(self.x);
+        self.py = clamp_range_clamp(self.y);
+        self.pz = clamp_range_clamp(self.z);
+        self.unclamp();
+    }
+
+    pub fn unclamp(&mut self) {
+        self.yaw = packed_to_angle(self.pyaw);
+        self.pitch = packed_to_angle(self.ppitch);
+        self.roll = packed_to_angle(self.proll);
+        self.x = (self.px - 16) as f32 / 16.0;
+        self.y = (self.py - 16) as f32 / 16.0;
+        self.z = (self.pz - 16) as f32 / 16.0;
+    }
+
+    // Only the fields that differ from base are sent. Without a base, a single flag
+    // is used to say the move is the same as NullMove.
+    pub fn pack(&self, stream: &mut BitStream, base: Option<&Move>) {
+        let always_write_all = base.is_some();
+        let null_move = Move::default();
+        let base = base.unwrap_or(&null_move);
+
+        let trigger_different = self.trigger != base.trigger;
+        let something_different = self.pyaw != base.pyaw
+            || self.ppitch != base.ppitch
+            || self.proll != base.proll
+            || self.px != base.px
+            || self.py != base.py
+            || self.pz != base.pz
+            || self.free_look != base.free_look
+            || trigger_different;
+
+        if always_write_all || stream.write_flag(something_different) {
+            if stream.write_flag(self.pyaw != base.pyaw) {
+                stream.write_int(self.pyaw, 16);
+            }
+            if stream.write_flag(self.ppitch != base.ppitch) {
+                stream.write_int(self.ppitch, 16);
+            }
+            if stream.write_flag(self.proll != base.proll) {
+                stream.write_int(self.proll, 16);
+            }
+            if stream.write_flag(self.px != base.px) {
+                stream.write_int(self.px as u32, 6);
+            }
+            if stream.write_flag(self.py != base.py) {
+                stream.write_int(self.py as u32, 6);
+            }
+            if stream.write_flag(self.pz != base.pz) {
+                stream.write_int(self.pz as u32, 6);
+            }
+            stream.write_flag(self.free_look);
+            if stream.write_flag(trigger_different) {
+                for &trigger in &self.trigger {
+                    stream.write_flag(trigger);
+                }
+            }
+        }
+    }
+
+    pub fn unpack(&mut self, stream: &mut BitStream, base: Option<&Move>) -> Result<()> {
+        let always_read_all = base.is_some();
+        let null_move = Move::default();
+        let base = base.unwrap_or(&null_move);
+
+        if !(always_read_all || stream.read_flag()?) {
+            *self = *base;
+            return Ok(());
+        }
+
+        self.pyaw = if stream.read_flag()? {
+            stream.read_int(16)?
+        } else {
+            base.pyaw
+        };
+        self.ppitch = if stream.read_flag()? {
+            stream.read_int(16)?
+        } else {
+            base.ppitch
+        };
+        self.proll = if stream.read_flag()? {
+            stream.read_int(16)?
+        } else {
+            base.proll
+        };
+        self.px = if stream.read_flag()? {
+            stream.read_int(6)? as i32
+        } else {
+            base.px
+        };
+        self.py = if stream.read_flag()? {
+            stream.read_int(6)? as i32
+        } else {
+            base.py
+        };
+        self.pz = if stream.read_flag()? {
+            stream.read_int(6)? as i32
+        } else {
+            base.pz
+        };
+        self.free_look = stream.read_flag()?;
+        let triggers_differ = stream.read_flag()?;
+        for i in 0..MAX_TRIGGER_KEYS {
+            self.trigger[i] = if triggers_differ {
+                stream.read_flag()?
+            } else {
+                base.trigger[i]
+            };
+        }
+        self.unclamp();
+        Ok(())
+    }
+}
+
+// Client side mMoveList. Moves are resent every packet until the server acks them.
+#[derive(Debug)]
+pub struct MoveQueue {
+    moves: VecDeque<Move>,
+    // Index of the first move in the queue, counting every move ever sent
+    first_move_index: u32,
+}
+
+impl Default for MoveQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveQueue {
+    pub fn new() -> Self {
+        MoveQueue {
+            moves: VecDeque::new(),
+            first_move_index: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    pub fn first_move_index(&self) -> u32 {
+        self.first_move_index
+    }
+
+    // Returns false if the queue is full, in which case the move is dropped
+    pub fn push(&mut self, mut new_move: Move) -> bool {
+        if self.moves.len() >= MAX_MOVE_QUEUE_SIZE {
+            return false;
+        }
+        new_move.clamp();
+        self.moves.push_back(new_move);
+        true
+    }
+
+    // Server told us it has processed every move before last_move_ack
+    pub fn ack(&mut self, last_move_ack: u32) {
+        while self.first_move_index < last_move_ack && !self.moves.is_empty() {
+            self.moves.pop_front();
+            self.first_move_index += 1;
+        }
+    }
+
+    // What goes in the next packet, the oldest unacked moves
+    pub fn pending_moves(&self) -> Vec<Move> {
+        self.moves.iter().take(MAX_MOVE_COUNT).copied().collect()
+    }
+}
+
+// Each move is sent relative to the one before it
+pub(crate) fn write_moves(stream: &mut BitStream, first_move_index: u32, moves: &[Move]) {
+    let count = moves.len().min(MAX_MOVE_COUNT);
+    stream.write_u32(first_move_index);
+    stream.write_int(count as u32, MOVE_COUNT_BITS);
+
+    let mut prev_move = None;
+    for current in moves.iter().take(count) {
+        current.pack(stream, prev_move);
+        prev_move = Some(current);
+    }
+}
+
+pub(crate) fn read_moves(stream: &mut BitStream) -> Result<(u32, Vec<Move>)> {
+    let first_move_index = stream.read_u32()?;
+    let count = stream.read_int(MOVE_COUNT_BITS)? as usize;
+    if count > MAX_MOVE_COUNT {
+        return Err(DnetError::TooManyMoves(count));
+    }
+
+    let mut moves: Vec<Move> = vec![];
+    for _ in 0..count {
+        let mut current = Move::default();
+        current.unpack(stream, moves.last())?;
+        moves.push(current);
+    }
+    Ok((first_move_index, moves))
+}
@@ -0,0 +1,250 @@
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+
+use super::net_event::NetEvent;
+use super::net_object::NetObject;
+use crate::error::{DnetError, Result};
+use crate::packet::BitStream;
+use crate::NetClassGroups::NetClassGroupsCount;
+
+pub mod NetClassTypes {
+    pub const NetClassTypeObject: u32 = 0;
+    pub const NetClassTypeDataBlock: u32 = 1;
+    pub const NetClassTypeEvent: u32 = 2;
+    pub const NetClassTypesCount: u32 = 3;
+}
+
+pub mod NetClassGroupMasks {
+    pub const NetClassGroupGameMask: u32 = 1 << 0;
+    pub const NetClassGroupCommunityMask: u32 = 1 << 1;
+    pub const NetClassGroup3Mask: u32 = 1 << 2;
+    pub const NetClassGroup4Mask: u32 = 1 << 3;
+    pub const NetClassGroupAllMask: u32 = (1 << 4) - 1;
+}
+
+const INITIAL_CRC_VALUE: u32 = 0xFFFFFFFF;
+const CRC_POLYNOMIAL: u32 = 0xEDB88320;
+
+// Same as Torque's calculateCRC, which does not do the final xor
+pub fn calculate_crc(buffer: &[u8], mut crc: u32) -> u32 {
+    for &b in buffer {
+        let mut val = (crc ^ b as u32) & 0xFF;
+        for _ in 0..8 {
+            val = if val & 1 != 0 {
+                CRC_POLYNOMIAL ^ (val >> 1)
+            } else {
+                val >> 1
+            };
+        }
+        crc = val ^ (crc >> 8);
+    }
+    crc
+}
+
+#[derive(Clone)]
+pub enum NetClassFactory {
+    // Class exists on the other side and takes up an id, but we can't construct it
+    None,
+    Event(fn() -> Box<dyn NetEvent>),
+    Object(fn() -> Box<dyn NetObject>),
+}
+
+#[derive(Clone)]
+pub struct NetClassRep {
+    pub class_name: String,
+    pub class_type: u32,
+    pub class_group_mask: u32,
+    pub factory: NetClassFactory,
+}
+
+// AbstractClassRep's id tables. Classes are sorted by name (case insensitive) within
+// each group/type pair and their ids are their index, so every class the other side
+// knows about needs to be registered here or ids will not line up.
+#[derive(Clone)]
+pub struct NetClassRegistry {
+    classes: Vec<NetClassRep>,
+    class_table: Vec<Vec<Vec<usize>>>,
+    class_crc: Vec<u32>,
+}
+
+impl Default for NetClassRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetClassRegistry {
+    pub fn new() -> Self {
+        let mut registry = NetClassRegistry {
+            classes: vec![],
+            class_table: vec![],
+            class_crc: vec![],
+        };
+        registry.initialize();
+        registry
+    }
+
+    pub fn register_class(&mut self, class_name: &str, class_type: u32, class_group_mask: u32) {
+        self.register(NetClassRep {
+            class_name: class_name.to_string(),
+            class_type,
+            class_group_mask,
+            factory: NetClassFactory::None,
+        });
+    }
+
+    pub fn register_event(
+        &mut self,
+        class_name: &str,
+        class_group_mask: u32,
+        factory: fn() -> Box<dyn NetEvent>,
+    ) {
+        self.register(NetClassRep {
+            class_name: class_name.to_string(),
+            class_type: NetClassTypes::NetClassTypeEvent,
+            class_group_mask,
+            factory: NetClassFactory::Event(factory),
+        });
+    }
+
+    pub fn register_object(
+        &mut self,
+        class_name: &str,
+        class_group_mask: u32,
+        factory: fn() -> Box<dyn NetObject>,
+    ) {
+        self.register(NetClassRep {
+            class_name: class_name.to_string(),
+            class_type: NetClassTypes::NetClassTypeObject,
+            class_group_mask,
+            factory: NetClassFactory::Object(factory),
+        });
+    }
+
+    pub fn register(&mut self, rep: NetClassRep) {
+        // Re-registering replaces, so placeholders can be filled in later
+        self.classes.retain(|other| {
+            other.class_type != rep.class_type
+                || !other.class_name.eq_ignore_ascii_case(&rep.class_name)
+        });
+        self.classes.push(rep);
+        self.initialize();
+    }
+
+    fn initialize(&mut self) {
+        self.class_table.clear();
+        self.class_crc.clear();
+
+        for group in 0..NetClassGroupsCount {
+            let group_mask = 1 << group;
+            let mut crc = INITIAL_CRC_VALUE;
+            let mut group_table = vec![];
+
+            for class_type in 0..NetClassTypes::NetClassTypesCount {
+                let mut dynamic_table = self
+                    .classes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, rep)| {
+                        rep.class_type == class_type && (rep.class_group_mask & group_mask) != 0
+                    })
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+
+                // dStricmp
+                dynamic_table.sort_by(|&a, &b| {
+                    self.classes[a]
+                        .class_name
+                        .to_ascii_lowercase()
+                        .cmp(&self.classes[b].class_name.to_ascii_lowercase())
+                });
+
+                for &i in &dynamic_table {
+                    crc = calculate_crc(self.classes[i].class_name.as_bytes(), crc);
+                }
+
+                group_table.push(dynamic_table);
+            }
+
+            self.class_table.push(group_table);
+            self.class_crc.push(crc);
+        }
+    }
+
+    pub fn class_count(&self, group: u32, class_type: u32) -> u32 {
+        self.class_table[group as usize][class_type as usize].len() as u32
+    }
+
+    pub fn class_bit_size(&self, group: u32, class_type: u32) -> usize {
+        // getBinLog2(getNextPow2(count + 1))
+        (self.class_count(group, class_type) + 1)
+            .next_power_of_two()
+            .trailing_zeros() as usize
+    }
+
+    pub fn class_crc(&self, group: u32) -> u32 {
+        self.class_crc[group as usize]
+    }
+
+    // NetConnection::readConnectRequest
+    pub fn check_class_crc(&self, group: u32, class_crc: u32) -> bool {
+        group < NetClassGroupsCount && self.class_crc(group) == class_crc
+    }
+
+    pub fn class_id(&self, group: u32, class_type: u32, class_name: &str) -> Option<u32> {
+        self.class_table[group as usize][class_type as usize]
+            .iter()
+            .position(|&i| self.classes[i].class_name.eq_ignore_ascii_case(class_name))
+            .map(|id| id as u32)
+    }
+
+    pub fn class_rep(&self, group: u32, class_type: u32, class_id: u32) -> Option<&NetClassRep> {
+        self.class_table[group as usize][class_type as usize]
+            .get(class_id as usize)
+            .map(|&i| &self.classes[i])
+    }
+
+    pub fn write_class_id(
+        &self,
+        stream: &mut BitStream,
+        class_id: u32,
+        class_type: u32,
+        group: u32,
+    ) -> u32 {
+        stream.write_int(class_id, self.class_bit_size(group, class_type));
+        class_id
+    }
+
+    pub fn read_class_id(
+        &self,
+        stream: &mut BitStream,
+        class_type: u32,
+        group: u32,
+    ) -> Result<u32> {
+        let class_id = stream.read_int(self.class_bit_size(group, class_type))?;
+        if class_id >= self.class_count(group, class_type) {
+            return Err(DnetError::InvalidClassId(class_id));
+        }
+        Ok(class_id)
+    }
+
+    pub fn create_event(&self, group: u32, class_id: u32) -> Result<Box<dyn NetEvent>> {
+        let rep = self
+            .class_rep(group, NetClassTypes::NetClassTypeEvent, class_id)
+            .ok_or(DnetError::InvalidClassId(class_id))?;
+        match rep.factory {
+            NetClassFactory::Event(factory) => Ok(factory()),
+            _ => Err(DnetError::UnconstructableClass(rep.class_name.clone())),
+        }
+    }
+
+    pub fn create_object(&self, group: u32, class_id: u32) -> Result<Box<dyn NetObject>> {
+        let rep = self
+            .class_rep(group, NetClassTypes::NetClassTypeObject, class_id)
+            .ok_or(DnetError::InvalidClassId(class_id))?;
+        match rep.factory {
+            NetClassFactory::Object(factory) => Ok(factory()),
+            _ => Err(DnetError::UnconstructableClass(rep.class_name.clone())),
+        }
+    }
+}
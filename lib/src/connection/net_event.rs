@@ -0,0 +1,22 @@
+use super::connection::GameConnection;
+use super::event::GuaranteeType;
+use crate::error::Result;
+use crate::packet::BitStream;
+use std::fmt::Debug;
+
+pub trait NetEvent: Debug + Send {
+    // Must match the name registered in the NetClassRegistry
+    fn class_name(&self) -> &'static str;
+
+    fn guarantee_type(&self) -> GuaranteeType {
+        GuaranteeType::GuaranteedOrdered
+    }
+
+    fn pack(&self, stream: &mut BitStream);
+
+    fn unpack(&mut self, connection: &GameConnection, stream: &mut BitStream) -> Result<()>;
+
+    fn process(&mut self, connection: &mut GameConnection) -> Result<()>;
+
+    fn notify_delivered(&mut self, _connection: &mut GameConnection, _made_it: bool) {}
+}
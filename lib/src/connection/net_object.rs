@@ -0,0 +1,38 @@
+use super::connection::GameConnection;
+use crate::error::Result;
+use crate::packet::BitStream;
+use std::any::Any;
+use std::fmt::Debug;
+
+pub trait NetObject: Debug + Send {
+    // Must match the name registered in the NetClassRegistry
+    fn class_name(&self) -> &'static str;
+
+    // Write whatever parts of the object are in mask, returning the bits that still
+    // need to be sent in a later packet
+    fn pack_update(&mut self, _mask: u32, _stream: &mut BitStream) -> u32 {
+        0
+    }
+
+    // Server side, higher goes out first. Same as NetObject::getUpdatePriority
+    fn update_priority(&self, _update_mask: u32, update_skip_count: u32) -> f32 {
+        update_skip_count as f32 * 0.1
+    }
+
+    fn unpack_update(&mut self, connection: &GameConnection, stream: &mut BitStream) -> Result<()>;
+
+    // Extra state sent in the packet header when this is the client's control object
+    fn write_packet_data(&mut self, _stream: &mut BitStream) {}
+
+    fn read_packet_data(
+        &mut self,
+        _connection: &GameConnection,
+        _stream: &mut BitStream,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // So you can get your own type back out of the ghost table
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
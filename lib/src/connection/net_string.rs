@@ -0,0 +1,311 @@
+#![allow(non_upper_case_globals)]
+
+use super::connection::GameConnection;
+use super::net_event::NetEvent;
+use crate::error::Result;
+use crate::packet::BitStream;
+
+// Tagged strings look like "\x01123" where 123 is the tag id. Once expanded they
+// become "\x01123 text", keeping the tag in front.
+pub const StringTagPrefixByte: char = '\x01';
+
+// ConnectionStringTable::EntryCount / EntryBitSize
+pub const ENTRY_COUNT: usize = 32;
+pub const ENTRY_BIT_SIZE: usize = 5;
+
+// NetConnection::NetStringConstants
+const NullString: u32 = 0;
+const CString: u32 = 1;
+const TagString: u32 = 2;
+const Integer: u32 = 3;
+
+pub fn is_tagged_string(string: &str) -> bool {
+    string.starts_with(StringTagPrefixByte)
+}
+
+// Like dAtoi, just the digits after the prefix count
+pub fn tag_id(string: &str) -> Option<u32> {
+    if !is_tagged_string(string) {
+        return None;
+    }
+    let digits = string[1..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(&string[1..], |end| &string[1..end + 1]);
+    digits.parse::<u32>().ok()
+}
+
+// What comes after the tag in an expanded tagged string, like the detag() script
+// function. Anything else comes back unchanged.
+pub fn detag(string: &str) -> &str {
+    if !is_tagged_string(string) {
+        return string;
+    }
+    match string.find(' ') {
+        Some(space) => &string[space + 1..],
+        None => "",
+    }
+}
+
+pub fn make_tagged_string(id: u32) -> String {
+    format!("{}{}", StringTagPrefixByte, id)
+}
+
+// NetConnection::packString
+pub fn pack_string(stream: &mut BitStream, string: &str) {
+    if string.is_empty() {
+        stream.write_int(NullString, 2);
+        return;
+    }
+    if let Some(id) = tag_id(string) {
+        stream.write_int(TagString, 2);
+        stream.write_int(id, ENTRY_BIT_SIZE);
+        return;
+    }
+    if string.starts_with('-') || string.starts_with(|c: char| c.is_ascii_digit()) {
+        // Only if it survives a round trip through dAtoi/dSprintf
+        if let Ok(num) = string.parse::<i32>() {
+            if num.to_string() == string {
+                stream.write_int(Integer, 2);
+                let num = if stream.write_flag(num < 0) {
+                    num.unsigned_abs()
+                } else {
+                    num as u32
+                };
+                if stream.write_flag(num < 128) {
+                    stream.write_int(num, 7);
+                    return;
+                }
+                if stream.write_flag(num < 65536) {
+                    stream.write_int(num, 16);
+                    return;
+                }
+                stream.write_int(num, 32);
+                return;
+            }
+        }
+    }
+    stream.write_int(CString, 2);
+    stream.write_string(&string.to_string());
+}
+
+// NetConnection::unpackString
+pub fn unpack_string(stream: &mut BitStream) -> Result<String> {
+    let code = stream.read_int(2)?;
+    match code {
+        NullString => Ok(String::new()),
+        CString => stream.read_string(),
+        TagString => Ok(make_tagged_string(stream.read_int(ENTRY_BIT_SIZE)?)),
+        Integer => {
+            let neg = stream.read_flag()?;
+            let num = if stream.read_flag()? {
+                stream.read_int(7)?
+            } else if stream.read_flag()? {
+                stream.read_int(16)?
+            } else {
+                stream.read_int(32)?
+            };
+            if neg {
+                Ok((num as i32).wrapping_neg().to_string())
+            } else {
+                Ok((num as i32).to_string())
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LocalEntry {
+    string: String,
+    received: bool,
+    last_used: u64,
+}
+
+// Per-connection string table. Strings we send get ids that the other side learns
+// about through NetStringEvents, and strings the other side sends us get mapped the
+// same way in the other direction.
+#[derive(Debug, Clone)]
+pub struct NetStringTable {
+    local: Vec<Option<LocalEntry>>,
+    remote: Vec<Option<String>>,
+    use_counter: u64,
+}
+
+impl Default for NetStringTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetStringTable {
+    pub fn new() -> Self {
+        NetStringTable {
+            local: vec![None; ENTRY_COUNT],
+            remote: vec![None; ENTRY_COUNT],
+            use_counter: 0,
+        }
+    }
+
+    // ConnectionStringTable::checkString, returns the id and whether it is new and
+    // needs a NetStringEvent sent for it
+    pub fn check_string(&mut self, string: &str) -> (u32, bool) {
+        self.use_counter += 1;
+
+        if let Some(id) = self.local_id(string) {
+            self.local[id as usize].as_mut().unwrap().last_used = self.use_counter;
+            return (id, false);
+        }
+
+        // Take a free slot, or kick out whatever was used the longest time ago
+        let id = match self.local.iter().position(|entry| entry.is_none()) {
+            Some(id) => id,
+            None => self
+                .local
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.as_ref().unwrap().last_used)
+                .map(|(id, _)| id)
+                .unwrap(),
+        };
+
+        self.local[id] = Some(LocalEntry {
+            string: string.to_string(),
+            received: false,
+            last_used: self.use_counter,
+        });
+        (id as u32, true)
+    }
+
+    pub fn local_id(&self, string: &str) -> Option<u32> {
+        self.local
+            .iter()
+            .position(|entry| matches!(entry, Some(entry) if entry.string == string))
+            .map(|id| id as u32)
+    }
+
+    pub fn is_received(&self, id: u32) -> bool {
+        matches!(self.local.get(id as usize), Some(Some(entry)) if entry.received)
+    }
+
+    pub fn confirm_string_received(&mut self, string: &str, id: u32) {
+        if let Some(Some(entry)) = self.local.get_mut(id as usize) {
+            // The slot may have been reused since the event was sent
+            if entry.string == string {
+                entry.received = true;
+            }
+        }
+    }
+
+    // NetConnection::mapString, from an incoming NetStringEvent
+    pub fn map_string(&mut self, id: u32, string: String) {
+        if let Some(entry) = self.remote.get_mut(id as usize) {
+            *entry = Some(string);
+        }
+    }
+
+    pub fn lookup_string(&self, id: u32) -> Option<&str> {
+        self.remote
+            .get(id as usize)
+            .and_then(|entry| entry.as_deref())
+    }
+
+    // Turns "\x01<id>" into whatever the other side mapped it to, and "\x01<id> text"
+    // into just the text. Anything else, or tags we haven't heard about, come back
+    // unchanged.
+    pub fn resolve(&self, string: &str) -> String {
+        if string.contains(' ') {
+            return detag(string).to_string();
+        }
+        match tag_id(string).and_then(|id| self.lookup_string(id)) {
+            Some(resolved) => resolved.to_string(),
+            None => string.to_string(),
+        }
+    }
+
+    // NetStringTable::expandString, gives "\x01<id> " followed by the tagged string
+    // with %1 - %9 filled in from args. Tagged args go in without their tag.
+    pub fn expand(&self, string: &str, args: &[String]) -> String {
+        let id = match tag_id(string) {
+            Some(id) => id,
+            None => return string.to_string(),
+        };
+        let mut result = format!("{}{} ", StringTagPrefixByte, id);
+        let mut chars = self.lookup_string(id).unwrap_or("").chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                result.push(ch);
+                continue;
+            }
+            // Like Torque, the % is dropped even when it isn't followed by an arg number
+            match chars.next() {
+                Some(digit @ '1'..='9') => {
+                    if let Some(arg) = args.get(digit as usize - '1' as usize) {
+                        result.push_str(detag(arg));
+                    }
+                }
+                Some(other) => result.push(other),
+                None => break,
+            }
+        }
+        result
+    }
+
+    // NetConnection::unpackNetStringHandleU
+    pub fn unpack_handle(&self, stream: &mut BitStream) -> Result<Option<String>> {
+        if !stream.read_flag()? {
+            return Ok(None);
+        }
+        if stream.read_flag()? {
+            let id = stream.read_int(ENTRY_BIT_SIZE)?;
+            Ok(self.lookup_string(id).map(|string| string.to_string()))
+        } else {
+            Ok(Some(stream.read_string()?))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetStringEvent {
+    pub id: u32,
+    pub string: String,
+}
+
+impl NetStringEvent {
+    pub const CLASS_NAME: &'static str = "NetStringEvent";
+
+    pub fn create() -> Box<dyn NetEvent> {
+        Box::new(NetStringEvent::default())
+    }
+}
+
+impl NetEvent for NetStringEvent {
+    fn class_name(&self) -> &'static str {
+        Self::CLASS_NAME
+    }
+
+    fn pack(&self, stream: &mut BitStream) {
+        stream.write_int(self.id, ENTRY_BIT_SIZE);
+        stream.write_string(&self.string);
+    }
+
+    fn unpack(&mut self, _connection: &GameConnection, stream: &mut BitStream) -> Result<()> {
+        self.id = stream.read_int(ENTRY_BIT_SIZE)?;
+        self.string = stream.read_string()?;
+        Ok(())
+    }
+
+    fn process(&mut self, connection: &mut GameConnection) -> Result<()> {
+        connection
+            .string_table_mut()
+            .map_string(self.id, self.string.clone());
+        Ok(())
+    }
+
+    fn notify_delivered(&mut self, connection: &mut GameConnection, made_it: bool) {
+        if made_it {
+            connection
+                .string_table_mut()
+                .confirm_string_received(&self.string, self.id);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use super::ghost::GHOST_ID_BIT_SIZE;
+use super::moves::{read_moves, write_moves, Move};
+use crate::error::Result;
+use crate::math::Point3F;
+use crate::packet::BitStream;
+
+// The GameConnection::writePacket / readPacket part of a data packet, which comes
+// after the rate info and before events and ghosts. What's in it depends on which way
+// the packet is going.
+
+const MIN_CAMERA_FOV: u32 = 1;
+const MAX_CAMERA_FOV: u32 = 179;
+const DAMAGE_FLASH_BITS: usize = 7;
+const WHITE_OUT_BITS: usize = 7;
+// White out goes up to 1.5, but is sent as 0..1
+const WHITE_OUT_SCALE: f32 = 1.5;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ControlScheme {
+    pub absolute_rotation: bool,
+    pub add_yaw_to_absolute_rotation: bool,
+    pub add_pitch_to_absolute_rotation: bool,
+}
+
+// Client -> server
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClientPacketHeader {
+    pub first_move_index: u32,
+    pub moves: Vec<Move>,
+    pub camera_pos: bool,
+    pub control_force_mismatch: bool,
+    // These are only sent when they change
+    pub control_scheme: Option<ControlScheme>,
+    pub first_person: Option<bool>,
+    pub camera_fov: Option<u32>,
+}
+
+// What the server says about the client's control object, when it has one
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ControlObjectUpdate {
+    // Ghost index of the control object, followed by its packet data
+    Update(u32),
+    // Nothing new, just what compressed points in the rest of the packet are relative to
+    CompressionPoint(Point3F),
+}
+
+// Server -> client
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ServerPacketHeader {
+    pub last_move_ack: u32,
+    // Only sent when they change, Some(0.0) turns them off
+    pub damage_flash: Option<f32>,
+    pub white_out: Option<f32>,
+    pub control_object: Option<ControlObjectUpdate>,
+    // Ghost index of the camera object, followed by its packet data. None when the
+    // control object is the camera.
+    pub camera_object: Option<u32>,
+    pub first_person: Option<bool>,
+    pub camera_fov: Option<u32>,
+    pub control_scheme: Option<ControlScheme>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameConnectionPacketHeader {
+    Client(ClientPacketHeader),
+    Server(ServerPacketHeader),
+}
+
+// A changed flag, then a nonzero flag, then the value scaled into 0..1
+fn write_changed_float(stream: &mut BitStream, value: Option<f32>, bits: usize, scale: f32) {
+    if stream.write_flag(value.is_some()) {
+        let value = value.unwrap();
+        if stream.write_flag(value != 0.0) {
+            stream.write_float_zero_to_one(value / scale, bits);
+        }
+    }
+}
+
+fn read_changed_float(stream: &mut BitStream, bits: usize, scale: f32) -> Result<Option<f32>> {
+    if !stream.read_flag()? {
+        return Ok(None);
+    }
+    if !stream.read_flag()? {
+        return Ok(Some(0.0));
+    }
+    Ok(Some(stream.read_float_zero_to_one(bits)? * scale))
+}
+
+fn write_control_scheme(stream: &mut BitStream, control_scheme: Option<ControlScheme>) {
+    if stream.write_flag(control_scheme.is_some()) {
+        let scheme = control_scheme.unwrap();
+        stream.write_flag(scheme.absolute_rotation);
+        stream.write_flag(scheme.add_yaw_to_absolute_rotation);
+        stream.write_flag(scheme.add_pitch_to_absolute_rotation);
+    }
+}
+
+fn read_control_scheme(stream: &mut BitStream) -> Result<Option<ControlScheme>> {
+    if !stream.read_flag()? {
+        return Ok(None);
+    }
+    Ok(Some(ControlScheme {
+        absolute_rotation: stream.read_flag()?,
+        add_yaw_to_absolute_rotation: stream.read_flag()?,
+        add_pitch_to_absolute_rotation: stream.read_flag()?,
+    }))
+}
+
+fn write_first_person(stream: &mut BitStream, first_person: Option<bool>) {
+    if stream.write_flag(first_person.is_some()) {
+        stream.write_flag(first_person.unwrap());
+    }
+}
+
+fn read_first_person(stream: &mut BitStream) -> Result<Option<bool>> {
+    if !stream.read_flag()? {
+        return Ok(None);
+    }
+    Ok(Some(stream.read_flag()?))
+}
+
+fn write_camera_fov(stream: &mut BitStream, camera_fov: Option<u32>) {
+    if stream.write_flag(camera_fov.is_some()) {
+        let fov = camera_fov.unwrap().clamp(MIN_CAMERA_FOV, MAX_CAMERA_FOV);
+        stream.write_ranged_u32(fov, MIN_CAMERA_FOV, MAX_CAMERA_FOV);
+    }
+}
+
+fn read_camera_fov(stream: &mut BitStream) -> Result<Option<u32>> {
+    if !stream.read_flag()? {
+        return Ok(None);
+    }
+    Ok(Some(
+        stream.read_ranged_u32(MIN_CAMERA_FOV, MAX_CAMERA_FOV)?,
+    ))
+}
+
+impl ClientPacketHeader {
+    pub fn write(&self, stream: &mut BitStream) {
+        write_moves(stream, self.first_move_index, &self.moves);
+        stream.write_flag(self.camera_pos);
+        stream.write_flag(self.control_force_mismatch);
+        write_control_scheme(stream, self.control_scheme);
+        write_first_person(stream, self.first_person);
+        write_camera_fov(stream, self.camera_fov);
+    }
+
+    pub fn read(stream: &mut BitStream) -> Result<Self> {
+        let (first_move_index, moves) = read_moves(stream)?;
+        Ok(ClientPacketHeader {
+            first_move_index,
+            moves,
+            camera_pos: stream.read_flag()?,
+            control_force_mismatch: stream.read_flag()?,
+            control_scheme: read_control_scheme(stream)?,
+            first_person: read_first_person(stream)?,
+            camera_fov: read_camera_fov(stream)?,
+        })
+    }
+}
+
+impl ServerPacketHeader {
+    // GameConnection::writePacket on the server. The control and camera objects'
+    // packet data is up to the objects, so write_object_data is called with their ghost
+    // index to fill that in.
+    pub fn write<F>(&self, stream: &mut BitStream, mut write_object_data: F)
+    where
+        F: FnMut(u32, &mut BitStream),
+    {
+        stream.write_u32(self.last_move_ack);
+        write_changed_float(stream, self.damage_flash, DAMAGE_FLASH_BITS, 1.0);
+        write_changed_float(stream, self.white_out, WHITE_OUT_BITS, WHITE_OUT_SCALE);
+
+        if stream.write_flag(self.control_object.is_some()) {
+            match self.control_object.unwrap() {
+                ControlObjectUpdate::Update(ghost_index) => {
+                    stream.write_flag(true);
+                    stream.write_int(ghost_index, GHOST_ID_BIT_SIZE);
+                    write_object_data(ghost_index, stream);
+                }
+                ControlObjectUpdate::CompressionPoint(point) => {
+                    stream.write_flag(false);
+                    stream.write_point(point);
+                    stream.set_compression_point(point);
+                }
+            }
+        }
+        if stream.write_flag(self.camera_object.is_some()) {
+            let ghost_index = self.camera_object.unwrap();
+            stream.write_int(ghost_index, GHOST_ID_BIT_SIZE);
+            write_object_data(ghost_index, stream);
+        }
+
+        write_first_person(stream, self.first_person);
+        write_camera_fov(stream, self.camera_fov);
+        write_control_scheme(stream, self.control_scheme);
+    }
+
+    // GameConnection::readPacket on the client
+    pub fn read<F>(stream: &mut BitStream, mut read_object_data: F) -> Result<Self>
+    where
+        F: FnMut(u32, &mut BitStream) -> Result<()>,
+    {
+        let last_move_ack = stream.read_u32()?;
+        let damage_flash = read_changed_float(stream, DAMAGE_FLASH_BITS, 1.0)?;
+        let white_out = read_changed_float(stream, WHITE_OUT_BITS, WHITE_OUT_SCALE)?;
+
+        let control_object = if !stream.read_flag()? {
+            None
+        } else if stream.read_flag()? {
+            let ghost_index = stream.read_int(GHOST_ID_BIT_SIZE)?;
+            read_object_data(ghost_index, stream)?;
+            Some(ControlObjectUpdate::Update(ghost_index))
+        } else {
+            let point = stream.read_point()?;
+            stream.set_compression_point(point);
+            Some(ControlObjectUpdate::CompressionPoint(point))
+        };
+        let camera_object = if stream.read_flag()? {
+            let ghost_index = stream.read_int(GHOST_ID_BIT_SIZE)?;
+            read_object_data(ghost_index, stream)?;
+            Some(ghost_index)
+        } else {
+            None
+        };
+
+        Ok(ServerPacketHeader {
+            last_move_ack,
+            damage_flash,
+            white_out,
+            control_object,
+            camera_object,
+            first_person: read_first_person(stream)?,
+            camera_fov: read_camera_fov(stream)?,
+            control_scheme: read_control_scheme(stream)?,
+        })
+    }
+}
+
+impl GameConnectionPacketHeader {
+    pub fn write<F>(&self, stream: &mut BitStream, write_object_data: F)
+    where
+        F: FnMut(u32, &mut BitStream),
+    {
+        match self {
+            GameConnectionPacketHeader::Client(header) => header.write(stream),
+            GameConnectionPacketHeader::Server(header) => header.write(stream, write_object_data),
+        }
+    }
+
+    // What a server reads
+    pub fn read_from_client(stream: &mut BitStream) -> Result<Self> {
+        Ok(GameConnectionPacketHeader::Client(
+            ClientPacketHeader::read(stream)?,
+        ))
+    }
+
+    // What a client reads
+    pub fn read_from_server<F>(stream: &mut BitStream, read_object_data: F) -> Result<Self>
+    where
+        F: FnMut(u32, &mut BitStream) -> Result<()>,
+    {
+        Ok(GameConnectionPacketHeader::Server(
+            ServerPacketHeader::read(stream, read_object_data)?,
+        ))
+    }
+}
@@ -0,0 +1,196 @@
+use crate::error::Result;
+use crate::packet::BitStream;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// NetConnection's mCurRate / mMaxRate and checkPacketSend
+
+pub const DEFAULT_UPDATE_DELAY: u32 = 102;
+pub const DEFAULT_PACKET_SIZE: u32 = 200;
+// checkMaxRate limits for $pref::Net::PacketRateTo* / $pref::Net::PacketSize
+pub const MIN_PACKET_RATE: u32 = 8;
+pub const MAX_PACKET_RATE: u32 = 32;
+pub const MIN_PACKET_SIZE: u32 = 100;
+pub const MAX_PACKET_SIZE: u32 = 450;
+
+const RATE_BITS: usize = 12;
+// Don't let a long stall turn into a burst of packets
+const MAX_SEND_DELAY_CREDIT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetRate {
+    // Milliseconds between packets
+    pub update_delay: u32,
+    // Bytes per packet
+    pub packet_size: u32,
+    pub changed: bool,
+}
+
+impl Default for NetRate {
+    fn default() -> Self {
+        NetRate {
+            update_delay: DEFAULT_UPDATE_DELAY,
+            packet_size: DEFAULT_PACKET_SIZE,
+            changed: false,
+        }
+    }
+}
+
+impl NetRate {
+    // Returns whether the rate was sent, so it can be sent again if the packet drops
+    fn write(&mut self, stream: &mut BitStream) -> bool {
+        if stream.write_flag(self.changed) {
+            stream.write_int(self.update_delay, RATE_BITS);
+            stream.write_int(self.packet_size, RATE_BITS);
+            self.changed = false;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// The rate parts of NetConnection's PacketNotify
+#[derive(Debug, Default)]
+struct RateNote {
+    rate_changed: bool,
+    max_rate_changed: bool,
+}
+
+#[derive(Debug)]
+pub struct RateControl {
+    // What we are sending at, agreed on with the other side
+    cur_rate: NetRate,
+    // The most we are willing to receive
+    max_rate: NetRate,
+    last_update_time: Option<Instant>,
+    send_delay_credit: Duration,
+    notify_queue: VecDeque<RateNote>,
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateControl {
+    pub fn new() -> Self {
+        RateControl {
+            cur_rate: NetRate::default(),
+            max_rate: NetRate::default(),
+            last_update_time: None,
+            send_delay_credit: Duration::from_millis(0),
+            notify_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn cur_rate(&self) -> NetRate {
+        self.cur_rate
+    }
+
+    pub fn max_rate(&self) -> NetRate {
+        self.max_rate
+    }
+
+    // Packet budget for the next data packet, in bits
+    pub fn max_packet_bits(&self) -> usize {
+        self.cur_rate.packet_size as usize * 8
+    }
+
+    pub fn update_delay(&self) -> Duration {
+        Duration::from_millis(self.cur_rate.update_delay as u64)
+    }
+
+    // checkMaxRate, packet_rate is in packets per second
+    pub fn set_max_rate(&mut self, packet_rate: u32, packet_size: u32) {
+        let packet_rate = packet_rate.clamp(MIN_PACKET_RATE, MAX_PACKET_RATE);
+        let packet_size = packet_size.clamp(MIN_PACKET_SIZE, MAX_PACKET_SIZE);
+        let update_delay = 1000 / packet_rate;
+
+        if update_delay != self.max_rate.update_delay || packet_size != self.max_rate.packet_size {
+            self.max_rate = NetRate {
+                update_delay,
+                packet_size,
+                changed: true,
+            };
+        }
+    }
+
+    // Rate info at the very start of the data packet
+    pub fn write_packet(&mut self, stream: &mut BitStream) {
+        let rate_changed = self.cur_rate.write(stream);
+        let max_rate_changed = self.max_rate.write(stream);
+        self.notify_queue.push_back(RateNote {
+            rate_changed,
+            max_rate_changed,
+        });
+    }
+
+    // For data packets sent without rate info, so notifies still line up
+    pub fn push_empty_notify(&mut self) {
+        self.notify_queue.push_back(RateNote::default());
+    }
+
+    // Call once for each DNetResult::HandleNotify. Rates sent in a dropped packet are
+    // flagged to go out again.
+    pub fn handle_notify(&mut self, recvd: bool) {
+        let note = match self.notify_queue.pop_front() {
+            Some(note) => note,
+            None => return,
+        };
+        if !recvd {
+            self.cur_rate.changed |= note.rate_changed;
+            self.max_rate.changed |= note.max_rate_changed;
+        }
+    }
+
+    pub fn read_packet(&mut self, stream: &mut BitStream) -> Result<()> {
+        // The rate the other side is sending at. Torque takes this as is, our own limits
+        // only come in below when we pick the rate we send at.
+        if stream.read_flag()? {
+            self.cur_rate.update_delay = stream.read_int(RATE_BITS)?;
+            self.cur_rate.packet_size = stream.read_int(RATE_BITS)?;
+        }
+        // The most the other side wants to receive, so slow down to match if we need to
+        if stream.read_flag()? {
+            let max_delay = stream.read_int(RATE_BITS)?.max(self.max_rate.update_delay);
+            let max_size = stream.read_int(RATE_BITS)?.min(self.max_rate.packet_size);
+
+            if max_delay != self.cur_rate.update_delay || max_size != self.cur_rate.packet_size {
+                self.cur_rate = NetRate {
+                    update_delay: max_delay,
+                    packet_size: max_size,
+                    changed: true,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // Whether enough time has passed since the last packet to send another. Time we
+    // were late by is credited towards the next packet so the rate evens out.
+    pub fn check_send_time(&mut self, now: Instant) -> bool {
+        let last_update_time = match self.last_update_time {
+            Some(time) => time,
+            None => return true,
+        };
+        let send_time =
+            last_update_time + self.update_delay().saturating_sub(self.send_delay_credit);
+        if now < send_time {
+            return false;
+        }
+        self.send_delay_credit = (now - send_time).min(MAX_SEND_DELAY_CREDIT);
+        true
+    }
+
+    pub fn mark_sent(&mut self, now: Instant) {
+        self.last_update_time = Some(now);
+    }
+
+    // When check_send_time will next say yes
+    pub fn next_send_time(&self) -> Option<Instant> {
+        self.last_update_time
+            .map(|time| time + self.update_delay().saturating_sub(self.send_delay_credit))
+    }
+}
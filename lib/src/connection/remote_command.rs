@@ -0,0 +1,87 @@
+use super::connection::GameConnection;
+use super::net_event::NetEvent;
+use super::net_string::{detag, is_tagged_string, pack_string, unpack_string};
+use crate::error::{DnetError, Result};
+use crate::packet::BitStream;
+
+// MaxRemoteCommandArgs is 20, which fits in 5 bits
+const MAX_REMOTE_COMMAND_ARGS: usize = 20;
+const COMMAND_ARGS_BITS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct RemoteCommand {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+// argv[0] is the command name, which Torque expects to be a tagged string
+#[derive(Debug, Clone, Default)]
+pub struct RemoteCommandEvent {
+    pub argv: Vec<String>,
+}
+
+impl RemoteCommandEvent {
+    pub const CLASS_NAME: &'static str = "RemoteCommandEvent";
+
+    pub fn new(name: &str, args: &[&str]) -> Result<Self> {
+        if args.len() + 1 > MAX_REMOTE_COMMAND_ARGS {
+            return Err(DnetError::TooManyCommandArgs(args.len()));
+        }
+        let mut argv = vec![name.to_string()];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        Ok(RemoteCommandEvent { argv })
+    }
+
+    pub fn create() -> Box<dyn NetEvent> {
+        Box::new(RemoteCommandEvent::default())
+    }
+}
+
+impl NetEvent for RemoteCommandEvent {
+    fn class_name(&self) -> &'static str {
+        Self::CLASS_NAME
+    }
+
+    fn pack(&self, stream: &mut BitStream) {
+        stream.write_int(self.argv.len() as u32, COMMAND_ARGS_BITS);
+        for arg in &self.argv {
+            pack_string(stream, arg);
+        }
+    }
+
+    fn unpack(&mut self, _connection: &GameConnection, stream: &mut BitStream) -> Result<()> {
+        let argc = stream.read_int(COMMAND_ARGS_BITS)? as usize;
+        if argc > MAX_REMOTE_COMMAND_ARGS {
+            return Err(DnetError::TooManyCommandArgs(argc));
+        }
+        self.argv.clear();
+        for _ in 0..argc {
+            self.argv.push(unpack_string(stream)?);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, connection: &mut GameConnection) -> Result<()> {
+        if self.argv.is_empty() {
+            return Err(DnetError::MissingCommandName);
+        }
+
+        // De-tag everything, back to front so that tags can be filled in with the
+        // (already expanded) args after them
+        for i in (0..self.argv.len()).rev() {
+            if is_tagged_string(&self.argv[i]) {
+                let expanded = connection
+                    .string_table()
+                    .expand(&self.argv[i], &self.argv[(i + 1)..]);
+                self.argv[i] = expanded;
+            }
+        }
+
+        // Args keep their tags for scripts to detag, but the name goes without
+        connection.handle_remote_command(RemoteCommand {
+            name: detag(&self.argv[0]).to_string(),
+            args: self.argv[1..].to_vec(),
+        });
+        Ok(())
+    }
+}
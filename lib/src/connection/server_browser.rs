@@ -0,0 +1,369 @@
+use crate::error::Result;
+use crate::logging::{HexDump, HEX_DUMP_TARGET};
+use crate::packet::{Packet, QueryFlags, MAX_PACKET_DATA_SIZE};
+use crate::PacketSource::GameToGame;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info_span, trace, Instrument};
+
+// gPingTimeout / gPingRetryCount in Torque's serverQuery.cpp, used for info requests too
+pub const DEFAULT_BROWSER_TIMEOUT: Duration = Duration::from_millis(800);
+pub const DEFAULT_BROWSER_RETRY_COUNT: u32 = 4;
+
+// Servers being pinged or queried at once, the rest wait their turn
+pub const MAX_CONCURRENT_SERVER_QUERIES: usize = 16;
+
+// A server's GamePingResponse and GameInfoResponse together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub address: (Ipv4Addr, u16),
+    pub ping: Duration,
+    // From the GamePingResponse
+    pub name: String,
+    pub version_string: String,
+    pub current_protocol_version: u32,
+    pub min_required_protocol_version: u32,
+    pub version: u32,
+    // From the GameInfoResponse
+    pub game_type: String,
+    pub mission_type: String,
+    pub mission_name: String,
+    pub filter_flag: u8,
+    pub player_count: u8,
+    pub max_players: u8,
+    pub bot_count: u8,
+    pub cpu_speed: u16,
+    pub server_info: String,
+    pub server_info_query: String,
+}
+
+// The GamePingResponse fields, kept until the info comes back
+struct PingResult {
+    ping: Duration,
+    name: String,
+    version_string: String,
+    current_protocol_version: u32,
+    min_required_protocol_version: u32,
+    version: u32,
+}
+
+struct PendingServer {
+    key: u16,
+    sent_at: Instant,
+    retries: u32,
+    // None while we're still pinging
+    ping: Option<PingResult>,
+}
+
+impl PendingServer {
+    fn request(&self, session: u16) -> Packet {
+        let (flags, key) = (QueryFlags::OnlineQuery, self.key);
+        match self.ping {
+            None => Packet::GamePingRequest {
+                flags,
+                key,
+                session,
+            },
+            Some(_) => Packet::GameInfoRequest {
+                flags,
+                key,
+                session,
+            },
+        }
+    }
+}
+
+struct Sweep {
+    socket: UdpSocket,
+    session: u16,
+    timeout: Duration,
+    retry_count: u32,
+    waiting: VecDeque<(Ipv4Addr, u16)>,
+    pending: HashMap<(Ipv4Addr, u16), PendingServer>,
+    tx: mpsc::UnboundedSender<ServerInfo>,
+}
+
+impl Sweep {
+    async fn run(mut self) -> Result<()> {
+        loop {
+            while self.pending.len() < MAX_CONCURRENT_SERVER_QUERIES {
+                match self.waiting.pop_front() {
+                    Some(address) => self.start(address).await,
+                    None => break,
+                }
+            }
+            if self.pending.is_empty() || self.tx.is_closed() {
+                return Ok(());
+            }
+
+            let next_timeout = self
+                .pending
+                .values()
+                .map(|server| server.sent_at + self.timeout)
+                .min()
+                .expect("not empty");
+
+            let mut buf: [u8; MAX_PACKET_DATA_SIZE] = [0; MAX_PACKET_DATA_SIZE];
+            select! {
+                result = self.socket.recv_from(&mut buf) => {
+                    let (len, from) = match result {
+                        Ok(result) => result,
+                        Err(error) if Self::is_transient(&error) => {
+                            debug!(%error, "Recv failed");
+                            continue;
+                        }
+                        Err(error) => return Err(error.into()),
+                    };
+                    trace!(target: HEX_DUMP_TARGET, "<<< {} bytes from {}\n{}", len, from, HexDump(&buf[0..len]));
+                    self.handle_packet(from, &buf[0..len]).await;
+                }
+                _ = sleep_until(next_timeout) => {
+                    self.retry_timed_out().await;
+                }
+            }
+        }
+    }
+
+    // Windows reports ICMP port unreachables from servers that are down as errors on
+    // the next recv, which is no reason to stop asking the rest
+    fn is_transient(error: &io::Error) -> bool {
+        matches!(
+            error.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+        )
+    }
+
+    async fn start(&mut self, address: (Ipv4Addr, u16)) {
+        let server = PendingServer {
+            key: rand::random(),
+            sent_at: Instant::now(),
+            retries: 0,
+            ping: None,
+        };
+        let request = server.request(self.session);
+        self.pending.insert(address, server);
+        self.send_to(address, request).await;
+    }
+
+    async fn handle_packet(&mut self, from: SocketAddr, bytes: &[u8]) {
+        let from = match from {
+            SocketAddr::V4(from) => (*from.ip(), from.port()),
+            SocketAddr::V6(_) => return,
+        };
+        let packet = match Packet::parse_bytes(bytes, GameToGame) {
+            Ok(packet) => packet,
+            Err(error) => {
+                debug!(%error, ?from, "Dropping malformed packet");
+                return;
+            }
+        };
+        debug!(?packet, ?from, "Recv");
+
+        let session = self.session;
+        let server = match self.pending.get_mut(&from) {
+            Some(server) => server,
+            None => return,
+        };
+
+        match packet {
+            Packet::GamePingResponse {
+                key,
+                session: response_session,
+                version_string,
+                current_protocol_version,
+                min_required_protocol_version,
+                version,
+                name,
+                ..
+            } if key == server.key && response_session == session && server.ping.is_none() => {
+                // Time since the last try, like Torque does
+                server.ping = Some(PingResult {
+                    ping: server.sent_at.elapsed(),
+                    name,
+                    version_string,
+                    current_protocol_version,
+                    min_required_protocol_version,
+                    version,
+                });
+                server.sent_at = Instant::now();
+                server.retries = 0;
+                let request = server.request(session);
+                self.send_to(from, request).await;
+            }
+            Packet::GameInfoResponse {
+                key,
+                session: response_session,
+                game_type,
+                mission_type,
+                mission_name,
+                filter_flag,
+                player_count,
+                max_players,
+                bot_count,
+                cpu_speed,
+                server_info,
+                server_info_query,
+                ..
+            } if key == server.key && response_session == session && server.ping.is_some() => {
+                let ping = self
+                    .pending
+                    .remove(&from)
+                    .and_then(|server| server.ping)
+                    .expect("checked above");
+                // Nobody to give it to if the browser was dropped, and run() stops then
+                let _ = self.tx.send(ServerInfo {
+                    address: from,
+                    ping: ping.ping,
+                    name: ping.name,
+                    version_string: ping.version_string,
+                    current_protocol_version: ping.current_protocol_version,
+                    min_required_protocol_version: ping.min_required_protocol_version,
+                    version: ping.version,
+                    game_type,
+                    mission_type,
+                    mission_name,
+                    filter_flag,
+                    player_count,
+                    max_players,
+                    bot_count,
+                    cpu_speed,
+                    server_info,
+                    server_info_query,
+                });
+            }
+            _ => {
+                debug!(?from, "Ignoring packet");
+            }
+        }
+    }
+
+    async fn retry_timed_out(&mut self) {
+        let now = Instant::now();
+        let timed_out = self
+            .pending
+            .iter()
+            .filter(|(_, server)| server.sent_at + self.timeout <= now)
+            .map(|(address, _)| *address)
+            .collect::<Vec<_>>();
+
+        for address in timed_out {
+            let server = self.pending.get_mut(&address).expect("just found");
+            if server.retries >= self.retry_count {
+                debug!(?address, "Server didn't respond, giving up");
+                self.pending.remove(&address);
+                continue;
+            }
+            server.retries += 1;
+            server.sent_at = now;
+            let request = server.request(self.session);
+            self.send_to(address, request).await;
+        }
+    }
+
+    async fn send_to(&self, to: (Ipv4Addr, u16), packet: Packet) {
+        debug!(?packet, ?to, "Send");
+        let bytes = packet.into_bytes();
+        trace!(target: HEX_DUMP_TARGET, ">>> {} bytes to {:?}\n{}", bytes.len(), to, HexDump(&bytes));
+        if let Err(error) = self.socket.send_to(&bytes, to).await {
+            debug!(%error, ?to, "Send failed");
+        }
+    }
+}
+
+// Pings and asks for the info of a list of servers, like from MasterServer::query_servers,
+// all over one socket. Servers come out of next() as they answer, and ones that never
+// do are left out.
+pub struct ServerBrowser {
+    rx: mpsc::UnboundedReceiver<ServerInfo>,
+    // Taken once the sweep is over and its result has been handed out
+    sweep_thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl ServerBrowser {
+    pub async fn sweep<B: ToSocketAddrs>(
+        bind_address: B,
+        servers: Vec<(Ipv4Addr, u16)>,
+    ) -> Result<Self> {
+        Self::sweep_with_timeout(
+            bind_address,
+            servers,
+            DEFAULT_BROWSER_TIMEOUT,
+            DEFAULT_BROWSER_RETRY_COUNT,
+        )
+        .await
+    }
+
+    // Each request is sent again if it isn't answered in timeout, up to retry_count times
+    pub async fn sweep_with_timeout<B: ToSocketAddrs>(
+        bind_address: B,
+        servers: Vec<(Ipv4Addr, u16)>,
+        timeout: Duration,
+        retry_count: u32,
+    ) -> Result<Self> {
+        let socket = UdpSocket::bind(bind_address).await?;
+        let span = info_span!("server_browser", local = %socket.local_addr()?);
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let mut waiting = VecDeque::new();
+        for server in servers {
+            if !waiting.contains(&server) {
+                waiting.push_back(server);
+            }
+        }
+
+        let sweep = Sweep {
+            socket,
+            session: rand::random(),
+            timeout,
+            retry_count,
+            waiting,
+            pending: HashMap::new(),
+            tx,
+        };
+        let sweep_thread = tokio::spawn(sweep.run().instrument(span));
+
+        Ok(ServerBrowser {
+            rx,
+            sweep_thread: Some(sweep_thread),
+        })
+    }
+
+    // None once every server has answered or been given up on, or the error that
+    // stopped the sweep early
+    pub async fn next(&mut self) -> Result<Option<ServerInfo>> {
+        if let Some(server) = self.rx.recv().await {
+            return Ok(Some(server));
+        }
+        match self.sweep_thread.take() {
+            Some(sweep_thread) => sweep_thread.await?.map(|_| None),
+            None => Ok(None),
+        }
+    }
+
+    // Waits for the whole sweep
+    pub async fn collect(mut self) -> Result<Vec<ServerInfo>> {
+        let mut servers = vec![];
+        while let Some(server) = self.next().await? {
+            servers.push(server);
+        }
+        Ok(servers)
+    }
+}
+
+impl Drop for ServerBrowser {
+    fn drop(&mut self) {
+        if let Some(sweep_thread) = &self.sweep_thread {
+            sweep_thread.abort();
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// How many of the most recent data packets packet loss is measured over
+pub const LOSS_WINDOW_SIZE: usize = 64;
+
+// Measurements for a connection, filled in by DNet as packets go in and out.
+// Round trip times are smoothed like TCP's SRTT / RTTVAR (RFC 6298).
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    smoothed_rtt: Option<Duration>,
+    jitter: Duration,
+    last_rtt: Option<Duration>,
+    // Whether each of the last LOSS_WINDOW_SIZE data packets we sent made it
+    recent_notifies: VecDeque<bool>,
+    bytes_in: u64,
+    bytes_out: u64,
+    packets_in: u64,
+    packets_out: u64,
+    packets_acked: u64,
+    packets_lost: u64,
+    packets_out_of_window: u64,
+}
+
+impl ConnectionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    // Mean deviation of round trip times from the smoothed value
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.last_rtt
+    }
+
+    // Percentage of the last LOSS_WINDOW_SIZE data packets that didn't make it
+    pub fn loss_percentage(&self) -> f32 {
+        if self.recent_notifies.is_empty() {
+            return 0.0;
+        }
+        let lost = self
+            .recent_notifies
+            .iter()
+            .filter(|&&made_it| !made_it)
+            .count();
+        lost as f32 * 100.0 / self.recent_notifies.len() as f32
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+
+    pub fn packets_in(&self) -> u64 {
+        self.packets_in
+    }
+
+    pub fn packets_out(&self) -> u64 {
+        self.packets_out
+    }
+
+    pub fn packets_acked(&self) -> u64 {
+        self.packets_acked
+    }
+
+    pub fn packets_lost(&self) -> u64 {
+        self.packets_lost
+    }
+
+    // Packets we threw away because their sequence numbers were too far ahead
+    pub fn packets_out_of_window(&self) -> u64 {
+        self.packets_out_of_window
+    }
+
+    pub fn record_rtt(&mut self, sample: Duration) {
+        self.last_rtt = Some(sample);
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(sample);
+                self.jitter = sample / 2;
+            }
+            Some(smoothed_rtt) => {
+                let deviation = sample.abs_diff(smoothed_rtt);
+                self.jitter = (self.jitter * 3 + deviation) / 4;
+                self.smoothed_rtt = Some((smoothed_rtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    pub fn record_notify(&mut self, made_it: bool) {
+        if made_it {
+            self.packets_acked += 1;
+        } else {
+            self.packets_lost += 1;
+        }
+        if self.recent_notifies.len() >= LOSS_WINDOW_SIZE {
+            self.recent_notifies.pop_front();
+        }
+        self.recent_notifies.push_back(made_it);
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.packets_in += 1;
+        self.bytes_in += bytes as u64;
+    }
+
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.packets_out += 1;
+        self.bytes_out += bytes as u64;
+    }
+
+    pub fn record_out_of_window(&mut self) {
+        self.packets_out_of_window += 1;
+    }
+}
@@ -0,0 +1,75 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DnetError {
+    #[error("End of stream at bit {bit_pos}")]
+    EndOfStream { bit_pos: usize },
+    #[error("Bad connect sequence bit")]
+    BadConnectSequence,
+    #[error("Too many ack bytes: {0}")]
+    TooManyAckBytes(u32),
+    #[error("Invalid packet type: {0}")]
+    InvalidPacketType(u32),
+    #[error("Unknown packet id: {0}")]
+    UnknownPacketId(u8),
+    #[error("String too long: {len} bytes, max is {max}")]
+    StringTooLong { len: usize, max: usize },
+    #[error("Stream overflow: {bit_pos} bits, max is {max_bits}")]
+    StreamOverflow { bit_pos: usize, max_bits: usize },
+    #[error("Invalid bit count for a signed int: {0}")]
+    InvalidBitCount(usize),
+    #[error("Can't encode {0:?} as Latin-1")]
+    UnencodableChar(char),
+    #[error("Huffman table needs {0} bit codes, max is 32")]
+    HuffmanCodeTooLong(u32),
+    #[error("Invalid class id {0}")]
+    InvalidClassId(u32),
+    #[error("Class {0} is not registered")]
+    UnregisteredClass(String),
+    #[error("Can't construct class {0}")]
+    UnconstructableClass(String),
+    #[error("Ghost index out of range: {0}")]
+    GhostIndexOutOfRange(u32),
+    #[error("Ghost index size out of range: {0} bits")]
+    GhostIndexSizeOutOfRange(usize),
+    #[error("Unknown ghost {0}")]
+    UnknownGhost(u32),
+    #[error("Too many ghosts, can't scope object {0}")]
+    TooManyGhosts(u32),
+    #[error("Ghost object {0} is poisoned")]
+    PoisonedGhost(u32),
+    #[error("Too many moves in packet: {0}")]
+    TooManyMoves(usize),
+    #[error("Too many remote command args: {0}")]
+    TooManyCommandArgs(usize),
+    #[error("Remote command without a name")]
+    MissingCommandName,
+    #[error("Master server query timed out")]
+    QueryTimedOut,
+    #[error("Channel closed")]
+    ChannelClosed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl<T> From<tokio::sync::mpsc::error::SendError<T>> for DnetError {
+    fn from(_: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        DnetError::ChannelClosed
+    }
+}
+
+impl<T> From<tokio::sync::broadcast::error::SendError<T>> for DnetError {
+    fn from(_: tokio::sync::broadcast::error::SendError<T>) -> Self {
+        DnetError::ChannelClosed
+    }
+}
+
+impl From<tokio::sync::broadcast::error::RecvError> for DnetError {
+    fn from(_: tokio::sync::broadcast::error::RecvError) -> Self {
+        DnetError::ChannelClosed
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DnetError>;
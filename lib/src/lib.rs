@@ -1,5 +1,10 @@
 pub mod connection;
+pub mod error;
+pub mod logging;
+pub mod math;
 pub mod packet;
 
 pub use connection::*;
+pub use error::DnetError;
+pub use math::{AngAxisF, MatrixF, Point3F, QuatF};
 pub use packet::*;
@@ -0,0 +1,39 @@
+use std::fmt;
+
+// Everything is logged with `tracing`, using the module path as the target, so
+// subsystems can be filtered separately, eg RUST_LOG=dnet::connection::dnet=trace.
+// Raw packet bytes are only logged to HEX_DUMP_TARGET, at trace level.
+pub const HEX_DUMP_TARGET: &str = "dnet::hexdump";
+
+// Formats bytes like `xxd`, 16 per line with offsets
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Display for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (line, chunk) in self.0.chunks(16).enumerate() {
+            if line > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:08x}: ", line * 16)?;
+            for i in 0..16 {
+                match chunk.get(i) {
+                    Some(byte) => write!(f, "{:02x}", byte)?,
+                    None => write!(f, "  ")?,
+                }
+                if i % 2 == 1 {
+                    write!(f, " ")?;
+                }
+            }
+            write!(f, " ")?;
+            for &byte in chunk {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+// Torque's math types, as far as the network code needs them
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Point3F {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Point3F {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Point3F { x, y, z }
+    }
+
+    pub fn len_squared(&self) -> f32 {
+        self.x * self.x + self.y * self.y + self.z * self.z
+    }
+
+    pub fn len(&self) -> f32 {
+        self.len_squared().sqrt()
+    }
+
+    pub fn dot(&self, other: Point3F) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    // Zero length vectors are left alone
+    pub fn normalized(&self) -> Point3F {
+        let len = self.len();
+        if len == 0.0 {
+            *self
+        } else {
+            *self * (1.0 / len)
+        }
+    }
+}
+
+impl Add for Point3F {
+    type Output = Point3F;
+
+    fn add(self, other: Point3F) -> Point3F {
+        Point3F::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Point3F {
+    type Output = Point3F;
+
+    fn sub(self, other: Point3F) -> Point3F {
+        Point3F::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f32> for Point3F {
+    type Output = Point3F;
+
+    fn mul(self, scale: f32) -> Point3F {
+        Point3F::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+impl Neg for Point3F {
+    type Output = Point3F;
+
+    fn neg(self) -> Point3F {
+        Point3F::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl From<(f32, f32, f32)> for Point3F {
+    fn from(value: (f32, f32, f32)) -> Self {
+        Point3F::new(value.0, value.1, value.2)
+    }
+}
+
+impl From<Point3F> for (f32, f32, f32) {
+    fn from(value: Point3F) -> Self {
+        (value.x, value.y, value.z)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QuatF {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Default for QuatF {
+    fn default() -> Self {
+        QuatF::identity()
+    }
+}
+
+impl QuatF {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        QuatF { x, y, z, w }
+    }
+
+    pub fn identity() -> Self {
+        QuatF::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    pub fn normalized(&self) -> QuatF {
+        let len = (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w).sqrt();
+        if len == 0.0 {
+            return QuatF::identity();
+        }
+        let inv = 1.0 / len;
+        QuatF::new(self.x * inv, self.y * inv, self.z * inv, self.w * inv)
+    }
+
+    // QuatF::set(const MatrixF&), only the rotation part is used
+    pub fn from_matrix(mat: &MatrixF) -> QuatF {
+        let trace = mat.get(0, 0) + mat.get(1, 1) + mat.get(2, 2);
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt();
+            let w = s * 0.5;
+            let s = 0.5 / s;
+            QuatF::new(
+                (mat.get(2, 1) - mat.get(1, 2)) * s,
+                (mat.get(0, 2) - mat.get(2, 0)) * s,
+                (mat.get(1, 0) - mat.get(0, 1)) * s,
+                w,
+            )
+        } else {
+            let mut q = [0f32; 3];
+            let mut i = 0;
+            if mat.get(1, 1) > mat.get(0, 0) {
+                i = 1;
+            }
+            if mat.get(2, 2) > mat.get(i, i) {
+                i = 2;
+            }
+            let j = (i + 1) % 3;
+            let k = (j + 1) % 3;
+
+            let s = ((mat.get(i, i) - (mat.get(j, j) + mat.get(k, k))) + 1.0).sqrt();
+            q[i] = s * 0.5;
+            let s = 0.5 / s;
+            q[j] = (mat.get(j, i) + mat.get(i, j)) * s;
+            q[k] = (mat.get(k, i) + mat.get(i, k)) * s;
+            let w = (mat.get(k, j) - mat.get(j, k)) * s;
+            QuatF::new(q[0], q[1], q[2], w)
+        }
+    }
+
+    // QuatF::setMatrix, with no translation
+    pub fn to_matrix(&self) -> MatrixF {
+        let mut mat = MatrixF::identity();
+        if self.x * self.x + self.y * self.y + self.z * self.z < 10e-20 {
+            return mat;
+        }
+
+        let xs = self.x * 2.0;
+        let ys = self.y * 2.0;
+        let zs = self.z * 2.0;
+        let wx = self.w * xs;
+        let wy = self.w * ys;
+        let wz = self.w * zs;
+        let xx = self.x * xs;
+        let xy = self.x * ys;
+        let xz = self.x * zs;
+        let yy = self.y * ys;
+        let yz = self.y * zs;
+        let zz = self.z * zs;
+
+        mat.set(0, 0, 1.0 - (yy + zz));
+        mat.set(0, 1, xy - wz);
+        mat.set(0, 2, xz + wy);
+        mat.set(1, 0, xy + wz);
+        mat.set(1, 1, 1.0 - (xx + zz));
+        mat.set(1, 2, yz - wx);
+        mat.set(2, 0, xz - wy);
+        mat.set(2, 1, yz + wx);
+        mat.set(2, 2, 1.0 - (xx + yy));
+        mat
+    }
+}
+
+impl From<AngAxisF> for QuatF {
+    fn from(value: AngAxisF) -> Self {
+        let (sin_half, cos_half) = (value.angle * 0.5).sin_cos();
+        QuatF::new(
+            value.axis.x * sin_half,
+            value.axis.y * sin_half,
+            value.axis.z * sin_half,
+            cos_half,
+        )
+    }
+}
+
+impl From<(f32, f32, f32, f32)> for QuatF {
+    fn from(value: (f32, f32, f32, f32)) -> Self {
+        QuatF::new(value.0, value.1, value.2, value.3)
+    }
+}
+
+impl From<QuatF> for (f32, f32, f32, f32) {
+    fn from(value: QuatF) -> Self {
+        (value.x, value.y, value.z, value.w)
+    }
+}
+
+// Rotation around an axis, in radians
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AngAxisF {
+    pub axis: Point3F,
+    pub angle: f32,
+}
+
+impl Default for AngAxisF {
+    fn default() -> Self {
+        AngAxisF::new(Point3F::new(0.0, 0.0, 1.0), 0.0)
+    }
+}
+
+impl AngAxisF {
+    pub fn new(axis: Point3F, angle: f32) -> Self {
+        AngAxisF { axis, angle }
+    }
+}
+
+impl From<QuatF> for AngAxisF {
+    fn from(value: QuatF) -> Self {
+        let angle = value.w.clamp(-1.0, 1.0).acos() * 2.0;
+        let sin_half = (value.x * value.x + value.y * value.y + value.z * value.z).sqrt();
+        if sin_half != 0.0 {
+            AngAxisF::new(
+                Point3F::new(value.x, value.y, value.z) * (1.0 / sin_half),
+                angle,
+            )
+        } else {
+            AngAxisF::new(Point3F::new(0.0, 0.0, 1.0), angle)
+        }
+    }
+}
+
+// 4x4, row major like Torque's, with the position in column 3
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MatrixF {
+    pub m: [f32; 16],
+}
+
+impl Default for MatrixF {
+    fn default() -> Self {
+        MatrixF::identity()
+    }
+}
+
+impl MatrixF {
+    pub fn identity() -> Self {
+        let mut m = [0f32; 16];
+        m[0] = 1.0;
+        m[5] = 1.0;
+        m[10] = 1.0;
+        m[15] = 1.0;
+        MatrixF { m }
+    }
+
+    // Rotation then translation, like Torque's transforms
+    pub fn from_rotation_position(rotation: QuatF, position: Point3F) -> Self {
+        let mut mat = rotation.to_matrix();
+        mat.set_column(3, position);
+        mat
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.m[row * 4 + col]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: f32) {
+        self.m[row * 4 + col] = value;
+    }
+
+    // Top three rows of a column
+    pub fn get_column(&self, col: usize) -> Point3F {
+        Point3F::new(self.get(0, col), self.get(1, col), self.get(2, col))
+    }
+
+    pub fn set_column(&mut self, col: usize, value: Point3F) {
+        self.set(0, col, value.x);
+        self.set(1, col, value.y);
+        self.set(2, col, value.z);
+    }
+
+    pub fn position(&self) -> Point3F {
+        self.get_column(3)
+    }
+
+    pub fn rotation(&self) -> QuatF {
+        QuatF::from_matrix(self)
+    }
+
+    pub fn mul_point(&self, point: Point3F) -> Point3F {
+        Point3F::new(
+            self.get(0, 0) * point.x + self.get(0, 1) * point.y + self.get(0, 2) * point.z,
+            self.get(1, 0) * point.x + self.get(1, 1) * point.y + self.get(1, 2) * point.z,
+            self.get(2, 0) * point.x + self.get(2, 1) * point.y + self.get(2, 2) * point.z,
+        ) + self.position()
+    }
+}
+
+impl From<QuatF> for MatrixF {
+    fn from(value: QuatF) -> Self {
+        value.to_matrix()
+    }
+}
+
+impl From<AngAxisF> for MatrixF {
+    fn from(value: AngAxisF) -> Self {
+        QuatF::from(value).to_matrix()
+    }
+}
@@ -1,14 +1,66 @@
-use super::huffman::HuffmanProcessor;
-use anyhow::{anyhow, Result};
+use super::huffman::HuffmanTable;
+use crate::error::{DnetError, Result};
+use crate::math::{MatrixF, Point3F, QuatF};
 use std::f32::consts::{FRAC_1_SQRT_2, PI, SQRT_2};
+use std::sync::Arc;
 
 const POINT_EPSILON: f32 = 0.0001f32;
 
+// Net::MaxPacketDataSize, the most we will put in one datagram
+pub const MAX_PACKET_DATA_SIZE: usize = 1500;
+// Longest string that fits in the 8 bit length of huffman strings and cstrings
+pub const MAX_STRING_LEN: usize = 0xFF;
+const MAX_LONG_CSTRING_LEN: usize = 0xFFFF;
+
+// Torque's default scale for compressed points, in world units
+pub const DEFAULT_COMPRESSED_POINT_SCALE: f32 = 0.01;
+// Bits per axis for each size of compressed point
+const COMPRESSED_POINT_BIT_COUNTS: [usize; 3] = [16, 18, 20];
+
+// How Strings become bytes on the wire. Torque just sends the bytes of its script
+// strings, which are Latin-1 (near enough) in TGE and UTF-8 in later engines.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum StringEncoding {
+    #[default]
+    Latin1,
+    Utf8,
+}
+
 #[derive(Debug)]
 pub struct BitStream {
     data: Vec<u8>,
     position: usize,
     shift: usize,
+    // In bytes. Writes that don't fit are dropped and the stream is marked overflowed,
+    // at the bit position of the first write that didn't fit.
+    max_size: Option<usize>,
+    overflowed_at: Option<usize>,
+    string_encoding: StringEncoding,
+    huffman_table: Arc<HuffmanTable>,
+    compression_point: Point3F,
+    // The first thing that went wrong writing, see try_into_bytes
+    error: Option<DnetError>,
+}
+
+// Strings go over the wire as Latin-1, one byte per char
+fn encode_latin1(value: &str) -> Result<Vec<u8>> {
+    value
+        .chars()
+        .map(|c| {
+            if (c as u32) <= 0xFF {
+                Ok(c as u8)
+            } else {
+                Err(DnetError::UnencodableChar(c))
+            }
+        })
+        .collect()
+}
+
+fn encode_latin1_lossy(value: &str) -> Vec<u8> {
+    value
+        .chars()
+        .map(|c| if (c as u32) <= 0xFF { c as u8 } else { b'?' })
+        .collect()
 }
 
 impl BitStream {
@@ -17,6 +69,12 @@ impl BitStream {
             data: buffer,
             position: 0,
             shift: 0,
+            max_size: None,
+            overflowed_at: None,
+            string_encoding: StringEncoding::default(),
+            huffman_table: HuffmanTable::stock(),
+            compression_point: Point3F::default(),
+            error: None,
         }
     }
 
@@ -25,6 +83,85 @@ impl BitStream {
             data: vec![0],
             position: 0,
             shift: 0,
+            max_size: None,
+            overflowed_at: None,
+            string_encoding: StringEncoding::default(),
+            huffman_table: HuffmanTable::stock(),
+            compression_point: Point3F::default(),
+            error: None,
+        }
+    }
+
+    // For packets going out, so running out of room is caught instead of sending a
+    // datagram bigger than the other side will take
+    pub fn with_max_size(max_size: usize) -> Self {
+        let mut stream = Self::new();
+        stream.max_size = Some(max_size);
+        stream
+    }
+
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<usize>) {
+        self.max_size = max_size;
+    }
+
+    pub fn string_encoding(&self) -> StringEncoding {
+        self.string_encoding
+    }
+
+    pub fn set_string_encoding(&mut self, string_encoding: StringEncoding) {
+        self.string_encoding = string_encoding;
+    }
+
+    pub fn huffman_table(&self) -> &Arc<HuffmanTable> {
+        &self.huffman_table
+    }
+
+    // For engines that build their huffman codes from different frequencies
+    pub fn set_huffman_table(&mut self, huffman_table: Arc<HuffmanTable>) {
+        self.huffman_table = huffman_table;
+    }
+
+    pub fn compression_point(&self) -> Point3F {
+        self.compression_point
+    }
+
+    // What compressed points are relative to, usually the control object's position
+    pub fn set_compression_point(&mut self, compression_point: Point3F) {
+        self.compression_point = compression_point;
+    }
+
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed_at.is_some()
+    }
+
+    // Whether `bits` more bits will fit
+    pub fn has_room(&self, bits: usize) -> bool {
+        match self.max_size {
+            Some(max_size) => {
+                self.overflowed_at.is_none() && self.get_bit_pos() + bits <= max_size * 8
+            }
+            None => true,
+        }
+    }
+
+    pub fn error(&self) -> Option<&DnetError> {
+        self.error.as_ref()
+    }
+
+    fn record_error(&mut self, error: DnetError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn overflow_error(&self, bits: usize) -> DnetError {
+        DnetError::StreamOverflow {
+            bit_pos: self.get_bit_pos() + bits,
+            max_bits: self.max_size.unwrap_or(0) * 8,
         }
     }
 
@@ -44,6 +181,14 @@ impl BitStream {
         self.data
     }
 
+    // into_bytes, but fails if any write overflowed the stream or had to mangle a string
+    pub fn try_into_bytes(self) -> Result<Vec<u8>> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.into_bytes()),
+        }
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.data.as_slice()
     }
@@ -57,11 +202,29 @@ impl BitStream {
         self.shift = pos % 8;
     }
 
+    // Goes back to pos to write over something that didn't fit, throwing away what was
+    // written after it and forgetting any overflow that happened there
+    pub fn rewind(&mut self, pos: usize) {
+        self.set_bit_pos(pos);
+        self.data.truncate(self.position + 1);
+        if let Some(last) = self.data.get_mut(self.position) {
+            *last &= !(0xFFu8 << self.shift);
+        }
+        if self.overflowed_at.is_some_and(|at| at >= pos) {
+            self.overflowed_at = None;
+            if matches!(self.error, Some(DnetError::StreamOverflow { .. })) {
+                self.error = None;
+            }
+        }
+    }
+
     fn read_bits(&mut self, bits: usize) -> Result<u8> {
         assert!(bits <= 8);
 
         if self.position >= self.len() {
-            return Err(anyhow!("End of stream"));
+            return Err(DnetError::EndOfStream {
+                bit_pos: self.get_bit_pos(),
+            });
         }
         if bits == 0 {
             return Ok(0);
@@ -89,7 +252,9 @@ impl BitStream {
             // it out unless we need it.
             if extra != 0 {
                 if self.position >= self.len() {
-                    return Err(anyhow!("End of stream"));
+                    return Err(DnetError::EndOfStream {
+                        bit_pos: self.get_bit_pos(),
+                    });
                 }
 
                 //Get the second, upper, part of the number from the new top and
@@ -189,6 +354,13 @@ impl BitStream {
 
     pub fn write_int(&mut self, mut value: u32, mut bits: usize) -> u32 {
         let original = value;
+        if !self.has_room(bits) {
+            if self.overflowed_at.is_none() {
+                self.record_error(self.overflow_error(bits));
+                self.overflowed_at = Some(self.get_bit_pos());
+            }
+            return original;
+        }
         loop {
             self.write_bits((value & 0xFF) as u8, bits.min(8));
             value >>= 8;
@@ -216,8 +388,26 @@ impl BitStream {
         return Ok(self.read_int(32)? as u32);
     }
 
+    // Invalid UTF-8 is replaced rather than failing the whole packet. Use the byte
+    // versions if you need to know exactly what was sent.
+    fn decode_string(&self, bytes: Vec<u8>) -> String {
+        match self.string_encoding {
+            StringEncoding::Latin1 => bytes.into_iter().map(|c| c as char).collect(),
+            StringEncoding::Utf8 => match String::from_utf8(bytes) {
+                Ok(string) => string,
+                Err(error) => String::from_utf8_lossy(error.as_bytes()).into_owned(),
+            },
+        }
+    }
+
+    pub fn read_huff_bytes(&mut self) -> Result<Vec<u8>> {
+        let huffman_table = self.huffman_table.clone();
+        huffman_table.read_bytes(self)
+    }
+
     pub fn read_string(&mut self) -> Result<String> {
-        HuffmanProcessor::read_string(self)
+        let bytes = self.read_huff_bytes()?;
+        Ok(self.decode_string(bytes))
     }
 
     pub fn read_cstring(&mut self) -> Result<String> {
@@ -226,7 +416,7 @@ impl BitStream {
         for _ in 0..len {
             chars.push(self.read_u8()?);
         }
-        return Ok(chars.into_iter().map(|c| c as char).collect());
+        Ok(self.decode_string(chars))
     }
 
     pub fn read_long_cstring(&mut self) -> Result<String> {
@@ -235,22 +425,26 @@ impl BitStream {
         for _ in 0..len {
             chars.push(self.read_u8()?);
         }
-        return Ok(chars.into_iter().map(|c| c as char).collect());
+        Ok(self.decode_string(chars))
     }
 
-    pub fn read_float_zero_to_one(&mut self, bit_count: usize) -> Result<f32> {
+    fn int_to_float_zero_to_one(i: u32, bit_count: usize) -> f32 {
         let max_int = (1u32 << bit_count) - 1;
-        let i = self.read_int(bit_count)?;
         if i == 0 {
-            return Ok(0f32);
+            return 0f32;
         }
         if i == (max_int / 2) + 1 {
-            return Ok(0.5f32);
+            return 0.5f32;
         }
         if i == max_int {
-            return Ok(1.0f32);
+            return 1.0f32;
         }
-        return Ok((i as f32) / (max_int as f32));
+        (i as f32) / (max_int as f32)
+    }
+
+    pub fn read_float_zero_to_one(&mut self, bit_count: usize) -> Result<f32> {
+        let i = self.read_int(bit_count)?;
+        Ok(Self::int_to_float_zero_to_one(i, bit_count))
     }
 
     pub fn read_signed_float_neg_one_to_one(&mut self, bit_count: usize) -> Result<f32> {
@@ -258,47 +452,112 @@ impl BitStream {
     }
 
     pub fn read_signed_int(&mut self, bit_count: usize) -> Result<i32> {
+        if !Self::valid_signed_int_bits(bit_count) {
+            return Err(DnetError::InvalidBitCount(bit_count));
+        }
         // 1s complement because torque is torque
         if self.read_flag()? {
-            return Ok(-(self.read_int(bit_count - 1)? as i32));
+            Ok((self.read_int(bit_count - 1)? as i32).wrapping_neg())
         } else {
-            return Ok(self.read_int(bit_count - 1)? as i32);
+            Ok(self.read_int(bit_count - 1)? as i32)
         }
     }
 
-    pub fn read_normal_vector(&mut self, bit_count: usize) -> Result<(f32, f32, f32)> {
+    pub fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    // mathRead(Point3F), three uncompressed floats
+    pub fn read_point(&mut self) -> Result<Point3F> {
+        Ok(Point3F::new(
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+        ))
+    }
+
+    pub fn read_normal_vector(&mut self, bit_count: usize) -> Result<Point3F> {
         let phi = self.read_signed_float_neg_one_to_one(bit_count + 1)? * PI;
         let theta = self.read_signed_float_neg_one_to_one(bit_count)? * (PI / 2.0);
 
-        Ok((
+        Ok(Point3F::new(
             phi.sin() * theta.cos(),
             phi.cos() * theta.cos(),
             theta.sin(),
         ))
     }
 
+    // The readNormalVector that sends z and the angle around it separately
+    pub fn read_normal_vector_z(
+        &mut self,
+        angle_bit_count: usize,
+        z_bit_count: usize,
+    ) -> Result<Point3F> {
+        if self.read_flag()? {
+            let z = if self.read_flag()? { -1.0 } else { 1.0 };
+            return Ok(Point3F::new(0.0, 0.0, z));
+        }
+
+        let z = self.read_signed_float_neg_one_to_one(z_bit_count)?;
+        let angle = self.read_signed_float_neg_one_to_one(angle_bit_count)? * 2.0 * PI;
+        let mult = (1.0 - z * z).max(0.0).sqrt();
+        Ok(Point3F::new(mult * angle.cos(), mult * angle.sin(), z))
+    }
+
     pub fn read_vector(
         &mut self,
         max_magnitude: f32,
         magnitude_bits: usize,
         normal_bits: usize,
-    ) -> Result<(f32, f32, f32)> {
+    ) -> Result<Point3F> {
         if !self.read_flag()? {
-            return Ok((0.0, 0.0, 0.0));
+            return Ok(Point3F::default());
         }
 
-        let mag;
-        if self.read_flag()? {
-            mag = self.read_float_zero_to_one(magnitude_bits)? * max_magnitude;
+        let mag = if self.read_flag()? {
+            self.read_float_zero_to_one(magnitude_bits)? * max_magnitude
         } else {
-            mag = f32::from_bits(self.read_int(32)?);
-        }
+            self.read_f32()?
+        };
 
         let normal = self.read_normal_vector(normal_bits)?;
-        Ok((normal.0 * mag, normal.1 * mag, normal.2 * mag))
+        Ok(normal * mag)
+    }
+
+    // Relative to the compression point, in multiples of `scale`, or the whole point
+    // if it's too far away
+    pub fn read_compressed_point(&mut self, scale: f32) -> Result<Point3F> {
+        let point_type = self.read_int(2)? as usize;
+        if point_type == 3 {
+            return self.read_point();
+        }
+
+        let bit_count = COMPRESSED_POINT_BIT_COUNTS[point_type];
+        let offset = Point3F::new(
+            self.read_signed_int(bit_count)? as f32,
+            self.read_signed_int(bit_count)? as f32,
+            self.read_signed_int(bit_count)? as f32,
+        );
+        Ok(self.compression_point + offset * scale)
+    }
+
+    // Position and rotation only, no scale
+    pub fn read_affine_transform(&mut self) -> Result<MatrixF> {
+        let position = self.read_point()?;
+        let x = self.read_f32()?;
+        let y = self.read_f32()?;
+        let z = self.read_f32()?;
+        let mut w = (1.0 - (x * x + y * y + z * z).min(1.0)).sqrt();
+        if self.read_flag()? {
+            w = -w;
+        }
+        Ok(MatrixF::from_rotation_position(
+            QuatF::new(x, y, z, w),
+            position,
+        ))
     }
 
-    pub fn read_quat(&mut self, bit_count: usize) -> Result<(f32, f32, f32, f32)> {
+    pub fn read_quat(&mut self, bit_count: usize) -> Result<QuatF> {
         let mut vals = [0f32; 4];
         let mut sum = 0f32;
 
@@ -317,15 +576,21 @@ impl BitStream {
             vals[idx_max] = (1.0 - sum).sqrt();
         }
 
-        return Ok((vals[0], vals[1], vals[2], vals[3]));
+        Ok(QuatF::new(vals[0], vals[1], vals[2], vals[3]))
+    }
+
+    // Enough bits for every value in the range. Done in u64 so 0..=u32::MAX doesn't
+    // overflow to an empty range.
+    fn ranged_bit_count(range_start: u32, range_end: u32) -> usize {
+        let range_size = (range_end - range_start) as u64 + 1;
+        range_size.next_power_of_two().trailing_zeros() as usize
     }
 
     pub fn read_ranged_u32(&mut self, range_start: u32, range_end: u32) -> Result<u32> {
-        let range_size = range_end - range_start + 1;
-        let range_bits = range_size.next_power_of_two().trailing_zeros();
+        let range_bits = Self::ranged_bit_count(range_start, range_end);
 
-        let val = self.read_int(range_bits as usize)?;
-        return Ok(val + range_start);
+        let val = self.read_int(range_bits)?;
+        Ok(val.wrapping_add(range_start))
     }
 
     pub fn read_cussed_u32(&mut self) -> Result<u32> {
@@ -364,126 +629,345 @@ impl BitStream {
         return value;
     }
 
+    // String writes never panic: chars outside Latin-1 become '?' and long strings are
+    // cut off, with the problem recorded for try_into_bytes. Use the try_write_*
+    // versions to find out right away instead.
+    fn encode_string_lossy(&mut self, value: &str, max_len: usize) -> Vec<u8> {
+        let mut bytes = match self.string_encoding {
+            StringEncoding::Latin1 => match encode_latin1(value) {
+                Ok(bytes) => bytes,
+                Err(error) => {
+                    self.record_error(error);
+                    encode_latin1_lossy(value)
+                }
+            },
+            StringEncoding::Utf8 => value.as_bytes().to_vec(),
+        };
+        if bytes.len() > max_len {
+            self.record_error(DnetError::StringTooLong {
+                len: bytes.len(),
+                max: max_len,
+            });
+            let mut len = max_len;
+            // Don't leave half a character on the end
+            while self.string_encoding == StringEncoding::Utf8 && !value.is_char_boundary(len) {
+                len -= 1;
+            }
+            bytes.truncate(len);
+        }
+        bytes
+    }
+
+    fn check_string(&self, value: &str, max_len: usize) -> Result<Vec<u8>> {
+        let bytes = match self.string_encoding {
+            StringEncoding::Latin1 => encode_latin1(value)?,
+            StringEncoding::Utf8 => value.as_bytes().to_vec(),
+        };
+        if bytes.len() > max_len {
+            return Err(DnetError::StringTooLong {
+                len: bytes.len(),
+                max: max_len,
+            });
+        }
+        Ok(bytes)
+    }
+
+    // Returns how many bytes were written. Anything past MAX_STRING_LEN is cut off and
+    // recorded as StringTooLong.
+    pub fn write_huff_bytes(&mut self, bytes: &[u8]) -> usize {
+        if bytes.len() > MAX_STRING_LEN {
+            self.record_error(DnetError::StringTooLong {
+                len: bytes.len(),
+                max: MAX_STRING_LEN,
+            });
+        }
+        let bytes = &bytes[..bytes.len().min(MAX_STRING_LEN)];
+        let huffman_table = self.huffman_table.clone();
+        huffman_table.write_bytes(self, bytes);
+        bytes.len()
+    }
+
     pub fn write_string(&mut self, value: &String) -> String {
-        HuffmanProcessor::write_string(self, value);
+        let bytes = self.encode_string_lossy(value, MAX_STRING_LEN);
+        self.write_huff_bytes(&bytes);
         return value.clone();
     }
 
     pub fn write_cstring(&mut self, value: &String) -> String {
-        assert!(value.len() < 256);
-        self.write_u8(value.len() as u8);
-        for ch in value.chars() {
-            self.write_u8(ch as u8);
+        let bytes = self.encode_string_lossy(value, MAX_STRING_LEN);
+        self.write_u8(bytes.len() as u8);
+        for byte in bytes {
+            self.write_u8(byte);
         }
         return value.clone();
     }
 
     pub fn write_long_cstring(&mut self, value: &String) -> String {
-        assert!(value.len() < 65536);
-        self.write_u16(value.len() as u16);
-        for ch in value.chars() {
-            self.write_u8(ch as u8);
+        let bytes = self.encode_string_lossy(value, MAX_LONG_CSTRING_LEN);
+        self.write_u16(bytes.len() as u16);
+        for byte in bytes {
+            self.write_u8(byte);
         }
         return value.clone();
     }
 
-    pub fn write_float_zero_to_one(&mut self, mut value: f32, bit_count: usize) -> f32 {
+    // Checked writes. These write nothing if they fail.
+
+    pub fn try_write_int(&mut self, value: u32, bits: usize) -> Result<u32> {
+        if !self.has_room(bits) {
+            return Err(self.overflow_error(bits));
+        }
+        Ok(self.write_int(value, bits))
+    }
+
+    pub fn try_write_signed_int(&mut self, value: i32, bit_count: usize) -> Result<i32> {
+        if !Self::valid_signed_int_bits(bit_count) {
+            return Err(DnetError::InvalidBitCount(bit_count));
+        }
+        if !self.has_room(bit_count) {
+            return Err(self.overflow_error(bit_count));
+        }
+        Ok(self.write_signed_int(value, bit_count))
+    }
+
+    pub fn try_write_flag(&mut self, value: bool) -> Result<bool> {
+        self.try_write_int(value as u32, 1)?;
+        Ok(value)
+    }
+
+    pub fn try_write_u8(&mut self, value: u8) -> Result<u8> {
+        self.try_write_int(value as u32, 8)?;
+        Ok(value)
+    }
+
+    pub fn try_write_u16(&mut self, value: u16) -> Result<u16> {
+        self.try_write_int(value as u32, 16)?;
+        Ok(value)
+    }
+
+    pub fn try_write_u32(&mut self, value: u32) -> Result<u32> {
+        self.try_write_int(value, 32)?;
+        Ok(value)
+    }
+
+    pub fn try_write_huff_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > MAX_STRING_LEN {
+            return Err(DnetError::StringTooLong {
+                len: bytes.len(),
+                max: MAX_STRING_LEN,
+            });
+        }
+        let bits = self.huffman_table.encoded_bits(bytes);
+        if !self.has_room(bits) {
+            return Err(self.overflow_error(bits));
+        }
+        let huffman_table = self.huffman_table.clone();
+        huffman_table.write_bytes(self, bytes);
+        Ok(())
+    }
+
+    pub fn try_write_string(&mut self, value: &str) -> Result<()> {
+        let bytes = self.check_string(value, MAX_STRING_LEN)?;
+        self.try_write_huff_bytes(&bytes)
+    }
+
+    pub fn try_write_cstring(&mut self, value: &str) -> Result<()> {
+        let bytes = self.check_string(value, MAX_STRING_LEN)?;
+        let bits = 8 + bytes.len() * 8;
+        if !self.has_room(bits) {
+            return Err(self.overflow_error(bits));
+        }
+        self.write_u8(bytes.len() as u8);
+        for byte in bytes {
+            self.write_u8(byte);
+        }
+        Ok(())
+    }
+
+    pub fn try_write_long_cstring(&mut self, value: &str) -> Result<()> {
+        let bytes = self.check_string(value, MAX_LONG_CSTRING_LEN)?;
+        let bits = 16 + bytes.len() * 8;
+        if !self.has_room(bits) {
+            return Err(self.overflow_error(bits));
+        }
+        self.write_u16(bytes.len() as u16);
+        for byte in bytes {
+            self.write_u8(byte);
+        }
+        Ok(())
+    }
+
+    pub fn write_float_zero_to_one(&mut self, value: f32, bit_count: usize) -> f32 {
         let max_int = (1u32 << bit_count) - 1;
         let i;
         if value < POINT_EPSILON {
             i = 0;
-            value = 0.0;
         } else if (value - 0.5).abs() < POINT_EPSILON {
             i = (max_int / 2) + 1;
-            value = 0.5;
         } else if value > (1.0f32 - POINT_EPSILON) {
             i = max_int;
-            value = 1.0;
         } else {
             i = (value * (max_int as f32)).round() as u32;
-            value = (i as f32) / (max_int as f32);
         }
 
         self.write_int(i, bit_count);
 
-        return value;
+        // Rounding can land on the int that means exactly 0.5, so return what the
+        // other side will read rather than i / max_int
+        Self::int_to_float_zero_to_one(i, bit_count)
     }
 
     pub fn write_signed_float_neg_one_to_one(&mut self, value: f32, bit_count: usize) -> f32 {
         return self.write_float_zero_to_one((value + 1f32) / 2f32, bit_count) * 2f32 - 1f32;
     }
 
+    // A sign flag and up to 32 bits of magnitude
+    fn valid_signed_int_bits(bit_count: usize) -> bool {
+        (1..=33).contains(&bit_count)
+    }
+
+    // Writes nothing and returns 0 if bit_count is out of range, and the error shows
+    // up in try_into_bytes
     pub fn write_signed_int(&mut self, value: i32, bit_count: usize) -> i32 {
-        // I will become back my money
-        if value < 0 {
-            self.write_flag(true);
-            self.write_int((-value) as u32, bit_count);
+        if !Self::valid_signed_int_bits(bit_count) {
+            self.record_error(DnetError::InvalidBitCount(bit_count));
+            return 0;
+        }
+        // Sign flag, then the magnitude in the other bit_count - 1 bits
+        let magnitude =
+            value.unsigned_abs() & u32::MAX.checked_shr(33 - bit_count as u32).unwrap_or(0);
+        let negative = self.write_flag(value < 0);
+        self.write_int(magnitude, bit_count - 1);
+
+        // What read_signed_int will get back, including any bits that didn't fit
+        if negative {
+            (magnitude as i32).wrapping_neg()
         } else {
-            self.write_flag(false);
-            self.write_int(value as u32, bit_count);
+            magnitude as i32
         }
+    }
 
-        return value;
+    pub fn write_f32(&mut self, value: f32) -> f32 {
+        self.write_u32(value.to_bits());
+        value
     }
 
-    pub fn write_normal_vector(
-        &mut self,
-        value: (f32, f32, f32),
-        bit_count: usize,
-    ) -> (f32, f32, f32) {
-        let phi = value.0.atan2(value.1) / PI;
+    pub fn write_point(&mut self, value: Point3F) -> Point3F {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+        self.write_f32(value.z);
+        value
+    }
+
+    pub fn write_normal_vector(&mut self, value: Point3F, bit_count: usize) -> Point3F {
+        let phi = value.x.atan2(value.y) / PI;
         let theta = value
-            .2
-            .atan2((value.0 * value.0 + value.1 * value.1).sqrt())
+            .z
+            .atan2((value.x * value.x + value.y * value.y).sqrt())
             / (PI / 2.0);
 
-        self.write_signed_float_neg_one_to_one(phi, bit_count + 1);
-        self.write_signed_float_neg_one_to_one(theta, bit_count);
+        // What the other side will read, after rounding
+        let phi = self.write_signed_float_neg_one_to_one(phi, bit_count + 1) * PI;
+        let theta = self.write_signed_float_neg_one_to_one(theta, bit_count) * (PI / 2.0);
 
-        (
+        Point3F::new(
             phi.sin() * theta.cos(),
             phi.cos() * theta.cos(),
             theta.sin(),
         )
     }
 
+    // Vectors pointing (nearly) straight up or down only take two bits
+    pub fn write_normal_vector_z(
+        &mut self,
+        value: Point3F,
+        angle_bit_count: usize,
+        z_bit_count: usize,
+    ) -> Point3F {
+        if self.write_flag(value.z.abs() >= 1.0 - 1.0 / z_bit_count as f32) {
+            let z = if self.write_flag(value.z < 0.0) {
+                -1.0
+            } else {
+                1.0
+            };
+            return Point3F::new(0.0, 0.0, z);
+        }
+
+        let z = self.write_signed_float_neg_one_to_one(value.z, z_bit_count);
+        let angle = self.write_signed_float_neg_one_to_one(
+            value.y.atan2(value.x) / (2.0 * PI),
+            angle_bit_count,
+        ) * 2.0
+            * PI;
+        let mult = (1.0 - z * z).max(0.0).sqrt();
+        Point3F::new(mult * angle.cos(), mult * angle.sin(), z)
+    }
+
     pub fn write_vector(
         &mut self,
-        value: (f32, f32, f32),
+        value: Point3F,
         max_magnitude: f32,
         magnitude_bits: usize,
         normal_bits: usize,
-    ) -> (f32, f32, f32) {
-        let mag = (value.0 * value.0 + value.1 * value.1 + value.2 * value.2).sqrt();
-        if mag < POINT_EPSILON {
-            self.write_flag(false);
-            return (0.0, 0.0, 0.0);
+    ) -> Point3F {
+        let mag = value.len();
+        if !self.write_flag(mag > POINT_EPSILON) {
+            return Point3F::default();
         }
 
-        if mag < max_magnitude {
-            self.write_flag(true);
-            self.write_float_zero_to_one(mag / max_magnitude, magnitude_bits);
+        // Too big to compress, so send it as is
+        let mag = if self.write_flag(mag < max_magnitude) {
+            self.write_float_zero_to_one(mag / max_magnitude, magnitude_bits) * max_magnitude
         } else {
-            self.write_int(mag.to_bits(), 32);
-        }
-
-        let div = 1.0 / mag;
+            self.write_f32(mag)
+        };
+
+        let normal = self.write_normal_vector(value * (1.0 / value.len()), normal_bits);
+        normal * mag
+    }
+
+    pub fn write_compressed_point(&mut self, value: Point3F, scale: f32) -> Point3F {
+        let offset = (value - self.compression_point) * (1.0 / scale);
+        let dist = offset.len();
+        let point_type = if dist < (1 << 15) as f32 {
+            0
+        } else if dist < (1 << 17) as f32 {
+            1
+        } else if dist < (1 << 19) as f32 {
+            2
+        } else {
+            3
+        };
 
-        self.write_normal_vector((value.0 * div, value.1 * div, value.2 * div), normal_bits);
+        self.write_int(point_type as u32, 2);
+        if point_type == 3 {
+            return self.write_point(value);
+        }
 
-        return (
-            (value.0 * div) * mag,
-            (value.1 * div) * mag,
-            (value.2 * div) * mag,
-        );
+        let bit_count = COMPRESSED_POINT_BIT_COUNTS[point_type];
+        let x = self.write_signed_int(offset.x as i32, bit_count);
+        let y = self.write_signed_int(offset.y as i32, bit_count);
+        let z = self.write_signed_int(offset.z as i32, bit_count);
+        self.compression_point + Point3F::new(x as f32, y as f32, z as f32) * scale
+    }
+
+    // Position and rotation, with the rotation as an uncompressed quaternion. w is
+    // worked out from the others on the other end, so only its sign is sent.
+    pub fn write_affine_transform(&mut self, value: &MatrixF) -> MatrixF {
+        let position = self.write_point(value.position());
+        let rotation = value.rotation().normalized();
+        let x = self.write_f32(rotation.x);
+        let y = self.write_f32(rotation.y);
+        let z = self.write_f32(rotation.z);
+        let mut w = (1.0 - (x * x + y * y + z * z).min(1.0)).sqrt();
+        if self.write_flag(rotation.w < 0.0) {
+            w = -w;
+        }
+        MatrixF::from_rotation_position(QuatF::new(x, y, z, w), position)
     }
 
-    pub fn write_quat(
-        &mut self,
-        value: (f32, f32, f32, f32),
-        bit_count: usize,
-    ) -> (f32, f32, f32, f32) {
-        let vals = [value.0, value.1, value.2, value.3];
+    pub fn write_quat(&mut self, value: QuatF, bit_count: usize) -> QuatF {
+        let vals = [value.x, value.y, value.z, value.w];
         let mut flip = vals[0] < 0.0;
         let mut max_val = vals[0].abs();
         let mut idx_max = 0;
@@ -498,22 +982,26 @@ impl BitStream {
 
         self.write_int(idx_max as u32, 2);
 
+        // What the other side will read, which may be the negated (but equivalent) quat
+        let mut sent = [0f32; 4];
+        let mut sum = 0f32;
         for i in 0..4 {
             if i == idx_max {
                 continue;
             }
             let cur_value = if flip { -vals[i] } else { vals[i] } * SQRT_2;
-            self.write_signed_float_neg_one_to_one(cur_value, bit_count);
+            sent[i] = self.write_signed_float_neg_one_to_one(cur_value, bit_count) * FRAC_1_SQRT_2;
+            sum += sent[i] * sent[i];
         }
+        sent[idx_max] = if sum > 1.0 { 1.0 } else { (1.0 - sum).sqrt() };
 
-        value
+        QuatF::new(sent[0], sent[1], sent[2], sent[3])
     }
 
     pub fn write_ranged_u32(&mut self, value: u32, range_start: u32, range_end: u32) -> u32 {
-        let range_size = range_end - range_start + 1;
-        let range_bits = range_size.next_power_of_two().trailing_zeros();
+        let range_bits = Self::ranged_bit_count(range_start, range_end);
 
-        self.write_int(value - range_start, range_bits as usize);
+        self.write_int(value - range_start, range_bits);
         return value;
     }
 
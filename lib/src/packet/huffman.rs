@@ -1,18 +1,19 @@
-#![allow(non_snake_case)]
-#![allow(non_upper_case_globals)]
-#![allow(non_camel_case_types)]
-
 use super::bitstream::BitStream;
-use anyhow::Result;
+use crate::error::{DnetError, Result};
 use lazy_static::lazy_static;
-use std::ptr::null_mut;
+use std::sync::Arc;
 
 lazy_static! {
-    static ref g_huffProcessor: HuffmanProcessor = HuffmanProcessor::new();
+    static ref STOCK_TABLE: Arc<HuffmanTable> =
+        Arc::new(HuffmanTable::new(&STOCK_CHAR_FREQS).expect("stock table is fine"));
 }
 
-// This giant array of u32s is your last chance to not see the danger
-const csm_charFreqs: [u32; 256] = [
+// Codes are read and written with write_int, so they can't be any longer than this
+const MAX_CODE_BITS: u32 = 32;
+
+// Character frequencies from Torque's HuffmanProcessor, which the codes are built from.
+// Every symbol gets one more than its count so none of them are left out of the tree.
+pub const STOCK_CHAR_FREQS: [u32; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 329, 21, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
     0, 2809, 68, 0, 27, 0, 58, 3, 62, 4, 7, 0, 0, 15, 65, 554, 3, 394, 404, 189, 117, 30, 51, 27,
     15, 34, 32, 80, 1, 142, 3, 142, 39, 0, 144, 125, 44, 122, 275, 70, 135, 61, 127, 8, 12, 113,
@@ -25,320 +26,221 @@ const csm_charFreqs: [u32; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
 
-// Ok, I warned you
+const SYMBOL_COUNT: usize = 256;
+
+// Either side of a node, or one of the not yet merged subtrees while building
+#[derive(Copy, Clone, Debug)]
+enum HuffIndex {
+    Leaf(usize),
+    Node(usize),
+}
 
 #[derive(Clone, Debug)]
 struct HuffNode {
-    pop: u32,
-    index0: i16,
-    index1: i16,
+    pop: u64,
+    index0: HuffIndex,
+    index1: HuffIndex,
 }
 
-#[derive(Clone, Debug)]
+// The symbol is the leaf's index
+#[derive(Copy, Clone, Debug, Default)]
 struct HuffLeaf {
-    pop: u32,
-    numBits: u8,
-    symbol: u8,
+    pop: u64,
+    num_bits: u8,
+    // First bit of the code is the lowest bit
     code: u32,
 }
 
+// HuffmanProcessor from Torque. Nodes and leaves refer to each other by index, and
+// the tree is built in the same order as Torque's buildTables so the codes come out
+// the same, including how ties between equal pops are broken.
+// Both sides need to use the same table, which is STOCK_CHAR_FREQS unless the engine
+// has been changed.
 #[derive(Clone, Debug)]
-struct HuffWrap {
-    // This should be red flag #2
-    pNode: *mut HuffNode,
-    pLeaf: *mut HuffLeaf,
+pub struct HuffmanTable {
+    // The root is nodes[0]
+    nodes: Vec<HuffNode>,
+    leaves: Vec<HuffLeaf>,
 }
 
-#[derive(Clone, Debug)]
-pub struct HuffmanProcessor {
-    m_tablesBuilt: bool,
-    m_huffNodes: Vec<HuffNode>,
-    m_huffLeaves: Vec<HuffLeaf>,
-}
-
-impl HuffWrap {
-    pub fn new() -> Self {
-        HuffWrap {
-            pNode: null_mut(),
-            pLeaf: null_mut(),
-        }
+impl Default for HuffmanTable {
+    fn default() -> Self {
+        (**STOCK_TABLE).clone()
     }
+}
 
-    pub fn set_leaf(&mut self, in_leaf: *mut HuffLeaf) {
-        self.pNode = null_mut();
-        self.pLeaf = in_leaf;
+impl HuffmanTable {
+    // Fails if the frequencies are lopsided enough to need codes over 32 bits
+    pub fn new(char_freqs: &[u32; 256]) -> Result<Self> {
+        let leaves = char_freqs
+            .iter()
+            .map(|&freq| HuffLeaf {
+                pop: freq as u64 + 1,
+                ..HuffLeaf::default()
+            })
+            .collect();
+        let mut table = HuffmanTable {
+            nodes: Vec::with_capacity(SYMBOL_COUNT),
+            leaves,
+        };
+        table.build_tables()?;
+        Ok(table)
     }
 
-    pub fn set_node(&mut self, in_node: *mut HuffNode) {
-        self.pNode = in_node;
-        self.pLeaf = null_mut();
+    // Shared copy of the table built from STOCK_CHAR_FREQS
+    pub fn stock() -> Arc<Self> {
+        STOCK_TABLE.clone()
     }
 
-    pub fn getPop(&self) -> u32 {
-        // SAFETY: Not safe
-        unsafe {
-            if !self.pNode.is_null() {
-                (*self.pNode).pop
-            } else if !self.pLeaf.is_null() {
-                (*self.pLeaf).pop
-            } else {
-                panic!("Null");
-            }
+    fn pop(&self, index: HuffIndex) -> u64 {
+        match index {
+            HuffIndex::Leaf(i) => self.leaves[i].pop,
+            HuffIndex::Node(i) => self.nodes[i].pop,
         }
     }
-}
-
-// XXX: Cursed. It's all cursed. Oh God
-impl HuffmanProcessor {
-    fn new() -> Self {
-        let mut processor = HuffmanProcessor {
-            m_tablesBuilt: false,
-            m_huffNodes: vec![],
-            m_huffLeaves: vec![],
-        };
-        processor.buildTables();
-        processor
-    }
-
-    fn buildTables(&mut self) {
-        assert!(!self.m_tablesBuilt);
-        self.m_tablesBuilt = true;
 
-        self.m_huffLeaves.resize_with(256, || HuffLeaf {
+    fn build_tables(&mut self) -> Result<()> {
+        // Placeholder for the root, which is filled in at the end
+        self.nodes.push(HuffNode {
             pop: 0,
-            numBits: 0,
-            symbol: 0,
-            code: 0,
-        });
-        self.m_huffNodes.reserve(256);
-        self.m_huffNodes.push(HuffNode {
-            pop: 0,
-            index0: 0,
-            index1: 0,
+            index0: HuffIndex::Leaf(0),
+            index1: HuffIndex::Leaf(0),
         });
 
-        for i in 0..256 {
-            self.m_huffLeaves[i].pop = csm_charFreqs[i] + 1;
-            self.m_huffLeaves[i].symbol = i as u8;
-            self.m_huffLeaves[i].code = 0;
-            self.m_huffLeaves[i].numBits = 0;
-        }
-
-        let mut currWraps = 256;
-        let mut pWrap = (0..256)
-            .into_iter()
-            .map(|_| HuffWrap::new())
-            .collect::<Vec<_>>();
-        for i in 0..256 {
-            pWrap[i].set_leaf(&mut self.m_huffLeaves[i]);
-        }
+        let mut wraps = (0..SYMBOL_COUNT).map(HuffIndex::Leaf).collect::<Vec<_>>();
 
-        while currWraps != 1 {
-            let mut min1 = 0xfffffffeu32;
-            let mut min2 = 0xffffffffu32;
+        while wraps.len() > 1 {
+            // The two lowest pops, earliest first when they tie
+            let mut min1 = u64::MAX - 1;
+            let mut min2 = u64::MAX;
+            let mut index1 = 0;
+            let mut index2 = 0;
 
-            let mut index1 = -1i32;
-            let mut index2 = -1i32;
-
-            for i in 0..currWraps {
-                if pWrap[i].getPop() < min1 {
+            for (i, &wrap) in wraps.iter().enumerate() {
+                let pop = self.pop(wrap);
+                if pop < min1 {
                     min2 = min1;
                     index2 = index1;
-
-                    min1 = pWrap[i].getPop();
-                    index1 = i as _;
-                } else if pWrap[i].getPop() < min2 {
-                    min2 = pWrap[i].getPop();
-                    index2 = i as _;
+                    min1 = pop;
+                    index1 = i;
+                } else if pop < min2 {
+                    min2 = pop;
+                    index2 = i;
                 }
             }
 
-            assert!(index1 != -1);
-            assert!(index2 != -1);
-            assert!(index1 != index2);
-
-            let det_index0 = self.determineIndex(&pWrap[index1 as usize]);
-            let det_index1 = self.determineIndex(&pWrap[index2 as usize]);
-
-            self.m_huffNodes.push(HuffNode {
-                pop: pWrap[index1 as usize].getPop() + pWrap[index2 as usize].getPop(),
-                index0: det_index0,
-                index1: det_index1,
+            self.nodes.push(HuffNode {
+                pop: min1 + min2,
+                index0: wraps[index1],
+                index1: wraps[index2],
             });
 
-            let mergeIndex = if index1 > index2 { index2 } else { index1 };
-            let nukeIndex = if index1 > index2 { index1 } else { index2 };
-
-            pWrap[mergeIndex as usize].set_node(self.m_huffNodes.last_mut().unwrap());
-
-            if index2 != (currWraps as i32 - 1) {
-                pWrap.swap(nukeIndex as usize, currWraps - 1);
-            }
-            currWraps -= 1;
+            // The new node takes the lower slot, and the last wrap moves into the other
+            wraps[index1.min(index2)] = HuffIndex::Node(self.nodes.len() - 1);
+            wraps.swap_remove(index1.max(index2));
         }
-        assert!(currWraps == 1);
-        assert!(!pWrap[0].pNode.is_null());
-        assert!(pWrap[0].pLeaf.is_null());
 
-        // SAFETY: Likely safe
-        self.m_huffNodes[0] = unsafe { (*pWrap[0].pNode).clone() };
-
-        let mut bs = BitStream::from_buffer(vec![0u8; 4]);
-        self.generateCodes(&mut bs, 0, 0);
-    }
-
-    fn determineIndex(&mut self, wrap: &HuffWrap) -> i16 {
-        if !wrap.pLeaf.is_null() {
-            assert!(wrap.pNode.is_null());
-
-            // SAFETY: DEFINITELY not safe
-            (unsafe { -((wrap.pLeaf.offset_from(self.m_huffLeaves.as_ptr())) + 1) }) as i16
-        } else {
-            assert!(!wrap.pNode.is_null());
+        let root = match wraps[0] {
+            HuffIndex::Node(i) => self.nodes[i].clone(),
+            HuffIndex::Leaf(_) => unreachable!("more than one symbol"),
+        };
+        self.nodes[0] = root;
 
-            // SAFETY: Extremely likely not safe
-            (unsafe { wrap.pNode.offset_from(self.m_huffNodes.as_ptr()) }) as i16
+        let max_depth = self.max_depth(HuffIndex::Node(0));
+        if max_depth > MAX_CODE_BITS {
+            return Err(DnetError::HuffmanCodeTooLong(max_depth));
         }
+        self.generate_codes(HuffIndex::Node(0), 0, 0);
+        Ok(())
     }
 
-    fn generateCodes(&mut self, stream: &mut BitStream, index: i32, depth: i32) {
-        if index < 0 {
-            stream.set_bit_pos(0);
-            // Real torque doesn't limit `code` to `numBits` but this is fine for us
-            self.m_huffLeaves[-(index + 1) as usize].code = stream
-                .read_int(depth as usize)
-                .expect("probably enough bytes in here");
-            self.m_huffLeaves[-(index + 1) as usize].numBits = depth as u8;
-        } else {
-            let index0 = self.m_huffNodes[index as usize].index0;
-            let index1 = self.m_huffNodes[index as usize].index1;
-
-            let pos = stream.get_bit_pos();
-
-            stream.write_flag(false);
-            self.generateCodes(stream, index0 as i32, depth + 1);
-
-            stream.set_bit_pos(pos);
-            stream.write_flag(true);
-            self.generateCodes(stream, index1 as i32, depth + 1);
-
-            stream.set_bit_pos(pos);
+    fn max_depth(&self, index: HuffIndex) -> u32 {
+        match index {
+            HuffIndex::Leaf(_) => 0,
+            HuffIndex::Node(i) => {
+                let node = &self.nodes[i];
+                1 + self.max_depth(node.index0).max(self.max_depth(node.index1))
+            }
         }
     }
 
-    fn readHuffBuffer(&self, stream: &mut BitStream, buffer: &mut [u8]) -> Result<u32> {
-        assert!(self.m_tablesBuilt);
-
-        if stream.read_flag()? {
-            let mut len = stream.read_int(8)?;
-            if len >= buffer.len() as u32 {
-                len = buffer.len() as u32;
+    fn generate_codes(&mut self, index: HuffIndex, code: u32, depth: u8) {
+        match index {
+            HuffIndex::Leaf(i) => {
+                self.leaves[i].code = code;
+                self.leaves[i].num_bits = depth;
             }
+            HuffIndex::Node(i) => {
+                let HuffNode { index0, index1, .. } = self.nodes[i];
+                self.generate_codes(index0, code, depth + 1);
+                self.generate_codes(index1, code | (1 << depth), depth + 1);
+            }
+        }
+    }
 
-            for i in 0..len {
-                let mut index = 0i16;
-                // TERMINATION: Lol maybe
-                loop {
-                    if index >= 0 {
-                        if stream.read_flag()? {
-                            index = self.m_huffNodes[index as usize].index1;
-                        } else {
-                            index = self.m_huffNodes[index as usize].index0;
-                        }
+    fn read_symbol(&self, stream: &mut BitStream) -> Result<u8> {
+        let mut index = HuffIndex::Node(0);
+        loop {
+            match index {
+                HuffIndex::Leaf(symbol) => return Ok(symbol as u8),
+                HuffIndex::Node(i) => {
+                    index = if stream.read_flag()? {
+                        self.nodes[i].index1
                     } else {
-                        buffer[i as usize] = self.m_huffLeaves[-(index + 1) as usize].symbol;
-                        break;
-                    }
+                        self.nodes[i].index0
+                    };
                 }
             }
-            return Ok(len);
-        } else {
-            let mut len = stream.read_int(8)?;
-            if len >= buffer.len() as u32 {
-                len = buffer.len() as u32;
-            }
-
-            for i in 0..len {
-                buffer[i as usize] = stream.read_u8()?;
-            }
-            return Ok(len);
         }
     }
 
-    fn writeHuffBuffer(&self, stream: &mut BitStream, buffer: Option<&[u8]>, maxLen: u32) -> u32 {
-        if buffer.is_none() {
-            stream.write_flag(false);
-            stream.write_int(0, 8);
-            return 0;
+    pub(crate) fn read_bytes(&self, stream: &mut BitStream) -> Result<Vec<u8>> {
+        let compressed = stream.read_flag()?;
+        let len = stream.read_int(8)? as usize;
+
+        let mut bytes = Vec::with_capacity(len);
+        for _ in 0..len {
+            bytes.push(if compressed {
+                self.read_symbol(stream)?
+            } else {
+                stream.read_u8()?
+            });
         }
+        Ok(bytes)
+    }
 
-        assert!(self.m_tablesBuilt);
+    // Length, then either huffman codes or the raw bytes if those would be smaller
+    pub(crate) fn encoded_bits(&self, buffer: &[u8]) -> usize {
+        let buffer = &buffer[..buffer.len().min(255)];
+        let code_bits = buffer
+            .iter()
+            .map(|&b| self.leaves[b as usize].num_bits as usize)
+            .sum::<usize>();
+        1 + 8 + code_bits.min(buffer.len() * 8)
+    }
 
-        let buffer = buffer.unwrap();
-        let mut len = buffer.len() as u32;
-        assert!(len <= 255);
-        if len > maxLen {
-            len = maxLen;
-        }
+    // Only the first 255 bytes fit, callers should check first
+    pub(crate) fn write_bytes(&self, stream: &mut BitStream, bytes: &[u8]) {
+        let bytes = &bytes[..bytes.len().min(255)];
 
-        let mut numBits = 0u32;
-        for i in 0..len {
-            numBits += self.m_huffLeaves[buffer[i as usize] as usize].numBits as u32;
-        }
+        let code_bits = bytes
+            .iter()
+            .map(|&b| self.leaves[b as usize].num_bits as usize)
+            .sum::<usize>();
 
-        if numBits >= (len * 8) {
+        if code_bits >= bytes.len() * 8 {
             stream.write_flag(false);
-            stream.write_int(len as u32, 8);
-
-            for i in 0..len {
-                stream.write_u8(buffer[i as usize]);
+            stream.write_int(bytes.len() as u32, 8);
+            for &b in bytes {
+                stream.write_u8(b);
             }
         } else {
             stream.write_flag(true);
-            stream.write_int(len as u32, 8);
-            for i in 0..len {
-                // Avert your eyes
-                stream.write_int(
-                    self.m_huffLeaves[buffer[i as usize] as usize].code,
-                    self.m_huffLeaves[buffer[i as usize] as usize].numBits as usize,
-                );
+            stream.write_int(bytes.len() as u32, 8);
+            for &b in bytes {
+                let leaf = &self.leaves[b as usize];
+                stream.write_int(leaf.code, leaf.num_bits as usize);
             }
         }
-
-        return len;
-    }
-
-    // Functions provided to nicely hide all the danger from you
-
-    pub fn read_buffer(stream: &mut BitStream, buffer: &mut [u8]) -> Result<usize> {
-        // SAFETY: Dangerous
-        return Ok(g_huffProcessor.readHuffBuffer(stream, buffer)? as usize);
-    }
-
-    pub fn write_buffer(stream: &mut BitStream, buffer: Option<&[u8]>, maxLen: usize) -> usize {
-        // SAFETY: Dangerous
-        return g_huffProcessor.writeHuffBuffer(stream, buffer, maxLen as u32) as usize;
-    }
-
-    pub fn read_string(stream: &mut BitStream) -> Result<String> {
-        let mut buffer = [0u8; 256];
-        let length = Self::read_buffer(stream, &mut buffer)?;
-
-        return Ok(buffer[0..length].iter().map(|&c| c as char).collect());
-    }
-
-    pub fn write_string(stream: &mut BitStream, value: &String) -> usize {
-        Self::write_buffer(
-            stream,
-            Some(
-                value
-                    .chars()
-                    .into_iter()
-                    .map(|c| c as u8)
-                    .collect::<Vec<u8>>()
-                    .as_slice(),
-            ),
-            256,
-        )
     }
 }
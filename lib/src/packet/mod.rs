@@ -3,6 +3,12 @@ mod huffman;
 mod packet;
 
 pub use bitstream::BitStream;
+pub use bitstream::StringEncoding;
+pub use bitstream::DEFAULT_COMPRESSED_POINT_SCALE;
+pub use bitstream::MAX_PACKET_DATA_SIZE;
+pub use bitstream::MAX_STRING_LEN;
+pub use huffman::HuffmanTable;
+pub use huffman::STOCK_CHAR_FREQS;
 pub use packet::FilterFlags;
 pub use packet::NetClassGroups;
 pub use packet::Packet;
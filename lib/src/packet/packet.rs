@@ -1,9 +1,11 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 
-use super::bitstream::BitStream;
-use anyhow::Result;
+use super::bitstream::{BitStream, StringEncoding, MAX_PACKET_DATA_SIZE};
+use crate::error::{DnetError, Result};
+use crate::logging::{HexDump, HEX_DUMP_TARGET};
 use std::net::Ipv4Addr;
+use tracing::{trace, warn};
 
 pub mod PacketTypes {
     pub const MasterServerGameTypesRequest: u8 = 2;
@@ -176,7 +178,10 @@ pub enum Packet {
         key: u16,
         session: u16,
     },
-    GGCPacket {},
+    // Payload isn't understood, so it's kept as is
+    GGCPacket {
+        data: Vec<u8>,
+    },
     ConnectChallengeRequest {
         sequence: u32,
     },
@@ -413,11 +418,26 @@ impl Packet {
     }
 
     pub fn try_from_bytes(bytes: &[u8], source: PacketSource) -> Option<Self> {
+        Self::parse_bytes(bytes, source).ok()
+    }
+
+    // Like try_from_bytes, but says what was wrong with the packet
+    pub fn parse_bytes(bytes: &[u8], source: PacketSource) -> Result<Self> {
+        Self::parse_bytes_with_encoding(bytes, source, StringEncoding::default())
+    }
+
+    // For servers that don't send Latin-1 strings
+    pub fn parse_bytes_with_encoding(
+        bytes: &[u8],
+        source: PacketSource,
+        string_encoding: StringEncoding,
+    ) -> Result<Self> {
         let mut stream = BitStream::from_buffer(Vec::<u8>::from(bytes));
+        stream.set_string_encoding(string_encoding);
 
-        match Self::try_from_stream(&mut stream, source) {
-            Ok(result) => result,
-            Err(_) => None,
+        match Self::try_from_stream(&mut stream, source)? {
+            Some(packet) => Ok(packet),
+            None => Err(DnetError::UnknownPacketId(bytes[0])),
         }
     }
 
@@ -618,7 +638,8 @@ impl Packet {
                 })
             }
             PacketTypes::GGCPacket => {
-                todo!()
+                let data = Self::read_remaining_bytes(stream);
+                Some(Self::GGCPacket { data })
             }
             PacketTypes::ConnectChallengeRequest => {
                 let sequence = stream.read_u32()?;
@@ -772,9 +793,7 @@ impl Packet {
             PacketTypes::MasterServerGamePingResponse => {
                 let (flags, key, session) = Self::read_flags_key_session(stream)?;
                 let address = Self::read_address_and_port(stream)?;
-                let buffer = Vec::from(&stream.as_bytes()[(stream.get_bit_pos() / 8)..]);
-                let packet = Packet::try_from_bytes(buffer.as_slice(), source)
-                    .unwrap_or_else(|| Self::Raw(buffer));
+                let packet = Self::read_relayed_packet(stream, source);
                 Some(Self::MasterServerGamePingResponse {
                     flags,
                     key,
@@ -796,9 +815,7 @@ impl Packet {
             PacketTypes::MasterServerGameInfoResponse => {
                 let (flags, key, session) = Self::read_flags_key_session(stream)?;
                 let address = Self::read_address_and_port(stream)?;
-                let buffer = Vec::from(&stream.as_bytes()[(stream.get_bit_pos() / 8)..]);
-                let packet = Packet::try_from_bytes(buffer.as_slice(), source)
-                    .unwrap_or_else(|| Self::Raw(buffer));
+                let packet = Self::read_relayed_packet(stream, source);
 
                 Some(Self::MasterServerGameInfoResponse {
                     flags,
@@ -879,21 +896,57 @@ impl Packet {
             }
             PacketTypes::MasterServerRelayHeartbeat => Some(Self::MasterServerRelayHeartbeat {}),
             _ => {
-                eprintln!(
-                    "Unknown packet type: {} {:?}",
-                    packet_type,
-                    stream.as_bytes()
-                );
+                warn!(packet_type, "Unknown packet type");
+                trace!(target: HEX_DUMP_TARGET, "{}", HexDump(stream.as_bytes()));
                 None
             }
         })
     }
 
+    // The rest of the stream is a whole packet from a game server, parsed with the same
+    // string settings. Kept raw if it can't be parsed.
+    fn read_relayed_packet(stream: &mut BitStream, source: PacketSource) -> Self {
+        let buffer = Self::read_remaining_bytes(stream);
+        let mut relayed = BitStream::from_buffer(buffer.clone());
+        relayed.set_string_encoding(stream.string_encoding());
+        relayed.set_huffman_table(stream.huffman_table().clone());
+        match Self::try_from_stream(&mut relayed, source) {
+            Ok(Some(packet)) => packet,
+            _ => Self::Raw(buffer),
+        }
+    }
+
+    // Everything from the current byte to the end of the stream
+    fn read_remaining_bytes(stream: &mut BitStream) -> Vec<u8> {
+        let start = stream.get_bit_pos().div_ceil(8);
+        let buffer = Vec::from(stream.as_bytes().get(start..).unwrap_or(&[]));
+        stream.set_bit_pos(stream.as_bytes().len() * 8);
+        buffer
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
+        if let Packet::Raw(raw_packet) = self {
+            return raw_packet;
+        }
         let mut out = BitStream::new();
+        self.write(&mut out);
+        out.into_bytes()
+    }
+
+    // into_bytes, but fails instead of going over MAX_PACKET_DATA_SIZE or sending
+    // strings that can't be encoded
+    pub fn try_into_bytes(self) -> Result<Vec<u8>> {
+        let mut out = BitStream::with_max_size(MAX_PACKET_DATA_SIZE);
+        self.write(&mut out);
+        out.try_into_bytes()
+    }
+
+    pub fn write(self, out: &mut BitStream) {
         match self {
             Packet::Raw(raw_packet) => {
-                return raw_packet;
+                for b in raw_packet {
+                    out.write_u8(b);
+                }
             }
             Packet::MasterServerGameTypesRequest {
                 flags,
@@ -901,7 +954,7 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::MasterServerGameTypesRequest);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::MasterServerGameTypesResponse {
                 flags,
@@ -911,7 +964,7 @@ impl Packet {
                 mission_types,
             } => {
                 out.write_u8(PacketTypes::MasterServerGameTypesResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
 
                 out.write_u8(game_types.len() as u8);
                 for game_type in game_types {
@@ -939,7 +992,7 @@ impl Packet {
                 buddy_list,
             } => {
                 out.write_u8(PacketTypes::MasterServerListRequest);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_u8(packet_index);
                 out.write_cstring(&game_type);
                 out.write_cstring(&mission_type);
@@ -968,13 +1021,13 @@ impl Packet {
                 servers,
             } => {
                 out.write_u8(PacketTypes::MasterServerListResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_u8(packet_index);
                 out.write_u8(packet_total);
 
                 out.write_u16(servers.len() as u16);
                 for server in servers {
-                    Self::write_address_and_port(&mut out, server);
+                    Self::write_address_and_port(out, server);
                 }
             }
             Packet::GameMasterInfoRequest {
@@ -983,7 +1036,7 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::GameMasterInfoRequest);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::GameMasterInfoResponse {
                 flags,
@@ -1001,7 +1054,7 @@ impl Packet {
                 guid_list,
             } => {
                 out.write_u8(PacketTypes::GameMasterInfoResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_cstring(&game_type);
                 out.write_cstring(&mission_type);
                 out.write_u8(max_players);
@@ -1025,7 +1078,7 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::GamePingRequest);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::GamePingResponse {
                 flags,
@@ -1038,12 +1091,12 @@ impl Packet {
                 name,
             } => {
                 out.write_u8(PacketTypes::GamePingResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
-                Self::write_maybe_compressed_string(&mut out, flags, &version_string);
+                Self::write_flags_key_session(out, flags, key, session);
+                Self::write_maybe_compressed_string(out, flags, &version_string);
                 out.write_u32(current_protocol_version);
                 out.write_u32(min_required_protocol_version);
                 out.write_u32(version);
-                Self::write_maybe_compressed_string(&mut out, flags, &name);
+                Self::write_maybe_compressed_string(out, flags, &name);
             }
             Packet::GameInfoRequest {
                 flags,
@@ -1051,7 +1104,7 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::GameInfoRequest);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::GameInfoResponse {
                 flags,
@@ -1069,16 +1122,16 @@ impl Packet {
                 server_info_query,
             } => {
                 out.write_u8(PacketTypes::GameInfoResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
-                Self::write_maybe_compressed_string(&mut out, flags, &game_type);
-                Self::write_maybe_compressed_string(&mut out, flags, &mission_type);
-                Self::write_maybe_compressed_string(&mut out, flags, &mission_name);
+                Self::write_flags_key_session(out, flags, key, session);
+                Self::write_maybe_compressed_string(out, flags, &game_type);
+                Self::write_maybe_compressed_string(out, flags, &mission_type);
+                Self::write_maybe_compressed_string(out, flags, &mission_name);
                 out.write_u8(filter_flag);
                 out.write_u8(player_count);
                 out.write_u8(max_players);
                 out.write_u8(bot_count);
                 out.write_u16(cpu_speed);
-                Self::write_maybe_compressed_string(&mut out, flags, &server_info);
+                Self::write_maybe_compressed_string(out, flags, &server_info);
                 out.write_long_cstring(&server_info_query);
             }
             Packet::GameHeartbeat {
@@ -1087,11 +1140,13 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::GameHeartbeat);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
-            Packet::GGCPacket {} => {
+            Packet::GGCPacket { data } => {
                 out.write_u8(PacketTypes::GGCPacket);
-                todo!();
+                for b in data {
+                    out.write_u8(b);
+                }
             }
             Packet::ConnectChallengeRequest { sequence } => {
                 out.write_u8(PacketTypes::ConnectChallengeRequest);
@@ -1182,7 +1237,7 @@ impl Packet {
             }
             Packet::MasterServerRequestArrangedConnection { address } => {
                 out.write_u8(PacketTypes::MasterServerRequestArrangedConnection);
-                Self::write_address_and_port(&mut out, address);
+                Self::write_address_and_port(out, address);
             }
             Packet::MasterServerClientRequestedArrangedConnection {
                 flags,
@@ -1192,12 +1247,12 @@ impl Packet {
                 possible_addresses,
             } => {
                 out.write_u8(PacketTypes::MasterServerClientRequestedArrangedConnection);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_u16(client_id);
 
                 out.write_u8(possible_addresses.len() as u8);
                 for address in possible_addresses {
-                    Self::write_address_and_port(&mut out, address);
+                    Self::write_address_and_port(out, address);
                 }
             }
             Packet::MasterServerAcceptArrangedConnection { client_id } => {
@@ -1211,11 +1266,11 @@ impl Packet {
                 possible_addresses,
             } => {
                 out.write_u8(PacketTypes::MasterServerArrangedConnectionAccepted);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
 
                 out.write_u8(possible_addresses.len() as u8);
                 for address in possible_addresses {
-                    Self::write_address_and_port(&mut out, address);
+                    Self::write_address_and_port(out, address);
                 }
             }
             Packet::MasterServerRejectArrangedConnection { client_id } => {
@@ -1229,7 +1284,7 @@ impl Packet {
                 reason,
             } => {
                 out.write_u8(PacketTypes::MasterServerArrangedConnectionRejected);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_u8(reason);
             }
             Packet::MasterServerGamePingRequest {
@@ -1240,8 +1295,8 @@ impl Packet {
             } => {
                 out.write_u8(PacketTypes::MasterServerGamePingRequest);
                 // Backwards because fuck me that's why
-                Self::write_address_and_port(&mut out, address);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_address_and_port(out, address);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::MasterServerGamePingResponse {
                 flags,
@@ -1251,11 +1306,10 @@ impl Packet {
                 packet,
             } => {
                 out.write_u8(PacketTypes::MasterServerGamePingResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
-                Self::write_address_and_port(&mut out, address);
-                for b in packet.into_bytes() {
-                    out.write_u8(b);
-                }
+                Self::write_flags_key_session(out, flags, key, session);
+                Self::write_address_and_port(out, address);
+                // Byte aligned here, so the same as appending its bytes
+                packet.write(out);
             }
             Packet::MasterServerGameInfoRequest {
                 address,
@@ -1264,8 +1318,8 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::MasterServerGameInfoRequest);
-                Self::write_address_and_port(&mut out, address);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_address_and_port(out, address);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::MasterServerGameInfoResponse {
                 flags,
@@ -1275,15 +1329,14 @@ impl Packet {
                 packet,
             } => {
                 out.write_u8(PacketTypes::MasterServerGameInfoResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
-                Self::write_address_and_port(&mut out, address);
-                for b in packet.into_bytes() {
-                    out.write_u8(b);
-                }
+                Self::write_flags_key_session(out, flags, key, session);
+                Self::write_address_and_port(out, address);
+                // Byte aligned here, so the same as appending its bytes
+                packet.write(out);
             }
             Packet::MasterServerRelayRequestToMaster { address } => {
                 out.write_u8(PacketTypes::MasterServerRelayRequest);
-                Self::write_address_and_port(&mut out, address);
+                Self::write_address_and_port(out, address);
             }
             Packet::MasterServerRelayRequestToRelay {
                 relay_id,
@@ -1292,8 +1345,8 @@ impl Packet {
             } => {
                 out.write_u8(PacketTypes::MasterServerRelayRequest);
                 out.write_u32(relay_id);
-                Self::write_address_and_port(&mut out, server_addr);
-                Self::write_address(&mut out, client_addr);
+                Self::write_address_and_port(out, server_addr);
+                Self::write_address(out, client_addr);
             }
             Packet::MasterServerRelayResponseFromMaster {
                 flags,
@@ -1303,9 +1356,9 @@ impl Packet {
                 address,
             } => {
                 out.write_u8(PacketTypes::MasterServerRelayResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 out.write_flag(is_host);
-                Self::write_address_and_port(&mut out, address);
+                Self::write_address_and_port(out, address);
             }
             Packet::MasterServerRelayResponseFromRelay {
                 relay_id,
@@ -1324,7 +1377,7 @@ impl Packet {
                 session,
             } => {
                 out.write_u8(PacketTypes::MasterServerRelayReady);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
             }
             Packet::MasterServerJoinInvite { invite_code } => {
                 out.write_u8(PacketTypes::MasterServerJoinInvite);
@@ -1337,11 +1390,11 @@ impl Packet {
                 address,
             } => {
                 out.write_u8(PacketTypes::MasterServerJoinInviteResponse);
-                Self::write_flags_key_session(&mut out, flags, key, session);
+                Self::write_flags_key_session(out, flags, key, session);
                 match address {
                     Some(address) => {
                         out.write_u8(1);
-                        Self::write_address_and_port(&mut out, address);
+                        Self::write_address_and_port(out, address);
                     }
                     None => {
                         out.write_u8(0);
@@ -1352,7 +1405,5 @@ impl Packet {
                 out.write_u8(PacketTypes::MasterServerRelayHeartbeat);
             }
         }
-
-        out.into_bytes()
     }
 }
@@ -0,0 +1,241 @@
+use dnet::{
+    AngAxisF, BitStream, DnetError, MatrixF, Point3F, QuatF, DEFAULT_COMPRESSED_POINT_SCALE,
+};
+use proptest::prelude::*;
+use std::f32::consts::PI;
+
+// Something after every value, so a reader that takes the wrong number of bits is
+// caught by the marker coming out wrong
+const MARKER: u8 = 0xA5;
+// Floats this close to 0, 0.5 or 1 are snapped to them
+const POINT_EPSILON: f32 = 0.0001;
+
+fn round_trip<W, R, T>(write: W, read: R) -> (T, T)
+where
+    W: FnOnce(&mut BitStream) -> T,
+    R: FnOnce(&mut BitStream) -> dnet::error::Result<T>,
+{
+    let mut stream = BitStream::new();
+    // Start off a byte boundary too
+    stream.write_flag(true);
+    let written = write(&mut stream);
+    stream.write_u8(MARKER);
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert!(stream.read_flag().unwrap());
+    let read = read(&mut stream).unwrap();
+    assert_eq!(stream.read_u8().unwrap(), MARKER);
+    (written, read)
+}
+
+fn unit_vector() -> impl Strategy<Value = Point3F> {
+    (-1.0f32..1.0, -1.0f32..1.0, -1.0f32..1.0)
+        .prop_map(Point3F::from)
+        .prop_filter("too short to normalize", |v| v.len() > 0.1)
+        .prop_map(|v| v.normalized())
+}
+
+fn quat() -> impl Strategy<Value = QuatF> {
+    (unit_vector(), -PI..PI).prop_map(|(axis, angle)| QuatF::from(AngAxisF::new(axis, angle)))
+}
+
+// Most a unit vector can be off by when its angles are sent with `bits` bits,
+// including snapping to the ends of the range
+fn angle_error(bits: usize) -> f32 {
+    2.0 * PI / (1u32 << bits) as f32 + 2.0 * PI * POINT_EPSILON
+}
+
+fn distance(a: Point3F, b: Point3F) -> f32 {
+    (a - b).len()
+}
+
+// q and -q are the same rotation
+fn quat_distance(a: QuatF, b: QuatF) -> f32 {
+    let dot = a.x * b.x + a.y * b.y + a.z * b.z + a.w * b.w;
+    1.0 - dot.abs()
+}
+
+proptest! {
+    #[test]
+    fn float_zero_to_one(value in 0.0f32..=1.0, bits in 2usize..24) {
+        let (written, read) = round_trip(
+            |s| s.write_float_zero_to_one(value, bits),
+            |s| s.read_float_zero_to_one(bits),
+        );
+        prop_assert_eq!(written, read);
+        let step = 1.0 / ((1u32 << bits) - 1) as f32;
+        prop_assert!((read - value).abs() <= step.max(POINT_EPSILON));
+    }
+
+    #[test]
+    fn signed_float(value in -1.0f32..=1.0, bits in 2usize..24) {
+        let (written, read) = round_trip(
+            |s| s.write_signed_float_neg_one_to_one(value, bits),
+            |s| s.read_signed_float_neg_one_to_one(bits),
+        );
+        prop_assert_eq!(written, read);
+        let step = 2.0 / ((1u32 << bits) - 1) as f32;
+        prop_assert!((read - value).abs() <= step.max(2.0 * POINT_EPSILON));
+    }
+
+    #[test]
+    fn f32_raw(value in any::<f32>().prop_filter("NaN", |v| !v.is_nan())) {
+        let (written, read) = round_trip(|s| s.write_f32(value), |s| s.read_f32());
+        prop_assert_eq!(written, value);
+        prop_assert_eq!(read, value);
+    }
+
+    #[test]
+    fn point(x in -1e6f32..1e6, y in -1e6f32..1e6, z in -1e6f32..1e6) {
+        let value = Point3F::new(x, y, z);
+        let (written, read) = round_trip(|s| s.write_point(value), |s| s.read_point());
+        prop_assert_eq!(written, value);
+        prop_assert_eq!(read, value);
+    }
+
+    #[test]
+    fn normal_vector(value in unit_vector(), bits in 6usize..16) {
+        let (written, read) = round_trip(
+            |s| s.write_normal_vector(value, bits),
+            |s| s.read_normal_vector(bits),
+        );
+        prop_assert_eq!(written, read);
+        prop_assert!(distance(read, value) <= angle_error(bits));
+    }
+
+    #[test]
+    fn normal_vector_z(
+        value in unit_vector(),
+        angle_bits in 6usize..16,
+        z_bits in 6usize..16,
+    ) {
+        let (written, read) = round_trip(
+            |s| s.write_normal_vector_z(value, angle_bits, z_bits),
+            |s| s.read_normal_vector_z(angle_bits, z_bits),
+        );
+        prop_assert_eq!(written, read);
+        // Near the poles everything snaps to straight up or down
+        let pole_error = (2.0 / z_bits as f32).sqrt();
+        let error = angle_error(angle_bits) + angle_error(z_bits);
+        prop_assert!(distance(read, value) <= error.max(pole_error));
+    }
+
+    #[test]
+    fn vector(
+        direction in unit_vector(),
+        magnitude in 0.0f32..200.0,
+        magnitude_bits in 8usize..16,
+        normal_bits in 8usize..16,
+    ) {
+        let max_magnitude = 100.0;
+        let value = direction * magnitude;
+        let (written, read) = round_trip(
+            |s| s.write_vector(value, max_magnitude, magnitude_bits, normal_bits),
+            |s| s.read_vector(max_magnitude, magnitude_bits, normal_bits),
+        );
+        prop_assert_eq!(written, read);
+
+        let magnitude_error = if magnitude < max_magnitude {
+            max_magnitude / ((1u32 << magnitude_bits) - 1) as f32
+        } else {
+            0.0
+        };
+        let normal_error = angle_error(normal_bits);
+        let error = magnitude_error + normal_error * magnitude + 0.001;
+        prop_assert!(distance(read, value) <= error);
+    }
+
+    #[test]
+    fn compressed_point(x in -6000f32..6000.0, y in -6000f32..6000.0, z in -6000f32..6000.0) {
+        let value = Point3F::new(x, y, z);
+        let scale = DEFAULT_COMPRESSED_POINT_SCALE;
+        let (written, read) = round_trip(
+            |s| s.write_compressed_point(value, scale),
+            |s| s.read_compressed_point(scale),
+        );
+        prop_assert_eq!(written, read);
+        // Far away points are sent whole, the rest are truncated to multiples of scale
+        prop_assert!(distance(read, value) <= 2.0 * scale);
+    }
+
+    #[test]
+    fn quat_compressed(value in quat(), bits in 8usize..16) {
+        let (written, read) = round_trip(
+            |s| s.write_quat(value, bits),
+            |s| s.read_quat(bits),
+        );
+        prop_assert_eq!(written, read);
+        prop_assert!(quat_distance(read, value) <= 8.0 / (1u32 << bits) as f32);
+    }
+
+    #[test]
+    fn affine_transform(
+        rotation in quat(),
+        x in -1e4f32..1e4,
+        y in -1e4f32..1e4,
+        z in -1e4f32..1e4,
+    ) {
+        let position = Point3F::new(x, y, z);
+        let value = MatrixF::from_rotation_position(rotation, position);
+        let (written, read) = round_trip(
+            |s| s.write_affine_transform(&value),
+            |s| s.read_affine_transform(),
+        );
+        prop_assert_eq!(written, read);
+        prop_assert_eq!(read.position(), position);
+        prop_assert!(quat_distance(read.rotation(), rotation) <= 1e-4);
+    }
+}
+
+#[test]
+fn zero_vector_is_one_bit() {
+    let mut stream = BitStream::new();
+    let written = stream.write_vector(Point3F::default(), 100.0, 8, 8);
+    assert_eq!(written, Point3F::default());
+    assert_eq!(stream.get_bit_pos(), 1);
+}
+
+#[test]
+fn big_vector_is_sent_raw() {
+    let value = Point3F::new(0.0, 1000.0, 0.0);
+    let (written, read) = round_trip(
+        |s| s.write_vector(value, 100.0, 8, 8),
+        |s| s.read_vector(100.0, 8, 8),
+    );
+    assert_eq!(written, read);
+    assert!(distance(read, value) < 1.0);
+}
+
+#[test]
+fn signed_int_bit_counts() {
+    for bit_count in [0, 34, usize::MAX] {
+        let mut stream = BitStream::new();
+        assert!(matches!(
+            stream.try_write_signed_int(5, bit_count),
+            Err(DnetError::InvalidBitCount(bits)) if bits == bit_count
+        ));
+        assert!(stream.error().is_none());
+        assert_eq!(stream.write_signed_int(5, bit_count), 0);
+        assert_eq!(stream.get_bit_pos(), 0);
+        assert!(matches!(
+            stream.try_into_bytes(),
+            Err(DnetError::InvalidBitCount(_))
+        ));
+
+        let mut stream = BitStream::from_buffer(vec![0xFF; 8]);
+        assert!(stream.read_signed_int(bit_count).is_err());
+    }
+
+    // Just the sign, and as much as 32 bits of magnitude
+    let (written, read) = round_trip(
+        |s| {
+            assert_eq!(s.write_signed_int(-5, 1), 0);
+            s.try_write_signed_int(i32::MIN, 33).unwrap()
+        },
+        |s| {
+            assert_eq!(s.read_signed_int(1)?, 0);
+            s.read_signed_int(33)
+        },
+    );
+    assert_eq!((written, read), (i32::MIN, i32::MIN));
+}
@@ -0,0 +1,181 @@
+use dnet::{Packet, PacketSource, ServerBrowser};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout, Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Default)]
+struct Behaviour {
+    // Pings ignored before answering one
+    dropped_pings: usize,
+    ping_delay: Duration,
+    wrong_key: bool,
+    silent: bool,
+}
+
+async fn fake_server(name: &str, behaviour: Behaviour) -> (Ipv4Addr, u16) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = match socket.local_addr().unwrap() {
+        SocketAddr::V4(address) => (*address.ip(), address.port()),
+        SocketAddr::V6(_) => unreachable!(),
+    };
+    let name = name.to_string();
+
+    tokio::spawn(async move {
+        let mut dropped_pings = 0;
+        loop {
+            let mut buf = [0u8; 1500];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            if behaviour.silent {
+                continue;
+            }
+            let packet = Packet::parse_bytes(&buf[0..len], PacketSource::GameToGame).unwrap();
+            let response = match packet {
+                Packet::GamePingRequest {
+                    flags,
+                    key,
+                    session,
+                } => {
+                    if dropped_pings < behaviour.dropped_pings {
+                        dropped_pings += 1;
+                        continue;
+                    }
+                    sleep(behaviour.ping_delay).await;
+                    Packet::GamePingResponse {
+                        flags,
+                        key: if behaviour.wrong_key { key ^ 1 } else { key },
+                        session,
+                        version_string: "VER1".to_string(),
+                        current_protocol_version: 12,
+                        min_required_protocol_version: 12,
+                        version: 100,
+                        name: name.clone(),
+                    }
+                }
+                Packet::GameInfoRequest {
+                    flags,
+                    key,
+                    session,
+                } => Packet::GameInfoResponse {
+                    flags,
+                    key,
+                    session,
+                    game_type: "Test".to_string(),
+                    mission_type: "Race".to_string(),
+                    mission_name: "Beginner".to_string(),
+                    filter_flag: 0,
+                    player_count: 3,
+                    max_players: 8,
+                    bot_count: 0,
+                    cpu_speed: 3000,
+                    server_info: format!("{} info", name),
+                    server_info_query: String::new(),
+                },
+                other => panic!("Unexpected packet {:?}", other),
+            };
+            socket.send_to(&response.into_bytes(), from).await.unwrap();
+        }
+    });
+
+    address
+}
+
+#[tokio::test]
+async fn sweeps_servers() {
+    let quick = fake_server("Quick", Behaviour::default()).await;
+    let slow = fake_server(
+        "Slow",
+        Behaviour {
+            ping_delay: Duration::from_millis(50),
+            ..Default::default()
+        },
+    )
+    .await;
+    let lossy = fake_server(
+        "Lossy",
+        Behaviour {
+            dropped_pings: 2,
+            ..Default::default()
+        },
+    )
+    .await;
+    let silent = fake_server(
+        "Silent",
+        Behaviour {
+            silent: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let wrong_key = fake_server(
+        "Wrong key",
+        Behaviour {
+            wrong_key: true,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let browser = ServerBrowser::sweep_with_timeout(
+        "127.0.0.1:0",
+        vec![quick, slow, lossy, silent, wrong_key, quick],
+        Duration::from_millis(100),
+        3,
+    )
+    .await
+    .unwrap();
+    let mut servers = timeout(TIMEOUT, browser.collect()).await.unwrap().unwrap();
+    servers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let names = servers.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Lossy", "Quick", "Slow"]);
+
+    let slow_info = &servers[2];
+    assert_eq!(slow_info.address, slow);
+    assert!(slow_info.ping >= Duration::from_millis(50));
+    assert_eq!(slow_info.server_info, "Slow info");
+    assert_eq!(slow_info.mission_name, "Beginner");
+    assert_eq!(slow_info.player_count, 3);
+    assert_eq!(slow_info.version_string, "VER1");
+}
+
+#[tokio::test]
+async fn empty_sweep_finishes() {
+    let browser = ServerBrowser::sweep("127.0.0.1:0", vec![]).await.unwrap();
+    assert!(timeout(TIMEOUT, browser.collect())
+        .await
+        .unwrap()
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn carries_on_past_closed_ports() {
+    let closed = {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        match socket.local_addr().unwrap() {
+            SocketAddr::V4(address) => (*address.ip(), address.port()),
+            SocketAddr::V6(_) => unreachable!(),
+        }
+    };
+    let quick = fake_server("Quick", Behaviour::default()).await;
+
+    let mut browser = ServerBrowser::sweep_with_timeout(
+        "127.0.0.1:0",
+        vec![closed, quick],
+        Duration::from_millis(50),
+        1,
+    )
+    .await
+    .unwrap();
+    let server = timeout(TIMEOUT, browser.next()).await.unwrap().unwrap();
+    assert_eq!(server.map(|server| server.name), Some("Quick".to_string()));
+    assert!(timeout(TIMEOUT, browser.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .is_none());
+    // Still nothing once the sweep has been waited on
+    assert!(browser.next().await.unwrap().is_none());
+}
@@ -0,0 +1,341 @@
+use dnet::{BitStream, MatrixF, Point3F, QuatF};
+
+// Byte vectors for the BitStream primitives, as Torque's BitStream lays them out:
+// bits are packed least significant first, writeInt sends the low bitCount bits of
+// the value, writeSignedInt is a sign flag then the magnitude in bitCount - 1 bits,
+// and writeRangedU32 uses just enough bits for the range.
+//
+// These were not captured from a running Torque build. They were worked out by hand
+// (with a calculator for the float bits) from core/bitStream.cc and math/mathIO.h,
+// never with this crate, so they check that we match our reading of that code and not
+// just that we agree with ourselves. Bytes dumped from the engine should replace them
+// if anyone has a build to get them from. Both sides are checked: writing has to give
+// these exact bytes, and reading them has to give the values back.
+//
+// String vectors live in tests/huffman.rs, and the float encodings are also covered by
+// the round trips in tests/bitstream.rs.
+
+fn check<W, R>(expected: &[u8], bit_count: usize, write: W, read: R)
+where
+    W: FnOnce(&mut BitStream),
+    R: FnOnce(&mut BitStream) -> dnet::error::Result<()>,
+{
+    let mut stream = BitStream::new();
+    write(&mut stream);
+    assert_eq!(stream.get_bit_pos(), bit_count);
+    assert_eq!(stream.into_bytes(), expected);
+
+    let mut stream = BitStream::from_buffer(expected.to_vec());
+    read(&mut stream).unwrap();
+    assert_eq!(stream.get_bit_pos(), bit_count);
+}
+
+#[test]
+fn bytes() {
+    check(
+        &[0x12, 0x56, 0x34, 0xDE, 0xBC, 0x9A, 0x78],
+        56,
+        |s| {
+            s.write_u8(0x12);
+            s.write_u16(0x3456);
+            s.write_u32(0x789ABCDE);
+        },
+        |s| {
+            assert_eq!(s.read_u8()?, 0x12);
+            assert_eq!(s.read_u16()?, 0x3456);
+            assert_eq!(s.read_u32()?, 0x789ABCDE);
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn flags() {
+    let flags = [true, false, true, true, false, false, false, true, true];
+    check(
+        &[0x8D, 0x01],
+        9,
+        |s| {
+            for flag in flags.iter() {
+                s.write_flag(*flag);
+            }
+        },
+        |s| {
+            for flag in flags.iter() {
+                assert_eq!(s.read_flag()?, *flag);
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn ints() {
+    let ints = [(5, 3), (0x1F, 5), (0x2AB, 10), (0x12345, 17)];
+    check(
+        &[0xFD, 0xAB, 0x16, 0x8D, 0x04],
+        35,
+        |s| {
+            for (value, bits) in ints.iter() {
+                s.write_int(*value, *bits);
+            }
+        },
+        |s| {
+            for (value, bits) in ints.iter() {
+                assert_eq!(s.read_int(*bits)?, *value);
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn unaligned_bytes() {
+    check(
+        &[0xFF, 0x69, 0x24, 0x00],
+        25,
+        |s| {
+            s.write_flag(true);
+            s.write_u8(0xFF);
+            s.write_u16(0x1234);
+        },
+        |s| {
+            assert!(s.read_flag()?);
+            assert_eq!(s.read_u8()?, 0xFF);
+            assert_eq!(s.read_u16()?, 0x1234);
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn signed_ints() {
+    let ints = [(-5, 8), (5, 8), (-1, 4), (3, 4), (-100, 16), (32767, 16)];
+    check(
+        &[0x0B, 0x0A, 0x63, 0xC9, 0x00, 0xFE, 0xFF],
+        56,
+        |s| {
+            for (value, bits) in ints.iter() {
+                assert_eq!(s.write_signed_int(*value, *bits), *value);
+            }
+        },
+        |s| {
+            for (value, bits) in ints.iter() {
+                assert_eq!(s.read_signed_int(*bits)?, *value);
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn signed_int_too_big() {
+    // Only the low bitCount - 1 bits of the magnitude make it
+    let mut stream = BitStream::new();
+    assert_eq!(stream.write_signed_int(-0x1FF, 8), -0x7F);
+    assert_eq!(stream.write_signed_int(i32::MIN, 32), 0);
+    assert_eq!(stream.get_bit_pos(), 40);
+}
+
+#[test]
+fn ranged_u32() {
+    let values = [
+        (7, 5, 10),
+        (15, 0, 15),
+        (1000, 1000, 1000),
+        (0xABCDEF01, 0, u32::MAX),
+    ];
+    check(
+        &[0xFA, 0x80, 0xF7, 0xE6, 0x55],
+        39,
+        |s| {
+            for (value, start, end) in values.iter() {
+                s.write_ranged_u32(*value, *start, *end);
+            }
+        },
+        |s| {
+            for (value, start, end) in values.iter() {
+                assert_eq!(s.read_ranged_u32(*start, *end)?, *value);
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn cussed_u32() {
+    let values = [0, 9, 200, 40000, 0x123456, 0xDEADBEEF];
+    check(
+        &[
+            0x4D, 0x22, 0x23, 0x10, 0x27, 0xB4, 0xA2, 0x91, 0x00, 0xEF, 0xBE, 0xAD, 0xDE,
+        ],
+        104,
+        |s| {
+            for value in values.iter() {
+                s.write_cussed_u32(*value);
+            }
+        },
+        |s| {
+            for value in values.iter() {
+                assert_eq!(s.read_cussed_u32()?, *value);
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn f32_raw() {
+    check(
+        &[0x00, 0x00, 0xC0, 0x3F, 0x00, 0x00, 0x00, 0x80],
+        64,
+        |s| {
+            s.write_f32(1.5);
+            s.write_f32(-0.0);
+        },
+        |s| {
+            assert_eq!(s.read_f32()?, 1.5);
+            assert_eq!(s.read_f32()?.to_bits(), (-0.0f32).to_bits());
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn compressed_points() {
+    let points = [
+        // 16 bits per axis
+        (
+            Point3F::new(100.0, -200.0, 50.0),
+            &[0x20, 0x03, 0x44, 0x06, 0x90, 0x01, 0x00][..],
+            50,
+        ),
+        // 18 bits per axis
+        (
+            Point3F::new(40000.0, 0.0, -3.0),
+            &[0x01, 0xE2, 0x04, 0x00, 0xC0, 0x01, 0x00][..],
+            56,
+        ),
+        // Too far away, so sent whole
+        (
+            Point3F::new(1e6, 0.0, 0.0),
+            &[
+                0x03, 0x90, 0xD0, 0x25, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ][..],
+            98,
+        ),
+    ];
+
+    for (point, bytes, bit_count) in points.iter() {
+        check(
+            bytes,
+            *bit_count,
+            |s| assert_eq!(s.write_compressed_point(*point, 1.0), *point),
+            |s| {
+                assert_eq!(s.read_compressed_point(1.0)?, *point);
+                Ok(())
+            },
+        );
+    }
+}
+
+#[test]
+fn cstrings() {
+    check(
+        &[0x03, 0x61, 0x62, 0x63, 0x02, 0x00, 0x68, 0x69],
+        64,
+        |s| {
+            s.write_cstring(&"abc".to_string());
+            s.write_long_cstring(&"hi".to_string());
+        },
+        |s| {
+            assert_eq!(s.read_cstring()?, "abc");
+            assert_eq!(s.read_long_cstring()?, "hi");
+            Ok(())
+        },
+    );
+}
+
+fn assert_near(a: f32, b: f32) {
+    assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+}
+
+#[test]
+fn normal_vectors() {
+    // Phi as a signed float in bitCount + 1 bits, then theta in bitCount. Straight
+    // along y is the 0.5 special case for both, straight along -x has phi at 0.25.
+    let normals = [Point3F::new(0.0, 1.0, 0.0), Point3F::new(-1.0, 0.0, 0.0)];
+    check(
+        &[0x00, 0x01, 0x01, 0x01, 0x02],
+        34,
+        |s| {
+            assert_eq!(s.write_normal_vector(normals[0], 8), normals[0]);
+            let sent = s.write_normal_vector(normals[1], 8);
+            assert!((sent - normals[1]).len() < 0.01);
+        },
+        |s| {
+            assert_eq!(s.read_normal_vector(8)?, normals[0]);
+            assert!((s.read_normal_vector(8)? - normals[1]).len() < 0.01);
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn quats() {
+    // The index of the biggest component in 2 bits, then the other three times sqrt(2)
+    // as signed floats, flipped if the biggest one is negative
+    let quats = [QuatF::identity(), QuatF::new(0.5, 0.5, 0.5, -0.5)];
+    check(
+        &[0x03, 0x04, 0x08, 0x10, 0xDA, 0xB4, 0x97, 0x00],
+        58,
+        |s| {
+            for quat in quats.iter() {
+                s.write_quat(*quat, 9);
+            }
+        },
+        |s| {
+            assert_eq!(s.read_quat(9)?, quats[0]);
+            let read = s.read_quat(9)?;
+            assert_near(read.x, 0.5);
+            assert_near(read.y, 0.5);
+            assert_near(read.z, 0.5);
+            assert_near(read.w, -0.5);
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn affine_transforms() {
+    // The position, then x, y and z of the rotation as raw floats, and w's sign
+    let transforms = [
+        (
+            MatrixF::from_rotation_position(QuatF::identity(), Point3F::new(1.0, 2.0, -3.0)),
+            &[
+                0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x40, 0xC0, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ][..],
+        ),
+        // Half a turn around z
+        (
+            MatrixF::from_rotation_position(QuatF::new(0.0, 0.0, 1.0, 0.0), Point3F::default()),
+            &[
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3F, 0x00,
+            ][..],
+        ),
+    ];
+
+    for (transform, bytes) in transforms.iter() {
+        check(
+            bytes,
+            193,
+            |s| assert_eq!(s.write_affine_transform(transform), *transform),
+            |s| {
+                assert_eq!(s.read_affine_transform()?, *transform);
+                Ok(())
+            },
+        );
+    }
+}
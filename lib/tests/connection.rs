@@ -0,0 +1,110 @@
+use dnet::error::Result;
+use dnet::{
+    BitStream, DNet, DisconnectReason, DnetError, GameConnection, NetEvent, NetPacketType, Packet,
+    PacketSource, ServerPacketHeader,
+};
+use tokio::net::UdpSocket;
+
+#[derive(Debug)]
+struct UnknownEvent;
+
+impl NetEvent for UnknownEvent {
+    fn class_name(&self) -> &'static str {
+        "UnknownEvent"
+    }
+
+    fn pack(&self, _stream: &mut BitStream) {}
+
+    fn unpack(&mut self, _connection: &GameConnection, _stream: &mut BitStream) -> Result<()> {
+        Ok(())
+    }
+
+    fn process(&mut self, _connection: &mut GameConnection) -> Result<()> {
+        Ok(())
+    }
+}
+
+async fn connection() -> GameConnection {
+    GameConnection::connect("127.0.0.1:0", "127.0.0.1:9", 1)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn rejects_unregistered_events() {
+    let mut connection = connection().await;
+    assert!(matches!(
+        connection.post_event(Box::new(UnknownEvent)),
+        Err(DnetError::UnregisteredClass(name)) if name == "UnknownEvent"
+    ));
+    assert!(connection.tag_string("hello").unwrap().starts_with('\x01'));
+}
+
+#[tokio::test]
+async fn drops_malformed_packets() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut connection = GameConnection::connect("127.0.0.1:0", server.local_addr().unwrap(), 1)
+        .await
+        .unwrap();
+    connection.send_raw_packet().await.unwrap();
+    let mut buf = [0u8; 1500];
+    let (_, client) = server.recv_from(&mut buf).await.unwrap();
+
+    // A disconnect packet cut off halfway through its sequence number
+    let disconnect = Packet::Disconnect {
+        sequence: 1,
+        reason: "Bye".to_string(),
+    }
+    .into_bytes();
+    server.send_to(&disconnect[0..3], client).await.unwrap();
+    assert!(connection.read_packet().await.unwrap().is_none());
+    assert!(!connection.is_disconnected());
+
+    server.send_to(&disconnect, client).await.unwrap();
+    assert!(matches!(
+        connection.read_packet().await.unwrap(),
+        Some(Packet::Disconnect { .. })
+    ));
+    assert_eq!(
+        connection.disconnect_reason(),
+        Some(&DisconnectReason::Remote("Bye".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn disconnects_on_invalid_data_packets() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut connection = GameConnection::connect("127.0.0.1:0", server.local_addr().unwrap(), 1)
+        .await
+        .unwrap();
+
+    // A data packet from the server with an ordered event that stops halfway through
+    let mut server_dnet = DNet::new(1);
+    let mut packet = BitStream::new();
+    server_dnet
+        .build_send_packet_header(&mut packet, NetPacketType::DataPacket)
+        .unwrap();
+    packet.write_flag(false);
+    packet.write_flag(false);
+    ServerPacketHeader::default().write(&mut packet, |_, _| {});
+    packet.write_flag(false);
+    packet.write_flag(true);
+    packet.write_flag(false);
+    packet.write_int(0, 7);
+
+    let result = connection
+        .process_raw_packet(BitStream::from_buffer(packet.into_bytes()))
+        .await;
+    assert!(matches!(result, Err(DnetError::EndOfStream { .. })));
+    assert_eq!(
+        connection.disconnect_reason(),
+        Some(&DisconnectReason::Local("Invalid packet.".to_string()))
+    );
+
+    let mut buf = [0u8; 1500];
+    let len = server.recv(&mut buf).await.unwrap();
+    assert!(matches!(
+        Packet::parse_bytes(&buf[0..len], PacketSource::GameToGame).unwrap(),
+        Packet::Disconnect { sequence: 1, reason } if reason == "Invalid packet."
+    ));
+}
@@ -0,0 +1,126 @@
+use dnet::{BitStream, DNet, DNetResult, DisconnectReason, NetPacketType};
+use std::time::{Duration, Instant};
+
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn packet_type(packet: &[u8]) -> u32 {
+    let mut stream = BitStream::from_buffer(packet.to_vec());
+    stream.read_int(1 + 1 + 9 + 9).unwrap();
+    stream.read_int(2).unwrap()
+}
+
+fn deliver(packet: Vec<u8>, to: &mut DNet) -> Vec<DNetResult> {
+    to.process_raw_packet(BitStream::from_buffer(packet))
+        .unwrap()
+}
+
+fn expect_ping(result: Option<DNetResult>) -> Vec<u8> {
+    match result {
+        Some(DNetResult::SendPacket(stream)) => stream.into_bytes(),
+        _ => panic!("Expected a ping"),
+    }
+}
+
+#[test]
+fn pings_then_times_out() {
+    let mut dnet = DNet::new(1);
+    dnet.set_ping_timeout(PING_TIMEOUT, 2);
+    let start = Instant::now();
+
+    // Starts the clock
+    assert!(dnet.check_timeout(start).unwrap().is_none());
+    assert!(dnet.check_timeout(start + PING_TIMEOUT).unwrap().is_none());
+
+    let mut now = start;
+    for _ in 0..2 {
+        now += PING_TIMEOUT + Duration::from_millis(1);
+        let ping = expect_ping(dnet.check_timeout(now).unwrap());
+        assert_eq!(packet_type(&ping), NetPacketType::PingPacket as u32);
+        assert!(dnet.check_timeout(now + PING_TIMEOUT).unwrap().is_none());
+    }
+
+    now += PING_TIMEOUT + Duration::from_millis(1);
+    match dnet.check_timeout(now).unwrap() {
+        Some(DNetResult::Disconnect(reason)) => assert_eq!(
+            reason,
+            DisconnectReason::TimedOut {
+                unanswered_pings: 2
+            }
+        ),
+        _ => panic!("Expected a timeout"),
+    }
+}
+
+#[test]
+fn keep_alive_stops_pinging() {
+    let mut dnet = DNet::new(1);
+    dnet.set_ping_timeout(PING_TIMEOUT, 1);
+    let start = Instant::now();
+    dnet.check_timeout(start).unwrap();
+    let now = start + PING_TIMEOUT * 2;
+    expect_ping(dnet.check_timeout(now).unwrap());
+
+    // Would have timed out, but we heard back
+    dnet.keep_alive();
+    let later = now + PING_TIMEOUT * 2;
+    assert!(dnet.check_timeout(later).unwrap().is_none());
+    expect_ping(dnet.check_timeout(later + PING_TIMEOUT * 2).unwrap());
+}
+
+#[test]
+fn answers_pings() {
+    let mut client = DNet::new(1);
+    let mut server = DNet::new(1);
+    let start = Instant::now();
+    client.check_timeout(start).unwrap();
+    let ping = expect_ping(client.check_timeout(start + PING_TIMEOUT * 6).unwrap());
+
+    // The server acks the ping, and hearing anything at all keeps it alive
+    let mut results = deliver(ping, &mut server);
+    assert_eq!(results.len(), 2);
+    assert!(matches!(results.pop(), Some(DNetResult::KeepAlive)));
+    let ack = match results.pop() {
+        Some(DNetResult::SendPacket(ack)) => ack.into_bytes(),
+        _ => panic!("Expected an ack"),
+    };
+    assert_eq!(packet_type(&ack), NetPacketType::AckPacket as u32);
+
+    let results = deliver(ack, &mut client);
+    assert!(matches!(results[..], [DNetResult::KeepAlive]));
+}
+
+#[test]
+fn notifies_data_packets() {
+    let mut client = DNet::new(1);
+    let mut server = DNet::new(1);
+
+    let mut packet = BitStream::new();
+    client
+        .build_send_packet_header(&mut packet, NetPacketType::DataPacket)
+        .unwrap();
+    packet.write_u32(1234);
+    let mut results = deliver(packet.into_bytes(), &mut server);
+    assert_eq!(results.len(), 2);
+    let mut data = match results.pop() {
+        Some(DNetResult::HandlePacket(data)) => data,
+        _ => panic!("Expected the packet"),
+    };
+    assert!(matches!(results.pop(), Some(DNetResult::KeepAlive)));
+    assert_eq!(data.read_u32().unwrap(), 1234);
+
+    let mut ack = BitStream::new();
+    server
+        .build_send_packet_header(&mut ack, NetPacketType::AckPacket)
+        .unwrap();
+    let results = deliver(ack.into_bytes(), &mut client);
+    assert!(matches!(
+        results[..],
+        [
+            DNetResult::HandleNotify(true),
+            DNetResult::HandleConnectionEstablished,
+            DNetResult::KeepAlive
+        ]
+    ));
+    assert!(client.round_trip_time().is_some());
+    assert!(!client.window_full());
+}
@@ -0,0 +1,162 @@
+use dnet::{BitStream, DnetError, EventManager, GuaranteeType};
+
+fn send(events: &mut EventManager<u32>, max_bits: usize) -> Vec<u8> {
+    let mut stream = BitStream::with_max_size(1500);
+    events
+        .write_packet(&mut stream, max_bits, |message, stream| {
+            stream.write_u32(*message);
+            Ok(())
+        })
+        .unwrap();
+    stream.try_into_bytes().unwrap()
+}
+
+fn recv(events: &mut EventManager<u32>, packet: &[u8]) -> Vec<u32> {
+    let mut stream = BitStream::from_buffer(packet.to_vec());
+    events
+        .read_packet(&mut stream, |stream| stream.read_u32())
+        .unwrap()
+}
+
+#[test]
+fn ordered_delivery() {
+    let mut sender = EventManager::new();
+    let mut receiver = EventManager::new();
+    for message in 0..5 {
+        sender.post_event(message, GuaranteeType::GuaranteedOrdered);
+    }
+
+    let packet = send(&mut sender, 1500 * 8);
+    // End of the unordered phase, then the first event with its sequence number and
+    // the rest flagged as following on, then the end of the ordered phase
+    assert_eq!(
+        packet.len(),
+        (1 + (2 + 7 + 32) + 4 * (2 + 32) + 1usize).div_ceil(8)
+    );
+    assert_eq!(recv(&mut receiver, &packet), vec![0, 1, 2, 3, 4]);
+
+    let finished = sender.handle_notify(true);
+    assert_eq!(
+        finished,
+        vec![(0, true), (1, true), (2, true), (3, true), (4, true)]
+    );
+    assert!(!sender.is_send_pending());
+}
+
+#[test]
+fn resends_dropped_events() {
+    let mut sender = EventManager::new();
+    let mut receiver = EventManager::new();
+    sender.post_event(10, GuaranteeType::GuaranteedOrdered);
+    sender.post_event(11, GuaranteeType::GuaranteedOrdered);
+    sender.post_event(20, GuaranteeType::Guaranteed);
+    sender.post_event(30, GuaranteeType::Unguaranteed);
+
+    send(&mut sender, 1500 * 8);
+    assert!(!sender.is_send_pending());
+    // Only the unguaranteed event is given up on
+    assert_eq!(sender.handle_notify(false), vec![(30, false)]);
+    assert!(sender.is_send_pending());
+
+    let packet = send(&mut sender, 1500 * 8);
+    assert_eq!(recv(&mut receiver, &packet), vec![20, 10, 11]);
+    assert_eq!(
+        sender.handle_notify(true),
+        vec![(20, true), (10, true), (11, true)]
+    );
+}
+
+#[test]
+fn holds_events_received_out_of_order() {
+    let mut sender = EventManager::new();
+    let mut receiver = EventManager::new();
+    sender.post_event(0, GuaranteeType::GuaranteedOrdered);
+    sender.post_event(1, GuaranteeType::GuaranteedOrdered);
+    send(&mut sender, 1500 * 8);
+    sender.post_event(2, GuaranteeType::GuaranteedOrdered);
+    let second = send(&mut sender, 1500 * 8);
+
+    // The first packet is lost, so the second gets there first
+    assert_eq!(sender.handle_notify(false), vec![]);
+    assert_eq!(recv(&mut receiver, &second), vec![]);
+    // 2 made it, but isn't done until 0 and 1 are acked
+    assert_eq!(sender.handle_notify(true), vec![]);
+
+    let resent = send(&mut sender, 1500 * 8);
+    assert_eq!(recv(&mut receiver, &resent), vec![0, 1, 2]);
+    assert_eq!(
+        sender.handle_notify(true),
+        vec![(0, true), (1, true), (2, true)]
+    );
+
+    // Getting the same packet again doesn't deliver anything twice
+    assert_eq!(recv(&mut receiver, &resent), vec![]);
+}
+
+#[test]
+fn leaves_events_that_dont_fit_for_later() {
+    let mut sender = EventManager::new();
+    let mut receiver = EventManager::new();
+    sender.post_event(1, GuaranteeType::Guaranteed);
+    sender.post_event(2, GuaranteeType::Guaranteed);
+    for message in 3..6 {
+        sender.post_event(message, GuaranteeType::GuaranteedOrdered);
+    }
+
+    // Room for the unordered events and two ordered ones
+    let max_bits = 2 * 33 + 1 + (2 + 7 + 32) + (2 + 32) + 1;
+    let packet = send(&mut sender, max_bits);
+    assert!(packet.len() * 8 <= max_bits + 7);
+    assert_eq!(recv(&mut receiver, &packet), vec![1, 2, 3, 4]);
+    assert!(sender.is_send_pending());
+
+    // Nothing fits, but the packet still ends properly
+    let packet = send(&mut sender, 10);
+    assert_eq!(recv(&mut receiver, &packet), vec![]);
+
+    let packet = send(&mut sender, 1500 * 8);
+    assert_eq!(recv(&mut receiver, &packet), vec![5]);
+    assert!(!sender.is_send_pending());
+}
+
+#[test]
+fn rewinds_past_stream_overflow() {
+    let mut sender = EventManager::new();
+    for message in 0..4 {
+        sender.post_event(message, GuaranteeType::Guaranteed);
+    }
+
+    // The stream fills up before max_bits does
+    let mut stream = BitStream::with_max_size(10);
+    sender
+        .write_packet(&mut stream, 1500 * 8, |message, stream| {
+            stream.write_u32(*message);
+            Ok(())
+        })
+        .unwrap();
+    assert!(!stream.is_overflowed());
+    let packet = stream.try_into_bytes().unwrap();
+
+    let mut receiver = EventManager::new();
+    assert_eq!(recv(&mut receiver, &packet), vec![0, 1]);
+}
+
+#[test]
+fn bad_event_fails_the_whole_packet() {
+    let mut sender = EventManager::new();
+    let mut receiver = EventManager::new();
+    sender.post_event(1, GuaranteeType::GuaranteedOrdered);
+    sender.post_event(0xBAD, GuaranteeType::GuaranteedOrdered);
+    sender.post_event(3, GuaranteeType::GuaranteedOrdered);
+    let packet = send(&mut sender, 1500 * 8);
+
+    let mut stream = BitStream::from_buffer(packet.clone());
+    let result = receiver.read_packet(&mut stream, |stream| match stream.read_u32()? {
+        0xBAD => Err(DnetError::InvalidClassId(0xBAD)),
+        message => Ok(message),
+    });
+    assert!(matches!(result, Err(DnetError::InvalidClassId(0xBAD))));
+
+    // Nothing from the packet was taken as received, so event 1 isn't skipped over
+    assert_eq!(recv(&mut receiver, &packet), vec![1, 0xBAD, 3]);
+}
@@ -0,0 +1,173 @@
+use dnet::error::Result;
+use dnet::{
+    BitStream, DnetError, GameConnection, NetClassGroupMasks, NetClassTypes, NetObject,
+    GHOST_INDEX_BIT_SIZE, MAX_GHOST_COUNT,
+};
+use std::any::Any;
+
+#[derive(Debug, Default)]
+struct TestObject {
+    value: u32,
+    updates: u32,
+}
+
+impl TestObject {
+    const CLASS_NAME: &'static str = "TestObject";
+
+    fn create() -> Box<dyn NetObject> {
+        Box::new(TestObject::default())
+    }
+}
+
+impl NetObject for TestObject {
+    fn class_name(&self) -> &'static str {
+        Self::CLASS_NAME
+    }
+
+    fn unpack_update(
+        &mut self,
+        _connection: &GameConnection,
+        stream: &mut BitStream,
+    ) -> Result<()> {
+        self.value = stream.read_u32()?;
+        self.updates += 1;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+async fn connection() -> GameConnection {
+    let mut connection = GameConnection::connect("127.0.0.1:0", "127.0.0.1:9", 1)
+        .await
+        .unwrap();
+    connection.class_registry_mut().register_object(
+        TestObject::CLASS_NAME,
+        NetClassGroupMasks::NetClassGroupGameMask,
+        TestObject::create,
+    );
+    connection
+}
+
+enum Change {
+    Create(u32, u32),
+    Update(u32, u32),
+    Delete(u32),
+}
+
+// What ghostWritePacket would send, with 5 bit ghost ids
+fn ghost_packet(connection: &GameConnection, changes: &[Change]) -> BitStream {
+    let registry = connection.class_registry();
+    let group = connection.net_class_group();
+    let class_id = registry
+        .class_id(
+            group,
+            NetClassTypes::NetClassTypeObject,
+            TestObject::CLASS_NAME,
+        )
+        .unwrap();
+
+    let mut stream = BitStream::new();
+    stream.write_flag(true);
+    stream.write_int(5 - 3, GHOST_INDEX_BIT_SIZE);
+    for change in changes {
+        stream.write_flag(true);
+        match *change {
+            Change::Create(index, value) => {
+                stream.write_int(index, 5);
+                stream.write_flag(false);
+                registry.write_class_id(
+                    &mut stream,
+                    class_id,
+                    NetClassTypes::NetClassTypeObject,
+                    group,
+                );
+                stream.write_u32(value);
+            }
+            Change::Update(index, value) => {
+                stream.write_int(index, 5);
+                stream.write_flag(false);
+                stream.write_u32(value);
+            }
+            Change::Delete(index) => {
+                stream.write_int(index, 5);
+                stream.write_flag(true);
+            }
+        }
+    }
+    stream.write_flag(false);
+    BitStream::from_buffer(stream.into_bytes())
+}
+
+fn object(connection: &GameConnection, index: u32) -> &TestObject {
+    connection
+        .ghosts()
+        .get(index)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<TestObject>()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn creates_updates_and_deletes_ghosts() {
+    let mut connection = connection().await;
+
+    let mut stream = ghost_packet(
+        &connection,
+        &[Change::Create(3, 100), Change::Create(31, 200)],
+    );
+    connection.read_ghost_packet(&mut stream).unwrap();
+    assert_eq!(connection.ghosts().len(), 2);
+    assert_eq!(object(&connection, 3).value, 100);
+    assert_eq!(object(&connection, 31).value, 200);
+
+    let mut stream = ghost_packet(&connection, &[Change::Update(3, 101), Change::Delete(31)]);
+    connection.read_ghost_packet(&mut stream).unwrap();
+    assert_eq!(connection.ghosts().len(), 1);
+    assert_eq!(object(&connection, 3).value, 101);
+    assert_eq!(object(&connection, 3).updates, 2);
+    assert!(connection.ghosts().get(31).is_none());
+}
+
+#[tokio::test]
+async fn skips_packets_without_ghosts() {
+    let mut connection = connection().await;
+    let mut stream = BitStream::from_buffer(vec![0]);
+    connection.read_ghost_packet(&mut stream).unwrap();
+    assert_eq!(stream.get_bit_pos(), 1);
+    assert!(connection.ghosts().is_empty());
+}
+
+#[tokio::test]
+async fn rejects_deleting_unknown_ghosts() {
+    let mut connection = connection().await;
+    let mut stream = ghost_packet(&connection, &[Change::Delete(7)]);
+    assert!(matches!(
+        connection.read_ghost_packet(&mut stream),
+        Err(DnetError::UnknownGhost(7))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_indices_wider_than_the_ghost_table() {
+    let mut connection = connection().await;
+    let mut stream = BitStream::new();
+    stream.write_flag(true);
+    stream.write_int(13 - 3, GHOST_INDEX_BIT_SIZE);
+    stream.write_flag(true);
+    stream.write_int(MAX_GHOST_COUNT as u32, 13);
+    stream.write_flag(true);
+    stream.write_flag(false);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert!(matches!(
+        connection.read_ghost_packet(&mut stream),
+        Err(DnetError::GhostIndexSizeOutOfRange(13))
+    ));
+}
@@ -0,0 +1,259 @@
+use dnet::error::Result;
+use dnet::{
+    BitStream, GameConnection, GhostManager, NetClassGroupMasks, NetClassTypes, NetObject,
+    GHOST_INDEX_BIT_SIZE,
+};
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+
+#[derive(Debug, Default)]
+struct TestObject {
+    value: u32,
+    priority: f32,
+}
+
+impl TestObject {
+    const CLASS_NAME: &'static str = "TestObject";
+
+    fn create() -> Box<dyn NetObject> {
+        Box::new(TestObject::default())
+    }
+}
+
+impl NetObject for TestObject {
+    fn class_name(&self) -> &'static str {
+        Self::CLASS_NAME
+    }
+
+    fn pack_update(&mut self, _mask: u32, stream: &mut BitStream) -> u32 {
+        stream.write_u32(self.value);
+        0
+    }
+
+    fn update_priority(&self, _update_mask: u32, _update_skip_count: u32) -> f32 {
+        self.priority
+    }
+
+    fn unpack_update(
+        &mut self,
+        _connection: &GameConnection,
+        stream: &mut BitStream,
+    ) -> Result<()> {
+        self.value = stream.read_u32()?;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn object(value: u32, priority: f32) -> Arc<Mutex<TestObject>> {
+    Arc::new(Mutex::new(TestObject { value, priority }))
+}
+
+// The client side, which also has the class registry both sides share
+async fn client() -> GameConnection {
+    let mut connection = GameConnection::connect("127.0.0.1:0", "127.0.0.1:9", 1)
+        .await
+        .unwrap();
+    connection.class_registry_mut().register_object(
+        TestObject::CLASS_NAME,
+        NetClassGroupMasks::NetClassGroupGameMask,
+        TestObject::create,
+    );
+    connection
+}
+
+fn send(manager: &mut GhostManager, client: &GameConnection, max_bits: usize) -> Vec<u8> {
+    let mut stream = BitStream::with_max_size(1500);
+    manager
+        .write_packet(
+            &mut stream,
+            max_bits,
+            client.class_registry(),
+            client.net_class_group(),
+        )
+        .unwrap();
+    stream.try_into_bytes().unwrap()
+}
+
+// Sends a packet and has the client read it
+fn deliver(manager: &mut GhostManager, client: &mut GameConnection, max_bits: usize) {
+    let packet = send(manager, client, max_bits);
+    let mut stream = BitStream::from_buffer(packet);
+    client.read_ghost_packet(&mut stream).unwrap();
+}
+
+// Values of the client's ghosts, by ghost index
+fn ghost_values(client: &GameConnection) -> Vec<(u32, u32)> {
+    client
+        .ghosts()
+        .iter()
+        .map(|(index, ghost)| {
+            let ghost = ghost.as_any().downcast_ref::<TestObject>().unwrap();
+            (index, ghost.value)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn scopes_objects() {
+    let mut client = client().await;
+    let mut manager = GhostManager::new();
+    manager.activate_ghosting();
+
+    assert_eq!(manager.object_in_scope(10, object(100, 1.0)).unwrap(), 0);
+    assert_eq!(manager.object_in_scope(11, object(110, 1.0)).unwrap(), 1);
+    assert_eq!(manager.object_in_scope(10, object(0, 1.0)).unwrap(), 0);
+    assert_eq!(manager.ghost_count(), 2);
+
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert_eq!(ghost_values(&client), vec![(0, 100), (1, 110)]);
+    // Not until the client is known to have them
+    assert_eq!(manager.ghosted_index(10), None);
+    manager.handle_notify(true);
+    assert_eq!(manager.ghosted_index(10), Some(0));
+    assert_eq!(manager.ghosted_index(11), Some(1));
+
+    assert!(manager.object_out_of_scope(10));
+    assert!(!manager.object_out_of_scope(12));
+    assert_eq!(manager.ghosted_index(10), None);
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert_eq!(ghost_values(&client), vec![(1, 110)]);
+    // The slot is held on to until the delete is acked
+    assert_eq!(manager.ghost_count(), 2);
+    manager.handle_notify(true);
+    assert_eq!(manager.ghost_count(), 1);
+}
+
+#[tokio::test]
+async fn drops_ghosts_that_never_went_out() {
+    let mut client = client().await;
+    let mut manager = GhostManager::new();
+    manager.activate_ghosting();
+
+    manager.object_in_scope(10, object(100, 1.0)).unwrap();
+    manager.object_out_of_scope(10);
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert!(client.ghosts().is_empty());
+    assert_eq!(manager.ghost_count(), 0);
+}
+
+#[tokio::test]
+async fn sends_highest_priority_first() {
+    let mut client = client().await;
+    let mut manager = GhostManager::new();
+    manager.activate_ghosting();
+
+    manager.object_in_scope(10, object(100, 0.5)).unwrap();
+    manager.object_in_scope(11, object(110, 2.0)).unwrap();
+    manager.object_in_scope(12, object(120, 1.0)).unwrap();
+
+    // Room for exactly one ghost: the ghosting flag and id size, then the ghost's
+    // flags, index, class id and value, then the end of the list
+    let class_bits = client
+        .class_registry()
+        .class_bit_size(client.net_class_group(), NetClassTypes::NetClassTypeObject);
+    let max_bits = 1 + GHOST_INDEX_BIT_SIZE + (1 + 3 + 1 + class_bits + 32) + 1;
+    deliver(&mut manager, &mut client, max_bits);
+    assert_eq!(ghost_values(&client), vec![(1, 110)]);
+
+    // The others are still waiting
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert_eq!(ghost_values(&client), vec![(0, 100), (1, 110), (2, 120)]);
+}
+
+#[tokio::test]
+async fn ghosts_large_indices() {
+    let mut client = client().await;
+    let mut manager = GhostManager::new();
+    manager.activate_ghosting();
+
+    // Past 1024, so the indices need 11 bits
+    let count = 1100;
+    for i in 0..count {
+        assert_eq!(manager.object_in_scope(i, object(i * 10, 1.0)).unwrap(), i);
+    }
+    while client.ghosts().len() < count as usize {
+        deliver(&mut manager, &mut client, 1500 * 8);
+        manager.handle_notify(true);
+    }
+    let expected = (0..count).map(|i| (i, i * 10)).collect::<Vec<_>>();
+    assert_eq!(ghost_values(&client), expected);
+    assert_eq!(manager.ghosted_index(count - 1), Some(count - 1));
+}
+
+#[tokio::test]
+async fn resends_dropped_ghosts() {
+    let mut client = client().await;
+    let mut manager = GhostManager::new();
+    manager.activate_ghosting();
+
+    let ghost = object(100, 1.0);
+    manager.object_in_scope(10, ghost.clone()).unwrap();
+    send(&mut manager, &client, 1500 * 8);
+    manager.handle_notify(false);
+    assert_eq!(manager.ghosted_index(10), None);
+
+    // Sent again as a new ghost, class id and all
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert_eq!(ghost_values(&client), vec![(0, 100)]);
+    manager.handle_notify(true);
+    assert_eq!(manager.ghosted_index(10), Some(0));
+
+    // Nothing changed, so nothing to send
+    deliver(&mut manager, &mut client, 1500 * 8);
+    manager.handle_notify(true);
+
+    // An update that gets lost goes out again
+    ghost.lock().unwrap().value = 101;
+    manager.set_mask_bits(10, 1);
+    send(&mut manager, &client, 1500 * 8);
+    manager.handle_notify(false);
+    ghost.lock().unwrap().value = 102;
+    deliver(&mut manager, &mut client, 1500 * 8);
+    assert_eq!(ghost_values(&client), vec![(0, 102)]);
+}
+
+#[tokio::test]
+async fn ghosts_from_a_connection() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut connection = GameConnection::connect("127.0.0.1:0", server.local_addr().unwrap(), 1)
+        .await
+        .unwrap();
+    connection.class_registry_mut().register_object(
+        TestObject::CLASS_NAME,
+        NetClassGroupMasks::NetClassGroupGameMask,
+        TestObject::create,
+    );
+    connection.set_ghosting_from(true);
+    let manager = connection.ghost_manager_mut();
+    manager.activate_ghosting();
+    manager.object_in_scope(10, object(100, 1.0)).unwrap();
+
+    assert!(connection.check_packet_send(true).await.unwrap());
+    let mut buf = [0u8; 1500];
+    server.recv(&mut buf).await.unwrap();
+
+    // The other side acks our first data packet
+    let mut ack = BitStream::new();
+    ack.write_flag(true);
+    ack.write_int(1, 1);
+    ack.write_int(0, 9);
+    ack.write_int(1, 9);
+    ack.write_int(2, 2);
+    ack.write_int(1, 3);
+    ack.write_int(1, 8);
+    connection
+        .process_raw_packet(BitStream::from_buffer(ack.into_bytes()))
+        .await
+        .unwrap();
+
+    assert_eq!(connection.ghost_manager().ghosted_index(10), Some(0));
+}
@@ -0,0 +1,245 @@
+use dnet::{BitStream, DnetError, HuffmanTable, StringEncoding, MAX_STRING_LEN, STOCK_CHAR_FREQS};
+use std::sync::Arc;
+
+fn encode(value: &str) -> Vec<u8> {
+    let mut stream = BitStream::new();
+    stream.write_string(&value.to_string());
+    stream.into_bytes()
+}
+
+fn decode(bytes: Vec<u8>) -> dnet::error::Result<String> {
+    BitStream::from_buffer(bytes).read_string()
+}
+
+// Simple xorshift so the fuzzing below is the same every run
+fn next_random(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+// Bytes from Torque's HuffmanProcessor::writeHuffBuffer
+#[test]
+fn golden_vectors() {
+    let vectors: &[(&str, &[u8])] = &[
+        ("", &[0, 0]),
+        ("hello world", &[23, 230, 31, 67, 175, 80, 27, 1]),
+        (
+            "The quick brown fox jumps over the lazy dog",
+            &[
+                87, 214, 243, 95, 100, 243, 140, 39, 183, 51, 45, 232, 54, 117, 114, 11, 143, 234,
+                118, 42, 189, 29, 204, 127, 35, 35, 169, 92, 208, 30,
+            ],
+        ),
+        (
+            "Marble Blast Gold",
+            &[35, 92, 105, 187, 241, 23, 49, 178, 193, 197, 234, 8],
+        ),
+        (
+            "1234567890!@#$",
+            &[
+                28, 98, 100, 102, 104, 106, 108, 110, 112, 114, 96, 66, 128, 70, 72, 0,
+            ],
+        ),
+        // Rare characters have long codes, so these are sent as raw bytes
+        ("\u{e9}t\u{e9}", &[6, 210, 233, 210, 1]),
+        ("\u{ff}\u{fe}\u{fd}", &[6, 254, 253, 251, 1]),
+    ];
+
+    for &(value, bytes) in vectors {
+        assert_eq!(encode(value), bytes, "encoding {:?}", value);
+        assert_eq!(
+            decode(bytes.to_vec()).unwrap(),
+            value,
+            "decoding {:?}",
+            bytes
+        );
+    }
+}
+
+#[test]
+fn round_trip_every_symbol() {
+    for symbol in 0u8..=255 {
+        let value = (symbol as char).to_string().repeat(10);
+        assert_eq!(decode(encode(&value)).unwrap(), value);
+    }
+}
+
+#[test]
+fn round_trip_random_strings() {
+    let alphabet =
+        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 _-.,!?\u{e9}\u{fc}";
+    let alphabet = alphabet.chars().collect::<Vec<_>>();
+    let mut state = 0x1234_5678;
+    for _ in 0..1000 {
+        let len = next_random(&mut state) as usize % 256;
+        let value = (0..len)
+            .map(|_| alphabet[next_random(&mut state) as usize % alphabet.len()])
+            .collect::<String>();
+        assert_eq!(decode(encode(&value)).unwrap(), value);
+    }
+}
+
+#[test]
+fn round_trip_after_other_bits() {
+    // Strings don't have to start on a byte boundary
+    let mut stream = BitStream::new();
+    stream.write_flag(true);
+    stream.write_int(5, 3);
+    stream.write_string(&"hello world".to_string());
+    stream.write_flag(true);
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert!(stream.read_flag().unwrap());
+    assert_eq!(stream.read_int(3).unwrap(), 5);
+    assert_eq!(stream.read_string().unwrap(), "hello world");
+    assert!(stream.read_flag().unwrap());
+}
+
+#[test]
+fn garbage_input_does_not_panic() {
+    let mut state = 0x8765_4321;
+    for _ in 0..10000 {
+        let len = next_random(&mut state) as usize % 64;
+        let bytes = (0..len)
+            .map(|_| next_random(&mut state) as u8)
+            .collect::<Vec<_>>();
+        let _ = decode(bytes);
+    }
+}
+
+#[test]
+fn truncated_input_is_an_error() {
+    let bytes = encode("The quick brown fox jumps over the lazy dog");
+    for len in 0..bytes.len() - 1 {
+        assert!(decode(bytes[..len].to_vec()).is_err(), "length {}", len);
+    }
+}
+
+#[test]
+fn huff_bytes_round_trip() {
+    let bytes = (0u8..255).collect::<Vec<_>>();
+    let mut stream = BitStream::new();
+    assert_eq!(stream.write_huff_bytes(&bytes), bytes.len());
+    assert!(stream.error().is_none());
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(stream.read_huff_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn huff_bytes_truncation_is_reported() {
+    let bytes = vec![b'a'; 300];
+    let mut stream = BitStream::new();
+    assert!(matches!(
+        stream.try_write_huff_bytes(&bytes),
+        Err(DnetError::StringTooLong { len: 300, max: 255 })
+    ));
+    assert_eq!(stream.get_bit_pos(), 0);
+
+    assert_eq!(stream.write_huff_bytes(&bytes), MAX_STRING_LEN);
+    assert!(matches!(
+        stream.error(),
+        Some(DnetError::StringTooLong { len: 300, max: 255 })
+    ));
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(stream.read_huff_bytes().unwrap(), &bytes[..MAX_STRING_LEN]);
+}
+
+#[test]
+fn latin1_strings() {
+    let mut stream = BitStream::new();
+    stream
+        .try_write_string("Ch\u{e2}teau d'\u{c9}t\u{e9}")
+        .unwrap();
+    assert!(matches!(
+        stream.try_write_string("\u{65e5}\u{672c}"),
+        Err(DnetError::UnencodableChar('\u{65e5}'))
+    ));
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(
+        stream.read_string().unwrap(),
+        "Ch\u{e2}teau d'\u{c9}t\u{e9}"
+    );
+}
+
+#[test]
+fn utf8_strings() {
+    let value = "Ch\u{e2}teau \u{65e5}\u{672c}";
+    let mut stream = BitStream::new();
+    stream.set_string_encoding(StringEncoding::Utf8);
+    stream.try_write_string(value).unwrap();
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(stream.read_huff_bytes().unwrap(), value.as_bytes());
+    stream.set_bit_pos(0);
+    stream.set_string_encoding(StringEncoding::Utf8);
+    assert_eq!(stream.read_string().unwrap(), value);
+}
+
+#[test]
+fn utf8_truncation_keeps_whole_chars() {
+    // 256 bytes, so cutting at 255 would land in the middle of the last char
+    let value = format!("a{}", "\u{65e5}".repeat(85));
+    let mut stream = BitStream::new();
+    stream.set_string_encoding(StringEncoding::Utf8);
+    stream.write_string(&value);
+    assert!(stream.error().is_some());
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    stream.set_string_encoding(StringEncoding::Utf8);
+    assert_eq!(
+        stream.read_string().unwrap(),
+        format!("a{}", "\u{65e5}".repeat(84))
+    );
+}
+
+#[test]
+fn stock_table_from_frequencies() {
+    let table = Arc::new(HuffmanTable::new(&STOCK_CHAR_FREQS).unwrap());
+    let mut stream = BitStream::new();
+    stream.set_huffman_table(table);
+    stream.write_string(&"hello world".to_string());
+    assert_eq!(stream.into_bytes(), encode("hello world"));
+}
+
+#[test]
+fn custom_table() {
+    // Make 'z' the most common character by far
+    let mut freqs = STOCK_CHAR_FREQS;
+    freqs[b'z' as usize] = 100_000;
+    let table = Arc::new(HuffmanTable::new(&freqs).unwrap());
+
+    let mut stream = BitStream::new();
+    stream.set_huffman_table(table.clone());
+    stream.write_string(&"zzzzzzzz".to_string());
+    let bytes = stream.into_bytes();
+    assert_ne!(bytes, encode("zzzzzzzz"));
+    // One bit per 'z' after the flag and length
+    assert_eq!(bytes.len(), 3);
+
+    let mut stream = BitStream::from_buffer(bytes);
+    stream.set_huffman_table(table);
+    assert_eq!(stream.read_string().unwrap(), "zzzzzzzz");
+}
+
+#[test]
+fn lopsided_table_is_an_error() {
+    // Fibonacci frequencies make the deepest trees. Starting above the pop of all the
+    // unused characters put together keeps them from flattening it out.
+    let mut freqs = [0u32; 256];
+    let (mut a, mut b) = (256u32, 256u32);
+    for freq in freqs.iter_mut().take(34) {
+        *freq = a;
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+    assert!(matches!(
+        HuffmanTable::new(&freqs),
+        Err(DnetError::HuffmanCodeTooLong(_))
+    ));
+}
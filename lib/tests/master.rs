@@ -0,0 +1,395 @@
+use dnet::{
+    DnetError, MasterServer, MasterServerHost, Packet, PacketSource, ServerQuery,
+    MAX_SERVERS_PER_LIST_PAGE,
+};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout, Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct FakeServer {
+    socket: UdpSocket,
+    game_type: String,
+    player_count: u8,
+    bot_count: u8,
+    guid_list: Vec<u32>,
+}
+
+impl FakeServer {
+    async fn new(game_type: &str, player_count: u8) -> FakeServer {
+        FakeServer {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            game_type: game_type.to_string(),
+            player_count,
+            bot_count: 0,
+            guid_list: (0..player_count as u32).collect(),
+        }
+    }
+
+    fn address(&self) -> (Ipv4Addr, u16) {
+        match self.socket.local_addr().unwrap() {
+            SocketAddr::V4(address) => (*address.ip(), address.port()),
+            SocketAddr::V6(_) => unreachable!(),
+        }
+    }
+
+    async fn heartbeat(&self, master: SocketAddr) {
+        let heartbeat = Packet::GameHeartbeat {
+            flags: 0,
+            key: 0,
+            session: 0,
+        };
+        self.socket
+            .send_to(&heartbeat.into_bytes(), master)
+            .await
+            .unwrap();
+    }
+
+    // Answers the GameMasterInfoRequest the master sends after a heartbeat
+    async fn answer_info_request(&self) {
+        let mut buf = [0u8; 1500];
+        let (len, from) = timeout(TIMEOUT, self.socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let (flags, key, session) =
+            match Packet::parse_bytes(&buf[0..len], PacketSource::GameToGame).unwrap() {
+                Packet::GameMasterInfoRequest {
+                    flags,
+                    key,
+                    session,
+                } => (flags, key, session),
+                other => panic!("Expected GameMasterInfoRequest, got {:?}", other),
+            };
+
+        let response = Packet::GameMasterInfoResponse {
+            flags,
+            key,
+            session,
+            game_type: self.game_type.clone(),
+            mission_type: "Race".to_string(),
+            max_players: 16,
+            region_mask: 2,
+            version: 100,
+            filter_flag: 0,
+            bot_count: self.bot_count,
+            cpu_speed: 3000,
+            player_count: self.player_count,
+            guid_list: self.guid_list.clone(),
+        };
+        self.socket
+            .send_to(&response.into_bytes(), from)
+            .await
+            .unwrap();
+    }
+}
+
+async fn register(host: &MasterServerHost, servers: &[FakeServer]) {
+    for server in servers {
+        server.heartbeat(host.local_addr()).await;
+    }
+    for server in servers {
+        server.answer_info_request().await;
+    }
+
+    // Info responses come in on another socket, so wait for the master to have them all
+    timeout(TIMEOUT, async {
+        loop {
+            let listed = host.servers().await;
+            if listed.len() == servers.len() && listed.iter().all(|s| s.info.is_some()) {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+async fn query(
+    host: &MasterServerHost,
+    game_type: &str,
+    buddy_list: Vec<u32>,
+) -> Vec<(Ipv4Addr, u16)> {
+    let master = MasterServer::connect("127.0.0.1:0", host.local_addr())
+        .await
+        .unwrap();
+    let mut servers = timeout(
+        TIMEOUT,
+        master.query_servers(
+            ServerQuery::new()
+                .game_type(game_type)
+                .buddy_list(buddy_list),
+        ),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    servers.sort();
+    servers
+}
+
+#[tokio::test]
+async fn lists_servers_after_heartbeat() {
+    let host = MasterServerHost::bind("127.0.0.1:0").await.unwrap();
+    let servers = vec![
+        FakeServer::new("Test", 2).await,
+        FakeServer::new("Other", 4).await,
+    ];
+    register(&host, &servers).await;
+
+    let mut all = servers.iter().map(|s| s.address()).collect::<Vec<_>>();
+    all.sort();
+    assert_eq!(query(&host, "any", vec![]).await, all);
+    assert_eq!(
+        query(&host, "test", vec![]).await,
+        vec![servers[0].address()]
+    );
+    assert_eq!(query(&host, "Nothing", vec![]).await, vec![]);
+}
+
+#[tokio::test]
+async fn filters_by_buddy_list() {
+    let host = MasterServerHost::bind("127.0.0.1:0").await.unwrap();
+    let mut servers = vec![
+        FakeServer::new("Test", 1).await,
+        FakeServer::new("Test", 1).await,
+    ];
+    servers[0].guid_list = vec![1234];
+    servers[1].guid_list = vec![5678];
+    register(&host, &servers).await;
+
+    assert_eq!(
+        query(&host, "any", vec![5678, 9]).await,
+        vec![servers[1].address()]
+    );
+}
+
+#[tokio::test]
+async fn expires_servers() {
+    let host = MasterServerHost::bind_with_timeout("127.0.0.1:0", Duration::from_millis(200))
+        .await
+        .unwrap();
+    let servers = vec![FakeServer::new("Test", 1).await];
+    register(&host, &servers).await;
+    assert_eq!(query(&host, "any", vec![]).await.len(), 1);
+
+    sleep(Duration::from_millis(300)).await;
+    assert!(host.servers().await.is_empty());
+    assert_eq!(query(&host, "any", vec![]).await, vec![]);
+}
+
+#[tokio::test]
+async fn paginates_long_lists() {
+    let host = MasterServerHost::bind("127.0.0.1:0").await.unwrap();
+    let mut servers = vec![];
+    for _ in 0..MAX_SERVERS_PER_LIST_PAGE + 10 {
+        servers.push(FakeServer::new("Test", 0).await);
+    }
+    register(&host, &servers).await;
+
+    let mut all = servers.iter().map(|s| s.address()).collect::<Vec<_>>();
+    all.sort();
+    assert_eq!(query(&host, "any", vec![]).await, all);
+}
+
+#[tokio::test]
+async fn lists_game_types() {
+    let host = MasterServerHost::bind("127.0.0.1:0").await.unwrap();
+    let servers = vec![
+        FakeServer::new("Test", 0).await,
+        FakeServer::new("Other", 0).await,
+        FakeServer::new("Test", 0).await,
+    ];
+    register(&host, &servers).await;
+
+    let master = MasterServer::connect("127.0.0.1:0", host.local_addr())
+        .await
+        .unwrap();
+    let (game_types, mission_types) = timeout(TIMEOUT, master.query_game_types())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(game_types, vec!["Other".to_string(), "Test".to_string()]);
+    assert_eq!(mission_types, vec!["Race".to_string()]);
+}
+
+// Stands in for a master server that loses packets
+struct LossyMaster {
+    socket: UdpSocket,
+}
+
+impl LossyMaster {
+    async fn new() -> LossyMaster {
+        LossyMaster {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    async fn client(&self) -> MasterServer {
+        let mut master = MasterServer::connect("127.0.0.1:0", self.socket.local_addr().unwrap())
+            .await
+            .unwrap();
+        master.set_query_timeout(Duration::from_millis(100), 3);
+        master
+    }
+
+    // Returns the key, session and packet index
+    async fn recv_request(&self) -> (u16, u16, u8, SocketAddr) {
+        let mut buf = [0u8; 1500];
+        let (len, from) = timeout(TIMEOUT, self.socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        match Packet::parse_bytes(&buf[0..len], PacketSource::GameToMaster).unwrap() {
+            Packet::MasterServerListRequest {
+                key,
+                session,
+                packet_index,
+                ..
+            } => (key, session, packet_index, from),
+            other => panic!("Expected MasterServerListRequest, got {:?}", other),
+        }
+    }
+
+    async fn send_page(
+        &self,
+        to: SocketAddr,
+        key: u16,
+        session: u16,
+        packet_index: u8,
+        packet_total: u8,
+        servers: Vec<(Ipv4Addr, u16)>,
+    ) {
+        let page = Packet::MasterServerListResponse {
+            flags: 0,
+            key,
+            session,
+            packet_index,
+            packet_total,
+            servers,
+        };
+        self.socket.send_to(&page.into_bytes(), to).await.unwrap();
+    }
+}
+
+fn server(port: u16) -> (Ipv4Addr, u16) {
+    (Ipv4Addr::new(10, 0, 0, 1), port)
+}
+
+#[tokio::test]
+async fn rerequests_missing_pages() {
+    let lossy = LossyMaster::new().await;
+    let master = lossy.client().await;
+    let query = tokio::spawn(async move { master.query_servers(ServerQuery::new()).await });
+
+    let (key, session, packet_index, from) = lossy.recv_request().await;
+    assert_eq!(packet_index, 0xFF);
+    // Page 1 and 2 go missing, and page 0 arrives twice
+    for _ in 0..2 {
+        lossy
+            .send_page(from, key, session, 0, 3, vec![server(1), server(2)])
+            .await;
+    }
+
+    let mut missing = vec![lossy.recv_request().await.2, lossy.recv_request().await.2];
+    missing.sort();
+    assert_eq!(missing, vec![1, 2]);
+
+    // Server 2 moved to a later page in the meantime
+    lossy
+        .send_page(from, key, session, 1, 3, vec![server(2), server(3)])
+        .await;
+    lossy
+        .send_page(from, key, session, 2, 3, vec![server(4)])
+        .await;
+
+    let servers = timeout(TIMEOUT, query).await.unwrap().unwrap().unwrap();
+    assert_eq!(servers, vec![server(1), server(2), server(3), server(4)]);
+}
+
+#[tokio::test]
+async fn resends_unanswered_query() {
+    let lossy = LossyMaster::new().await;
+    let master = lossy.client().await;
+    let query = tokio::spawn(async move { master.query_servers(ServerQuery::new()).await });
+
+    let (first_key, first_session, _, _) = lossy.recv_request().await;
+    let (key, session, packet_index, from) = lossy.recv_request().await;
+    assert_eq!(
+        (key, session, packet_index),
+        (first_key, first_session, 0xFF)
+    );
+    lossy
+        .send_page(from, key, session, 0, 1, vec![server(1)])
+        .await;
+
+    let servers = timeout(TIMEOUT, query).await.unwrap().unwrap().unwrap();
+    assert_eq!(servers, vec![server(1)]);
+}
+
+#[tokio::test]
+async fn returns_partial_list_after_retries() {
+    let lossy = LossyMaster::new().await;
+    let master = lossy.client().await;
+    let query = tokio::spawn(async move { master.query_servers(ServerQuery::new()).await });
+
+    let (key, session, _, from) = lossy.recv_request().await;
+    lossy
+        .send_page(from, key, session, 0, 3, vec![server(1)])
+        .await;
+    lossy
+        .send_page(from, key, session, 2, 3, vec![server(3)])
+        .await;
+
+    // Page 1 never arrives, however many times it's asked for
+    for _ in 0..3 {
+        assert_eq!(lossy.recv_request().await.2, 1);
+    }
+    let servers = timeout(TIMEOUT, query).await.unwrap().unwrap().unwrap();
+    assert_eq!(servers, vec![server(1), server(3)]);
+}
+
+#[tokio::test]
+async fn gives_up_after_retries() {
+    let lossy = LossyMaster::new().await;
+    let master = lossy.client().await;
+    let query = tokio::spawn(async move { master.query_servers(ServerQuery::new()).await });
+
+    // Nothing ever comes back, so there's no list to return. The first request and 3
+    // retries.
+    for _ in 0..4 {
+        lossy.recv_request().await;
+    }
+    let result = timeout(TIMEOUT, query).await.unwrap().unwrap();
+    assert!(matches!(result, Err(DnetError::QueryTimedOut)));
+}
+
+#[tokio::test]
+async fn keeps_going_while_the_master_is_down() {
+    // Nothing is listening yet, so the query only gets port unreachables back
+    let address = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut master = MasterServer::connect("127.0.0.1:0", address).await.unwrap();
+    master.set_query_timeout(Duration::from_millis(100), 1);
+    let result = timeout(TIMEOUT, master.query_servers(ServerQuery::new()))
+        .await
+        .unwrap();
+    assert!(matches!(result, Err(DnetError::QueryTimedOut)));
+
+    let lossy = LossyMaster {
+        socket: UdpSocket::bind(address).await.unwrap(),
+    };
+    let query = tokio::spawn(async move { master.query_servers(ServerQuery::new()).await });
+    let (key, session, _, from) = lossy.recv_request().await;
+    lossy
+        .send_page(from, key, session, 0, 1, vec![server(1)])
+        .await;
+
+    let servers = timeout(TIMEOUT, query).await.unwrap().unwrap().unwrap();
+    assert_eq!(servers, vec![server(1)]);
+}
@@ -0,0 +1,154 @@
+use dnet::{
+    BitStream, ClientPacketHeader, DnetError, Move, MoveQueue, MAX_MOVE_COUNT, MAX_MOVE_QUEUE_SIZE,
+};
+use std::f32::consts::PI;
+
+fn test_move(i: u32) -> Move {
+    let mut new_move = Move {
+        x: 0.5,
+        y: -1.0,
+        yaw: i as f32 * 0.01,
+        pitch: -0.25,
+        free_look: i.is_multiple_of(2),
+        ..Default::default()
+    };
+    new_move.trigger[i as usize % 6] = true;
+    new_move.clamp();
+    new_move
+}
+
+fn round_trip(current: &Move, base: Option<&Move>) -> (Move, usize) {
+    let mut stream = BitStream::new();
+    current.pack(&mut stream, base);
+    let bits = stream.get_bit_pos();
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let mut unpacked = Move::new();
+    unpacked.unpack(&mut stream, base).unwrap();
+    assert_eq!(stream.get_bit_pos(), bits);
+    (unpacked, bits)
+}
+
+#[test]
+fn clamps_moves() {
+    let mut new_move = Move {
+        x: 0.5,
+        y: 2.0,
+        z: -2.0,
+        yaw: PI / 2.0,
+        ..Default::default()
+    };
+    new_move.clamp();
+    assert_eq!((new_move.px, new_move.py, new_move.pz), (24, 32, 0));
+    assert_eq!((new_move.x, new_move.y, new_move.z), (0.5, 1.0, -1.0));
+    assert_eq!(new_move.pyaw, 0x4000);
+    assert!((new_move.yaw - PI / 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn packs_null_move_in_one_bit() {
+    assert_eq!(round_trip(&Move::default(), None), (Move::default(), 1));
+}
+
+#[test]
+fn packs_moves() {
+    let current = test_move(3);
+    let (unpacked, bits) = round_trip(&current, None);
+    assert_eq!(unpacked, current);
+    // Something different, then each field with its flag, free look and the triggers
+    assert_eq!(bits, 1 + (1 + 16) * 2 + 1 + (1 + 6) * 2 + 1 + 1 + 1 + 6);
+}
+
+#[test]
+fn packs_moves_against_the_previous_one() {
+    let prev = test_move(3);
+
+    // Nothing different still writes every flag, since there's no leading one
+    let (unpacked, bits) = round_trip(&prev, Some(&prev));
+    assert_eq!(unpacked, prev);
+    assert_eq!(bits, 8);
+
+    let mut current = prev;
+    current.yaw += 0.5;
+    current.free_look = !prev.free_look;
+    current.clamp();
+    let (unpacked, bits) = round_trip(&current, Some(&prev));
+    assert_eq!(unpacked, current);
+    assert_eq!(bits, 8 + 16);
+}
+
+#[test]
+fn queues_moves_until_acked() {
+    let mut queue = MoveQueue::new();
+    for i in 0..MAX_MOVE_QUEUE_SIZE as u32 {
+        assert!(queue.push(test_move(i)));
+    }
+    // Full
+    assert!(!queue.push(test_move(0)));
+    assert_eq!(queue.len(), MAX_MOVE_QUEUE_SIZE);
+
+    let pending = queue.pending_moves();
+    assert_eq!(pending.len(), MAX_MOVE_COUNT);
+    assert_eq!(pending[0], test_move(0));
+
+    queue.ack(10);
+    assert_eq!(queue.first_move_index(), 10);
+    assert_eq!(queue.len(), MAX_MOVE_QUEUE_SIZE - 10);
+    assert_eq!(queue.pending_moves()[0], test_move(10));
+
+    // Old acks don't do anything, and acks past the end just empty it
+    queue.ack(5);
+    assert_eq!(queue.first_move_index(), 10);
+    queue.ack(100);
+    assert!(queue.is_empty());
+    assert_eq!(queue.first_move_index(), MAX_MOVE_QUEUE_SIZE as u32);
+}
+
+#[test]
+fn writes_pending_moves() {
+    let mut queue = MoveQueue::new();
+    for i in 0..MAX_MOVE_QUEUE_SIZE as u32 {
+        queue.push(test_move(i));
+    }
+    queue.ack(7);
+
+    let header = ClientPacketHeader {
+        first_move_index: queue.first_move_index(),
+        moves: queue.pending_moves(),
+        ..Default::default()
+    };
+    let mut stream = BitStream::new();
+    header.write(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let read = ClientPacketHeader::read(&mut stream).unwrap();
+    assert_eq!(read, header);
+    assert_eq!(read.first_move_index, 7);
+    assert_eq!(read.moves.len(), MAX_MOVE_COUNT);
+}
+
+#[test]
+fn only_writes_max_move_count() {
+    let moves = (0..MAX_MOVE_COUNT as u32 + 5)
+        .map(test_move)
+        .collect::<Vec<_>>();
+    let header = ClientPacketHeader {
+        moves: moves.clone(),
+        ..Default::default()
+    };
+    let mut stream = BitStream::new();
+    header.write(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let read = ClientPacketHeader::read(&mut stream).unwrap();
+    assert_eq!(read.moves, moves[..MAX_MOVE_COUNT]);
+}
+
+#[test]
+fn rejects_too_many_moves() {
+    let mut stream = BitStream::new();
+    stream.write_u32(0);
+    stream.write_int(MAX_MOVE_COUNT as u32 + 1, 5);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert!(matches!(
+        ClientPacketHeader::read(&mut stream),
+        Err(DnetError::TooManyMoves(31))
+    ));
+}
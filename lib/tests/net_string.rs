@@ -0,0 +1,102 @@
+use dnet::{detag, pack_string, tag_id, unpack_string, BitStream, NetStringTable};
+
+fn round_trip(string: &str) -> (String, usize) {
+    let mut stream = BitStream::new();
+    pack_string(&mut stream, string);
+    let bits = stream.get_bit_pos();
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    (unpack_string(&mut stream).unwrap(), bits)
+}
+
+#[test]
+fn packs_strings() {
+    // Two bits of type, then whatever that type needs
+    assert_eq!(round_trip(""), (String::new(), 2));
+    assert_eq!(round_trip("\x0131"), ("\x0131".to_string(), 2 + 5));
+    assert_eq!(round_trip("5"), ("5".to_string(), 2 + 2 + 7));
+    assert_eq!(round_trip("-127"), ("-127".to_string(), 2 + 2 + 7));
+    assert_eq!(round_trip("128"), ("128".to_string(), 2 + 3 + 16));
+    assert_eq!(round_trip("-65536"), ("-65536".to_string(), 2 + 3 + 32));
+    assert_eq!(
+        round_trip("2147483647"),
+        ("2147483647".to_string(), 2 + 3 + 32)
+    );
+
+    // Things that aren't quite numbers go as strings
+    for string in ["007", "-0", "1.5", "12abc", "99999999999", "hello"] {
+        assert_eq!(round_trip(string).0, string);
+    }
+}
+
+#[test]
+fn tag_ids() {
+    assert_eq!(tag_id("\x0112"), Some(12));
+    assert_eq!(tag_id("\x0112 Bob"), Some(12));
+    assert_eq!(tag_id("12"), None);
+    assert_eq!(tag_id("\x01"), None);
+
+    assert_eq!(detag("\x0112 Bob joined"), "Bob joined");
+    assert_eq!(detag("\x0112"), "");
+    assert_eq!(detag("plain text"), "plain text");
+}
+
+#[test]
+fn checks_strings() {
+    let mut table = NetStringTable::new();
+    assert_eq!(table.check_string("zero"), (0, true));
+    assert_eq!(table.check_string("one"), (1, true));
+    assert_eq!(table.check_string("zero"), (0, false));
+    assert_eq!(table.local_id("one"), Some(1));
+
+    assert!(!table.is_received(0));
+    table.confirm_string_received("zero", 0);
+    assert!(table.is_received(0));
+    // A confirm for a string that has since lost its slot doesn't count
+    table.confirm_string_received("other", 1);
+    assert!(!table.is_received(1));
+}
+
+#[test]
+fn reuses_least_recently_used_entry() {
+    let mut table = NetStringTable::new();
+    for i in 0..32 {
+        assert_eq!(table.check_string(&i.to_string()), (i, true));
+    }
+    // 0 was used most recently, so 1 is the one to go
+    table.check_string("0");
+    assert_eq!(table.check_string("new"), (1, true));
+    assert_eq!(table.local_id("1"), None);
+    assert_eq!(table.check_string("1"), (2, true));
+}
+
+#[test]
+fn maps_remote_strings() {
+    let mut table = NetStringTable::new();
+    table.map_string(4, "Bob".to_string());
+    // Past the end of the table
+    table.map_string(32, "Nope".to_string());
+
+    assert_eq!(table.lookup_string(4), Some("Bob"));
+    assert_eq!(table.lookup_string(5), None);
+    assert_eq!(table.lookup_string(32), None);
+
+    assert_eq!(table.resolve("\x014"), "Bob");
+    assert_eq!(table.resolve("\x015"), "\x015");
+    assert_eq!(table.resolve("\x014 Alice"), "Alice");
+    assert_eq!(table.resolve("plain"), "plain");
+}
+
+#[test]
+fn expands_strings() {
+    let mut table = NetStringTable::new();
+    table.map_string(3, "%1 has %2 gems, 100%%, %9%x".to_string());
+    let args = ["\x014 Bob".to_string(), "7".to_string()];
+
+    assert_eq!(
+        table.expand("\x013", &args),
+        "\x013 Bob has 7 gems, 100%, x"
+    );
+    // Tags we don't know about still keep the tag
+    assert_eq!(table.expand("\x0120", &args), "\x0120 ");
+    assert_eq!(table.expand("plain %1", &args), "plain %1");
+}
@@ -0,0 +1,22 @@
+use dnet::{Packet, PacketSource};
+
+#[test]
+fn ggc_packet_round_trip() {
+    let bytes = vec![24, 0xDE, 0xAD, 0xBE, 0xEF, 0x00, 0x01];
+    let packet = Packet::parse_bytes(&bytes, PacketSource::GameToGame).unwrap();
+    match &packet {
+        Packet::GGCPacket { data } => assert_eq!(data, &bytes[1..]),
+        other => panic!("Expected GGCPacket, got {:?}", other),
+    }
+    assert_eq!(packet.into_bytes(), bytes);
+}
+
+#[test]
+fn empty_ggc_packet() {
+    let packet = Packet::parse_bytes(&[24], PacketSource::GameToMaster).unwrap();
+    match &packet {
+        Packet::GGCPacket { data } => assert!(data.is_empty()),
+        other => panic!("Expected GGCPacket, got {:?}", other),
+    }
+    assert_eq!(packet.into_bytes(), vec![24]);
+}
@@ -0,0 +1,140 @@
+use dnet::{
+    BitStream, ClientPacketHeader, ControlObjectUpdate, ControlScheme, Move, Point3F,
+    ServerPacketHeader,
+};
+
+fn server_round_trip(header: &ServerPacketHeader) -> (ServerPacketHeader, Vec<u32>) {
+    let mut stream = BitStream::new();
+    header.write(&mut stream, |ghost_index, stream| {
+        stream.write_u16(ghost_index as u16 + 1000);
+    });
+    let bits = stream.get_bit_pos();
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let mut object_data = vec![];
+    let read = ServerPacketHeader::read(&mut stream, |ghost_index, stream| {
+        assert_eq!(stream.read_u16()? as u32, ghost_index + 1000);
+        object_data.push(ghost_index);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(stream.get_bit_pos(), bits);
+    (read, object_data)
+}
+
+#[test]
+fn server_header_bytes() {
+    // Worked out by hand from the layout GameConnection::readPacket expects
+    let header = ServerPacketHeader {
+        last_move_ack: 0x12345678,
+        damage_flash: Some(0.0),
+        control_object: Some(ControlObjectUpdate::Update(5)),
+        first_person: Some(true),
+        ..Default::default()
+    };
+    let mut stream = BitStream::new();
+    header.write(&mut stream, |_, _| {});
+    assert_eq!(stream.get_bit_pos(), 54);
+    let bytes = stream.into_bytes();
+    assert_eq!(bytes, vec![0x78, 0x56, 0x34, 0x12, 0xB9, 0x00, 0x0C]);
+
+    let mut stream = BitStream::from_buffer(bytes);
+    let read = ServerPacketHeader::read(&mut stream, |_, _| Ok(())).unwrap();
+    assert_eq!(read, header);
+}
+
+#[test]
+fn empty_server_header() {
+    let header = ServerPacketHeader::default();
+    let mut stream = BitStream::new();
+    header.write(&mut stream, |_, _| {});
+    // Move ack, then a flag for each of the other fields
+    assert_eq!(stream.get_bit_pos(), 32 + 7);
+    assert_eq!(server_round_trip(&header), (header, vec![]));
+}
+
+#[test]
+fn full_server_header() {
+    let header = ServerPacketHeader {
+        last_move_ack: 77,
+        damage_flash: Some(1.0),
+        white_out: Some(1.5),
+        control_object: Some(ControlObjectUpdate::Update(12)),
+        camera_object: Some(4095),
+        first_person: Some(false),
+        camera_fov: Some(90),
+        control_scheme: Some(ControlScheme {
+            absolute_rotation: true,
+            add_yaw_to_absolute_rotation: false,
+            add_pitch_to_absolute_rotation: true,
+        }),
+    };
+    // The control object's data comes before the camera's
+    assert_eq!(server_round_trip(&header), (header, vec![12, 4095]));
+}
+
+#[test]
+fn damage_flash_and_white_out() {
+    for (damage_flash, white_out) in [
+        (Some(0.0), None),
+        (None, Some(0.0)),
+        (Some(0.5), Some(0.75)),
+    ] {
+        let header = ServerPacketHeader {
+            damage_flash,
+            white_out,
+            ..Default::default()
+        };
+        let (read, _) = server_round_trip(&header);
+        assert_eq!(read.damage_flash.is_some(), damage_flash.is_some());
+        assert_eq!(read.white_out.is_some(), white_out.is_some());
+        for (read, sent) in [
+            (read.damage_flash, damage_flash),
+            (read.white_out, white_out),
+        ] {
+            assert!((read.unwrap_or(0.0) - sent.unwrap_or(0.0)).abs() < 0.02);
+        }
+    }
+}
+
+#[test]
+fn sets_compression_point() {
+    let point = Point3F::new(100.0, -20.0, 3.5);
+    let header = ServerPacketHeader {
+        control_object: Some(ControlObjectUpdate::CompressionPoint(point)),
+        ..Default::default()
+    };
+    let mut stream = BitStream::new();
+    header.write(&mut stream, |_, _| {});
+    assert_eq!(stream.compression_point(), point);
+    // Just the point, no ghost index
+    assert_eq!(stream.get_bit_pos(), 32 + 7 + 1 + 96);
+
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let read = ServerPacketHeader::read(&mut stream, |_, _| panic!("No object data")).unwrap();
+    assert_eq!(read, header);
+    assert_eq!(stream.compression_point(), point);
+}
+
+#[test]
+fn client_header_round_trip() {
+    let mut new_move = Move {
+        x: 1.0,
+        yaw: 0.5,
+        ..Default::default()
+    };
+    new_move.clamp();
+    let header = ClientPacketHeader {
+        first_move_index: 40,
+        moves: vec![new_move, Move::default()],
+        camera_pos: true,
+        control_force_mismatch: false,
+        control_scheme: Some(ControlScheme::default()),
+        first_person: Some(true),
+        camera_fov: Some(179),
+    };
+    let mut stream = BitStream::new();
+    header.write(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(ClientPacketHeader::read(&mut stream).unwrap(), header);
+}
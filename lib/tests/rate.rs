@@ -0,0 +1,137 @@
+use dnet::{BitStream, NetRate, RateControl};
+use std::time::{Duration, Instant};
+
+fn send(from: &mut RateControl, to: &mut RateControl) {
+    let mut stream = BitStream::new();
+    from.write_packet(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    to.read_packet(&mut stream).unwrap();
+}
+
+fn rate(update_delay: u32, packet_size: u32) -> (u32, u32) {
+    (update_delay, packet_size)
+}
+
+fn values(rate: NetRate) -> (u32, u32) {
+    (rate.update_delay, rate.packet_size)
+}
+
+#[test]
+fn negotiates_down_to_the_slower_side() {
+    let mut client = RateControl::new();
+    let mut server = RateControl::new();
+    client.set_max_rate(32, 450);
+    server.set_max_rate(10, 300);
+    assert_eq!(values(client.max_rate()), rate(31, 450));
+    assert_eq!(values(server.max_rate()), rate(100, 300));
+
+    // The server can't send faster than it's willing to receive
+    send(&mut client, &mut server);
+    assert_eq!(values(server.cur_rate()), rate(100, 300));
+    assert!(server.cur_rate().changed);
+
+    // And the client takes on the server's rate
+    send(&mut server, &mut client);
+    assert_eq!(values(client.cur_rate()), rate(100, 300));
+    assert!(!client.cur_rate().changed);
+    assert!(!server.cur_rate().changed);
+    assert!(!server.max_rate().changed);
+
+    // Nothing more to say
+    let mut stream = BitStream::new();
+    client.write_packet(&mut stream);
+    server.write_packet(&mut stream);
+    assert_eq!(stream.get_bit_pos(), 4);
+}
+
+#[test]
+fn clamps_only_our_own_rate() {
+    let mut client = RateControl::new();
+    client.set_max_rate(16, 200);
+
+    // The rate the other side sends at is taken as is, like Torque does
+    let mut stream = BitStream::new();
+    stream.write_flag(true);
+    stream.write_int(10, 12);
+    stream.write_int(1000, 12);
+    stream.write_flag(false);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    client.read_packet(&mut stream).unwrap();
+    assert_eq!(values(client.cur_rate()), rate(10, 1000));
+    assert!(!client.cur_rate().changed);
+
+    // But when it tells us its limits, we send no faster than our own
+    let mut stream = BitStream::new();
+    stream.write_flag(false);
+    stream.write_flag(true);
+    stream.write_int(10, 12);
+    stream.write_int(1000, 12);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    client.read_packet(&mut stream).unwrap();
+    assert_eq!(values(client.cur_rate()), rate(62, 200));
+    assert!(client.cur_rate().changed);
+}
+
+#[test]
+fn resends_rates_from_dropped_packets() {
+    let mut client = RateControl::new();
+    let mut server = RateControl::new();
+    client.set_max_rate(20, 400);
+
+    let mut stream = BitStream::new();
+    client.write_packet(&mut stream);
+    assert!(!client.max_rate().changed);
+    // A packet without rate info in it, which does make it
+    client.push_empty_notify();
+
+    client.handle_notify(false);
+    assert!(client.max_rate().changed);
+    client.handle_notify(true);
+    assert!(client.max_rate().changed);
+
+    send(&mut client, &mut server);
+    assert_eq!(values(server.cur_rate()), rate(102, 200));
+    client.handle_notify(true);
+    assert!(!client.max_rate().changed);
+}
+
+#[test]
+fn spaces_out_sends() {
+    let mut rate = RateControl::new();
+    let start = Instant::now();
+    let delay = rate.update_delay();
+    assert_eq!(delay, Duration::from_millis(102));
+    assert_eq!(rate.next_send_time(), None);
+    assert!(rate.check_send_time(start));
+    rate.mark_sent(start);
+
+    assert_eq!(rate.next_send_time(), Some(start + delay));
+    assert!(!rate.check_send_time(start + delay / 2));
+    assert!(rate.check_send_time(start + delay));
+    rate.mark_sent(start + delay);
+    assert_eq!(rate.next_send_time(), Some(start + delay * 2));
+}
+
+#[test]
+fn credits_late_sends() {
+    let mut rate = RateControl::new();
+    let start = Instant::now();
+    let delay = rate.update_delay();
+    rate.mark_sent(start);
+
+    // 40ms late, so the next one can go 40ms early
+    let late = start + delay + Duration::from_millis(40);
+    assert!(rate.check_send_time(late));
+    rate.mark_sent(late);
+    let next = late + delay - Duration::from_millis(40);
+    assert_eq!(rate.next_send_time(), Some(next));
+    assert!(!rate.check_send_time(next - Duration::from_millis(1)));
+    assert!(rate.check_send_time(next));
+
+    // A long stall only earns a second of credit, which is more than the delay
+    rate.mark_sent(next);
+    let stalled = next + Duration::from_secs(10);
+    assert!(rate.check_send_time(stalled));
+    rate.mark_sent(stalled);
+    assert_eq!(rate.next_send_time(), Some(stalled));
+}
@@ -0,0 +1,93 @@
+use dnet::{BitStream, DnetError, GameConnection, NetEvent, RemoteCommandEvent};
+
+async fn connection() -> GameConnection {
+    GameConnection::connect("127.0.0.1:0", "127.0.0.1:9", 1)
+        .await
+        .unwrap()
+}
+
+fn round_trip(connection: &GameConnection, event: &RemoteCommandEvent) -> RemoteCommandEvent {
+    let mut stream = BitStream::new();
+    event.pack(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let mut unpacked = RemoteCommandEvent::default();
+    unpacked.unpack(connection, &mut stream).unwrap();
+    unpacked
+}
+
+#[tokio::test]
+async fn round_trips_args() {
+    let connection = connection().await;
+    let event = RemoteCommandEvent::new("\x017", &["", "text", "-12", "40000", "\x0130"]).unwrap();
+    assert_eq!(round_trip(&connection, &event).argv, event.argv);
+}
+
+#[test]
+fn sends_argc_in_5_bits() {
+    let event = RemoteCommandEvent::new("\x011", &["a", "b"]).unwrap();
+    let mut stream = BitStream::new();
+    event.pack(&mut stream);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    assert_eq!(stream.read_int(5).unwrap(), 3);
+}
+
+#[tokio::test]
+async fn allows_up_to_20_args() {
+    let connection = connection().await;
+    let args = (0..19).map(|i| i.to_string()).collect::<Vec<_>>();
+    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>();
+
+    // The name counts as one
+    let event = RemoteCommandEvent::new("\x011", &args).unwrap();
+    assert_eq!(event.argv.len(), 20);
+    assert_eq!(round_trip(&connection, &event).argv, event.argv);
+
+    let too_many = [args.as_slice(), &["19"]].concat();
+    assert!(matches!(
+        RemoteCommandEvent::new("\x011", &too_many),
+        Err(DnetError::TooManyCommandArgs(20))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_too_many_args_from_the_wire() {
+    let connection = connection().await;
+    let mut stream = BitStream::new();
+    stream.write_int(21, 5);
+    let mut stream = BitStream::from_buffer(stream.into_bytes());
+    let mut event = RemoteCommandEvent::default();
+    assert!(matches!(
+        event.unpack(&connection, &mut stream),
+        Err(DnetError::TooManyCommandArgs(21))
+    ));
+}
+
+#[tokio::test]
+async fn detags_args() {
+    let mut connection = connection().await;
+    let mut commands = connection.commands();
+    let strings = connection.string_table_mut();
+    strings.map_string(2, "ChatMessage".to_string());
+    strings.map_string(3, "%1 joined with %2".to_string());
+    strings.map_string(4, "Bob".to_string());
+
+    let mut event = RemoteCommandEvent::new("\x012", &["\x013", "\x014", "5"]).unwrap();
+    event.process(&mut connection).unwrap();
+
+    let command = commands.try_recv().unwrap();
+    assert_eq!(command.name, "ChatMessage");
+    assert_eq!(
+        command.args,
+        vec!["\x013 Bob joined with 5", "\x014 Bob", "5"]
+    );
+}
+
+#[tokio::test]
+async fn needs_a_name() {
+    let mut connection = connection().await;
+    let mut event = RemoteCommandEvent::default();
+    assert!(matches!(
+        event.process(&mut connection),
+        Err(DnetError::MissingCommandName)
+    ));
+}
@@ -0,0 +1,92 @@
+use dnet::{BitStream, ConnectionStats, DNet, NetPacketType, LOSS_WINDOW_SIZE};
+use std::time::Duration;
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn smooths_round_trip_times() {
+    let mut stats = ConnectionStats::new();
+    assert_eq!(stats.smoothed_rtt(), None);
+    assert_eq!(stats.jitter(), ms(0));
+
+    // The first sample is taken as is, with half of it as the deviation
+    stats.record_rtt(ms(100));
+    assert_eq!(stats.smoothed_rtt(), Some(ms(100)));
+    assert_eq!(stats.jitter(), ms(50));
+
+    // SRTT = 7/8 SRTT + 1/8 sample, RTTVAR = 3/4 RTTVAR + 1/4 |SRTT - sample|
+    stats.record_rtt(ms(200));
+    assert_eq!(stats.last_rtt(), Some(ms(200)));
+    assert_eq!(stats.smoothed_rtt(), Some(Duration::from_micros(112_500)));
+    assert_eq!(stats.jitter(), Duration::from_micros(62_500));
+
+    // A steady connection settles down
+    for _ in 0..100 {
+        stats.record_rtt(ms(80));
+    }
+    let smoothed_rtt = stats.smoothed_rtt().unwrap();
+    assert!(smoothed_rtt.abs_diff(ms(80)) < ms(1));
+    assert!(stats.jitter() < ms(1));
+}
+
+#[test]
+fn measures_loss_over_a_window() {
+    let mut stats = ConnectionStats::new();
+    assert_eq!(stats.loss_percentage(), 0.0);
+
+    for i in 0..LOSS_WINDOW_SIZE {
+        stats.record_notify(i % 4 != 0);
+    }
+    assert_eq!(stats.loss_percentage(), 25.0);
+    assert_eq!(stats.packets_lost(), LOSS_WINDOW_SIZE as u64 / 4);
+
+    // Old losses fall out of the window but stay in the totals
+    for _ in 0..LOSS_WINDOW_SIZE / 2 {
+        stats.record_notify(true);
+    }
+    assert_eq!(stats.loss_percentage(), 12.5);
+    for _ in 0..LOSS_WINDOW_SIZE / 2 {
+        stats.record_notify(true);
+    }
+    assert_eq!(stats.loss_percentage(), 0.0);
+    assert_eq!(stats.packets_lost(), LOSS_WINDOW_SIZE as u64 / 4);
+    assert_eq!(stats.packets_acked(), LOSS_WINDOW_SIZE as u64 * 7 / 4);
+}
+
+#[test]
+fn counts_packets_once_sent() {
+    let mut client = DNet::new(1);
+    let mut server = DNet::new(1);
+
+    // Building a header isn't sending it
+    let mut packet = BitStream::new();
+    client
+        .build_send_packet_header(&mut packet, NetPacketType::DataPacket)
+        .unwrap();
+    assert_eq!(client.stats().packets_out(), 0);
+
+    let bytes = packet.into_bytes();
+    client.record_sent(bytes.len());
+    assert_eq!(client.stats().packets_out(), 1);
+    assert_eq!(client.stats().bytes_out(), bytes.len() as u64);
+
+    let len = bytes.len();
+    server
+        .process_raw_packet(BitStream::from_buffer(bytes))
+        .unwrap();
+    assert_eq!(server.stats().packets_in(), 1);
+    assert_eq!(server.stats().bytes_in(), len as u64);
+
+    // The ack is notified and timed
+    let mut ack = BitStream::new();
+    server
+        .build_send_packet_header(&mut ack, NetPacketType::AckPacket)
+        .unwrap();
+    client
+        .process_raw_packet(BitStream::from_buffer(ack.into_bytes()))
+        .unwrap();
+    assert_eq!(client.stats().packets_acked(), 1);
+    assert!(client.stats().smoothed_rtt().is_some());
+}
@@ -1,13 +1,23 @@
 use anyhow::{anyhow, Result};
 use dnet::packet::Packet;
+use dnet::BitStream;
 use dnet::GameConnection;
 use dnet::NetClassGroups::NetClassGroupGame;
+use rand::Rng;
 use tokio::net::UdpSocket;
 use tokio::select;
 use tokio::time::{sleep, Duration};
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // RUST_LOG=dnet=debug,dnet::hexdump=trace for everything
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
     loop {
         let mut connection = GameConnection::connect("0.0.0.0:30000", "127.0.0.1:28000", 1).await?;
         connection
@@ -23,7 +33,7 @@ async fn main() -> Result<()> {
                     println!("Packet {:?}", packet);
                     match packet {
                         Ok(Some(Packet::Raw(raw_packet))) => {
-                            connection.process_raw_packet(raw_packet).await?;
+                            connection.process_raw_packet(BitStream::from_buffer(raw_packet)).await?;
                         }
                         Ok(Some(Packet::ConnectChallengeResponse { sequence, address_digest })) => {
                             connection.send_packet(Packet::ConnectRequest {
@@ -31,7 +41,7 @@ async fn main() -> Result<()> {
                                 address_digest,
                                 class_name: "GameConnection".to_string(),
                                 net_class_group: NetClassGroupGame,
-                                class_crc: 0xffffffff,
+                                class_crc: connection.class_registry().class_crc(NetClassGroupGame),
                                 game_string: "Test".to_string(),
                                 current_protocol_version: 12,
                                 min_required_protocol_version: 9,
@@ -40,7 +50,12 @@ async fn main() -> Result<()> {
                             }).await?;
                         },
                         Ok(Some(Packet::ConnectAccept { .. })) => {
-                            connection.send_raw_packet().await?;
+                            // LOL crash
+                            connection.send_raw_packet_with(|packet| {
+                                for _ in 0..2000 {
+                                    packet.write_flag(rand::thread_rng().gen_bool(0.5));
+                                }
+                            }).await?;
                         }
                         Ok(Some(Packet::Disconnect { .. })) => {
                             println!("Disconnected");
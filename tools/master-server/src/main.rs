@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use dnet::MasterServerHost;
+use dnet::DEFAULT_SERVER_TIMEOUT;
+use tokio::select;
+use tokio::time::Duration;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+// Usage: master-server [bind address] [server timeout in seconds]
+#[tokio::main]
+async fn main() -> Result<()> {
+    // RUST_LOG=dnet=debug,dnet::hexdump=trace for everything
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let bind_address = args.next().unwrap_or_else(|| "0.0.0.0:28002".to_string());
+    let server_timeout = match args.next() {
+        Some(seconds) => Duration::from_secs(
+            seconds
+                .parse()
+                .map_err(|_| anyhow!("Bad server timeout: {}", seconds))?,
+        ),
+        None => DEFAULT_SERVER_TIMEOUT,
+    };
+
+    let mut host = MasterServerHost::bind_with_timeout(bind_address, server_timeout).await?;
+    info!(address = %host.local_addr(), "Listening");
+
+    select! {
+        result = host.join() => {
+            result?;
+        }
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Shutting down");
+        }
+    }
+
+    Ok(())
+}